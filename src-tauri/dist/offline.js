@@ -0,0 +1,202 @@
+(function() {
+  var core = window.__TAURI__.core;
+  var events = window.__TAURI__.event;
+
+  var state = {
+    artist: null,
+    album: null,
+    query: "",
+    tracks: [],
+    queue: [],
+    index: -1
+  };
+
+  var audio = document.getElementById("audio");
+  var artistsEl = document.getElementById("artists");
+  var albumsEl = document.getElementById("albums");
+  var tracksEl = document.getElementById("tracks");
+  var errorEl = document.getElementById("error");
+
+  function mediaUrl(path) {
+    return path ? core.convertFileSrc(path, "library") : "";
+  }
+
+  function showError(error) {
+    errorEl.textContent = String(error);
+    errorEl.hidden = false;
+    setTimeout(function() { errorEl.hidden = true; }, 4000);
+  }
+
+  function el(tag, className, text) {
+    var node = document.createElement(tag);
+    if (className) node.className = className;
+    if (text !== undefined && text !== null) node.textContent = text;
+    return node;
+  }
+
+  function formatDuration(ms) {
+    var seconds = Math.round(ms / 1000);
+    var minutes = Math.floor(seconds / 60);
+    seconds = seconds % 60;
+    return minutes + ":" + (seconds < 10 ? "0" : "") + seconds;
+  }
+
+  function fileName(path) {
+    return path.split(/[\\/]/).pop().replace(/\.[^.]+$/, "");
+  }
+
+  function row(options) {
+    var node = el("div", "row" + (options.selected ? " selected" : ""));
+    if (options.cover !== undefined) {
+      var img = el("img");
+      img.src = mediaUrl(options.cover);
+      img.alt = "";
+      node.appendChild(img);
+    }
+    var meta = el("div", "meta");
+    meta.appendChild(el("div", "", options.title));
+    if (options.subtitle) meta.appendChild(el("div", "muted", options.subtitle));
+    node.appendChild(meta);
+    if (options.aside) node.appendChild(el("span", "muted", options.aside));
+    node.addEventListener("click", options.onClick);
+    return node;
+  }
+
+  function fill(container, nodes, emptyText) {
+    container.innerHTML = "";
+    if (!nodes.length) {
+      container.appendChild(el("div", "empty", emptyText));
+      return;
+    }
+    nodes.forEach(function(node) { container.appendChild(node); });
+  }
+
+  // -------------------------------------------------------------------------
+  // Browsing
+  // -------------------------------------------------------------------------
+
+  function loadArtists() {
+    return core.invoke("get_library_artists").then(function(artists) {
+      var nodes = [row({
+        title: "All Artists",
+        selected: state.artist === null,
+        onClick: function() { selectArtist(null); }
+      })];
+      artists.forEach(function(artist) {
+        nodes.push(row({
+          title: artist.name,
+          subtitle: artist.albums + " albums, " + artist.tracks + " tracks",
+          selected: state.artist === artist.name,
+          onClick: function() { selectArtist(artist.name); }
+        }));
+      });
+      fill(artistsEl, nodes, "No artists");
+    });
+  }
+
+  function loadAlbums() {
+    return core.invoke("get_library_albums", { artist: state.artist }).then(function(albums) {
+      fill(albumsEl, albums.map(function(album) {
+        return row({
+          title: album.title,
+          subtitle: [album.artist, album.year].filter(Boolean).join(" · "),
+          cover: album.cover,
+          selected: state.album === album.title,
+          onClick: function() { selectAlbum(album.title); }
+        });
+      }), "No albums");
+    });
+  }
+
+  function loadTracks() {
+    var request = state.query
+      ? core.invoke("search_library", { query: state.query })
+      : core.invoke("get_library_tracks", { artist: state.artist, album: state.album });
+    return request.then(function(tracks) {
+      state.tracks = tracks;
+      renderTracks();
+    });
+  }
+
+  function renderTracks() {
+    var playing = state.queue[state.index];
+    fill(tracksEl, state.tracks.map(function(track, index) {
+      var node = row({
+        title: track.title || fileName(track.path),
+        subtitle: [track.artist, track.album].filter(Boolean).join(" · "),
+        aside: track.format.toUpperCase() + "  " + formatDuration(track.durationMs),
+        onClick: function() { play(state.tracks, index); }
+      });
+      if (playing && playing.path === track.path) node.classList.add("playing");
+      return node;
+    }), state.query ? "No matches" : "Your downloaded tracks will appear here");
+  }
+
+  function selectArtist(artist) {
+    state.artist = artist;
+    state.album = null;
+    refresh();
+  }
+
+  function selectAlbum(album) {
+    state.album = album;
+    loadAlbums().then(loadTracks).catch(showError);
+  }
+
+  function refresh() {
+    return Promise.all([loadArtists(), loadAlbums(), loadTracks()]).catch(showError);
+  }
+
+  // -------------------------------------------------------------------------
+  // Playback
+  // -------------------------------------------------------------------------
+
+  function play(tracks, index) {
+    state.queue = tracks.slice();
+    state.index = index;
+    var track = state.queue[index];
+    if (!track) return;
+    audio.src = mediaUrl(track.path);
+    audio.play().catch(showError);
+    document.getElementById("now-title").textContent = track.title || fileName(track.path);
+    document.getElementById("now-artist").textContent = track.artist || "";
+    document.getElementById("now-cover").src = mediaUrl(track.cover);
+    renderTracks();
+  }
+
+  function step(offset) {
+    var index = state.index + offset;
+    if (index >= 0 && index < state.queue.length) play(state.queue, index);
+  }
+
+  audio.addEventListener("ended", function() { step(1); });
+  document.getElementById("prev").addEventListener("click", function() { step(-1); });
+  document.getElementById("next").addEventListener("click", function() { step(1); });
+
+  // -------------------------------------------------------------------------
+  // Toolbar
+  // -------------------------------------------------------------------------
+
+  var searchTimer = null;
+  document.getElementById("search").addEventListener("input", function(e) {
+    clearTimeout(searchTimer);
+    searchTimer = setTimeout(function() {
+      state.query = e.target.value.trim();
+      loadTracks().catch(showError);
+    }, 200);
+  });
+
+  document.getElementById("rescan").addEventListener("click", function() {
+    core.invoke("rescan_library").then(refresh).catch(showError);
+  });
+
+  document.getElementById("online").addEventListener("click", function() {
+    core.invoke("get_source_url").then(function(url) {
+      return core.invoke("set_source_url", { url: url });
+    }).catch(showError);
+  });
+
+  events.listen("library-changed", function() { refresh(); });
+
+  refresh();
+})();
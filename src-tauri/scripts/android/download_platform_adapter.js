@@ -38,56 +38,25 @@
         } catch (_) {}
     }
 
-    async function writeBlobToAndroid(invoke, uri, blob) {
-        const chunkSize = 1024 * 1024;
-        let offset = 0;
-        let firstChunk = true;
-
-        while (offset < blob.size) {
-            const slice = blob.slice(offset, offset + chunkSize);
-            const arrayBuffer = await slice.arrayBuffer();
-            const data = new Uint8Array(arrayBuffer);
-
-            await invoke('android_download_write', {
-                args: {
-                    uri: uri,
-                    data: data,
-                    append: !firstChunk,
-                },
-            });
-
-            firstChunk = false;
-            offset += chunkSize;
-
-            if (offset < blob.size) {
-                await new Promise((resolve) => setTimeout(resolve, 0));
-            }
-        }
-    }
-
     window.__monochromeDownloadPlatformAdapter = {
         platformName: 'android',
         saveBlob: async function (ctx) {
             const invoke = ctx.invoke;
             const filename = ctx.filename;
             const blob = ctx.blob;
-            const mimeType = blob.type || 'application/octet-stream';
+            const shared = ctx.shared || window.__monochromeDownloadShared;
             const relativePath = window.__monochromeDownloadRelativePath || 'Download';
 
-            const uri = await invoke('android_download_begin', {
-                args: {
-                    filename: filename,
-                    mimeType: mimeType,
-                    relativePath: relativePath,
-                },
-            });
+            if (!shared || typeof shared.saveBlobToSink !== 'function') {
+                throw new Error('Android download helpers are unavailable');
+            }
 
-            await writeBlobToAndroid(invoke, uri, blob);
-            await invoke('android_download_finish', { args: { uri: uri } });
+            const result = await shared.saveBlobToSink(invoke, filename, blob, relativePath);
 
             return {
                 locationLabel: 'Downloads',
-                savedName: filename,
+                savedName: result.savedName,
+                skipped: result.skipped,
             };
         },
     };
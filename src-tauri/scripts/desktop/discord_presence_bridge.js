@@ -55,6 +55,10 @@
                 if (audio.paused) audio.play(); else audio.pause();
             }
         });
+        window.__TAURI__.event.listen('media-next', () => {
+            const next = document.getElementById('next-btn');
+            if (next) next.click();
+        });
     }
 
     function updateRPC(force = false) {
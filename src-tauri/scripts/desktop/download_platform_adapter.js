@@ -0,0 +1,28 @@
+(function () {
+    if (window.__monochromeDesktopDownloadAdapterInit) return;
+    window.__monochromeDesktopDownloadAdapterInit = true;
+
+    window.__monochromeDownloadPlatformAdapter = {
+        platformName: 'desktop',
+        // The webview saves regular URLs itself (see `on_download`); only
+        // blob downloads go through the sink, which names, records, verifies
+        // and tags them the same way. The blob URL picks up any metadata
+        // registered for it.
+        blobOnly: true,
+        saveBlob: async function (ctx) {
+            const shared = ctx.shared || window.__monochromeDownloadShared;
+
+            if (!shared || typeof shared.saveBlobToSink !== 'function') {
+                throw new Error('Desktop download helpers are unavailable');
+            }
+
+            const result = await shared.saveBlobToSink(ctx.invoke, ctx.filename, ctx.blob, null, ctx.url);
+
+            return {
+                locationLabel: 'Downloads',
+                savedName: result.savedName,
+                skipped: result.skipped,
+            };
+        },
+    };
+})();
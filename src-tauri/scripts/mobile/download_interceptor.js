@@ -128,9 +128,55 @@
         }
     }
 
+    // Streams a blob through the native download sink: `download_begin`
+    // applies the collision policy and opens a session, `download_write`
+    // appends each chunk as a raw binary payload and `download_finish`
+    // publishes the file.
+    async function saveBlobToSink(invoke, filename, blob, relativePath, url) {
+        const begin = await invoke('download_begin', {
+            args: {
+                filename: filename,
+                mimeType: blob.type || 'application/octet-stream',
+                relativePath: relativePath,
+                size: blob.size,
+                url: url || null,
+            },
+        });
+
+        if (begin.skipped) {
+            return { savedName: begin.savedName, skipped: true };
+        }
+
+        const chunkSize = 4 * 1024 * 1024;
+        let offset = 0;
+
+        try {
+            while (offset < blob.size) {
+                const slice = blob.slice(offset, offset + chunkSize);
+                const data = new Uint8Array(await slice.arrayBuffer());
+
+                const written = await invoke('download_write', data, {
+                    headers: { session: begin.session },
+                });
+
+                offset += data.length;
+                if (written !== offset) {
+                    throw new Error('Short write: ' + written + ' of ' + offset + ' bytes');
+                }
+            }
+        } catch (err) {
+            await invoke('download_cancel', { session: begin.session }).catch(() => {});
+            throw err;
+        }
+
+        const finish = await invoke('download_finish', { session: begin.session });
+        return { savedName: begin.savedName, skipped: !finish.kept, bytes: finish.bytes };
+    }
+
     window.__monochromeDownloadShared = {
         resolveAvailableName: resolveAvailableName,
         writeBlobToFs: writeBlobToFs,
+        saveBlobToSink: saveBlobToSink,
     };
 
     const queue = [];
@@ -214,10 +260,14 @@
             if (!anchor || !anchor.hasAttribute('download')) return;
             if (!anchor.href) return;
 
+            const adapter = getAdapter();
+            // Some platforms download regular URLs natively and only need
+            // help with blobs.
+            if (adapter && adapter.blobOnly && !anchor.href.startsWith('blob:')) return;
+
             event.preventDefault();
             event.stopImmediatePropagation();
 
-            const adapter = getAdapter();
             if (!adapter) {
                 showToast('Download failed: no platform adapter available', true);
                 return;
@@ -252,12 +302,18 @@
                     invoke: invoke,
                     filename: sanitized,
                     blob: blob,
+                    url: anchor.href,
                     shared: window.__monochromeDownloadShared,
                 });
 
                 const savedName = result && result.savedName ? result.savedName : sanitized;
                 const locationLabel = result && result.locationLabel ? result.locationLabel : 'Files';
 
+                if (result && result.skipped) {
+                    showToast('Already in ' + locationLabel + ': ' + savedName);
+                    return;
+                }
+
                 showToast('Saved to ' + locationLabel + ': ' + savedName);
                 console.info('[Monochrome] download saved', {
                     name: savedName,
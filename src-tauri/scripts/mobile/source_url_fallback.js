@@ -1,6 +1,7 @@
 (function() {
   var EXPECTED_URL = "__EXPECTED_URL__";
   var DEFAULT_URL = "__DEFAULT_URL__";
+  var OFFLINE_LIBRARY = "__OFFLINE_LIBRARY__" === "true";
   var TIMEOUT_MS = 6000;
   var APP_SELECTORS = ".sidebar, .player-container, #app, .app";
   var LOGIN_SELECTORS = ".login-container";
@@ -82,6 +83,9 @@ background:#4a9eff;color:#fff;font-size:14px;cursor:pointer">Load</button>\
 <button id="__src_url_reset" style="padding:10px 20px;border:none;border-radius:6px;\
 background:#333;color:#eee;font-size:14px;cursor:pointer">Reset to Default</button>\
 </div>\
+' + (OFFLINE_LIBRARY ? '<button id="__src_url_offline" style="margin-top:16px;padding:10px 20px;\
+border:1px solid #333;border-radius:6px;background:transparent;color:#ccc;font-size:14px;\
+cursor:pointer">Open Offline Library</button>' : '') + '\
 <p id="__src_url_err" style="color:#ff6b6b;margin:12px 0 0;font-size:13px;display:none"></p>\
 </div></body>';
 
@@ -113,6 +117,15 @@ background:#333;color:#eee;font-size:14px;cursor:pointer">Reset to Default</butt
       applyUrl(DEFAULT_URL);
     });
 
+    var offlineButton = document.getElementById("__src_url_offline");
+    if (offlineButton) {
+      offlineButton.addEventListener("click", function() {
+        window.__TAURI__.core.invoke("open_offline_library").catch(function(e) {
+          showError(String(e));
+        });
+      });
+    }
+
     input.addEventListener("keydown", function(e) {
       if (e.key === "Enter") applyUrl(input.value.trim());
     });
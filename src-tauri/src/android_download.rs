@@ -1,9 +1,17 @@
 #[cfg(target_os = "android")]
-use jni::objects::{JObject, JValue};
+use crate::collision::{self, Candidate, CollisionPolicy};
+#[cfg(target_os = "android")]
+use crate::download_sink::{self, DownloadBegin, DownloadBeginArgs, DownloadFinish, DownloadSink};
+#[cfg(target_os = "android")]
+use jni::objects::{GlobalRef, JObject, JValue};
 #[cfg(target_os = "android")]
 use jni::JNIEnv;
 #[cfg(target_os = "android")]
-use serde::Deserialize;
+use std::collections::HashMap;
+#[cfg(target_os = "android")]
+use std::sync::Mutex;
+#[cfg(target_os = "android")]
+use std::time::Instant;
 #[cfg(target_os = "android")]
 use std::{sync::mpsc, time::Duration};
 #[cfg(target_os = "android")]
@@ -33,326 +41,657 @@ where
 }
 
 #[cfg(target_os = "android")]
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct AndroidDownloadBeginArgs {
-    filename: String,
-    mime_type: Option<String>,
-    relative_path: Option<String>,
+fn content_resolver<'local>(
+    env: &mut JNIEnv<'local>,
+    activity: &JObject,
+) -> Result<JObject<'local>, String> {
+    env.call_method(
+        activity,
+        "getContentResolver",
+        "()Landroid/content/ContentResolver;",
+        &[],
+    )
+    .and_then(|value| value.l())
+    .map_err(|e| format!("Failed to get ContentResolver: {e}"))
 }
 
 #[cfg(target_os = "android")]
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct AndroidDownloadWriteArgs {
-    uri: String,
-    data: Vec<u8>,
-    append: Option<bool>,
+fn parse_uri<'local>(env: &mut JNIEnv<'local>, uri: &str) -> Result<JObject<'local>, String> {
+    let uri_string = env
+        .new_string(uri)
+        .map_err(|e| format!("Failed to create URI string: {e}"))?;
+    env.call_static_method(
+        "android/net/Uri",
+        "parse",
+        "(Ljava/lang/String;)Landroid/net/Uri;",
+        &[JValue::Object(&uri_string)],
+    )
+    .and_then(|value| value.l())
+    .map_err(|e| format!("Failed to parse URI: {e}"))
 }
 
 #[cfg(target_os = "android")]
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct AndroidDownloadFinishArgs {
-    uri: String,
+fn java_string(env: &mut JNIEnv, value: JObject) -> Result<Option<String>, String> {
+    if value.is_null() {
+        return Ok(None);
+    }
+    let value = jni::objects::JString::from(value);
+    let string: String = env
+        .get_string(&value)
+        .map_err(|e| format!("Failed to convert string: {e}"))?
+        .into();
+    let _ = env.delete_local_ref(value);
+    Ok(Some(string))
 }
 
+/// A row of `MediaStore.Downloads` that a new download may collide with.
 #[cfg(target_os = "android")]
-#[tauri::command]
-pub fn android_download_begin(
-    app: AppHandle,
-    args: AndroidDownloadBeginArgs,
-) -> Result<String, String> {
-    with_android_env(&app, move |env, activity, _webview| {
-        let sdk_int = env
-            .get_static_field("android/os/Build$VERSION", "SDK_INT", "I")
-            .and_then(|value| value.i())
-            .unwrap_or(0);
-
-        if sdk_int < 29 {
-            return Err("Android 10+ required for public downloads".to_string());
-        }
+#[derive(Debug, Clone)]
+struct MediaEntry {
+    uri: String,
+    name: String,
+    mime: Option<String>,
+    size: u64,
+}
 
-        let resolver = env
-            .call_method(
-                activity,
-                "getContentResolver",
-                "()Landroid/content/ContentResolver;",
-                &[],
-            )
-            .and_then(|value| value.l())
-            .map_err(|e| format!("Failed to get ContentResolver: {e}"))?;
-
-        let values = env
-            .new_object("android/content/ContentValues", "()V", &[])
-            .map_err(|e| format!("Failed to create ContentValues: {e}"))?;
-
-        let display_name_key = env
-            .get_static_field(
-                "android/provider/MediaStore$MediaColumns",
-                "DISPLAY_NAME",
-                "Ljava/lang/String;",
-            )
-            .and_then(|value| value.l())
-            .map_err(|e| format!("Failed to get DISPLAY_NAME: {e}"))?;
-
-        let filename = env
-            .new_string(args.filename)
-            .map_err(|e| format!("Failed to create filename string: {e}"))?;
-        env.call_method(
-            &values,
-            "put",
-            "(Ljava/lang/String;Ljava/lang/String;)V",
-            &[JValue::Object(&display_name_key), JValue::Object(&filename)],
+/// Lists the Downloads entries visible to the app inside `relative_path`.
+#[cfg(target_os = "android")]
+fn list_entries(
+    env: &mut JNIEnv,
+    resolver: &JObject,
+    collection: &JObject,
+    relative_path: &str,
+) -> Result<Vec<MediaEntry>, String> {
+    let string_class = env
+        .find_class("java/lang/String")
+        .map_err(|e| format!("Failed to find String: {e}"))?;
+    let columns = ["_id", "_display_name", "mime_type", "_size"];
+    let projection = env
+        .new_object_array(columns.len() as i32, &string_class, JObject::null())
+        .map_err(|e| format!("Failed to create projection: {e}"))?;
+    for (index, column) in columns.iter().enumerate() {
+        let column = env
+            .new_string(column)
+            .map_err(|e| format!("Failed to create column string: {e}"))?;
+        env.set_object_array_element(&projection, index as i32, column)
+            .map_err(|e| format!("Failed to fill projection: {e}"))?;
+    }
+
+    let selection = env
+        .new_string("relative_path = ?")
+        .map_err(|e| format!("Failed to create selection: {e}"))?;
+    let selection_args = env
+        .new_object_array(1, &string_class, JObject::null())
+        .map_err(|e| format!("Failed to create selection args: {e}"))?;
+    let relative_value = env
+        .new_string(relative_path)
+        .map_err(|e| format!("Failed to create RELATIVE_PATH string: {e}"))?;
+    env.set_object_array_element(&selection_args, 0, relative_value)
+        .map_err(|e| format!("Failed to fill selection args: {e}"))?;
+
+    let null_obj = JObject::null();
+    let cursor = env
+        .call_method(
+            resolver,
+            "query",
+            "(Landroid/net/Uri;[Ljava/lang/String;Ljava/lang/String;[Ljava/lang/String;Ljava/lang/String;)Landroid/database/Cursor;",
+            &[
+                JValue::Object(collection),
+                JValue::Object(&projection),
+                JValue::Object(&selection),
+                JValue::Object(&selection_args),
+                JValue::Object(&null_obj),
+            ],
         )
-        .map_err(|e| format!("Failed to set display name: {e}"))?;
-
-        if let Some(mime) = args.mime_type {
-            if !mime.is_empty() {
-                let mime_key = env
-                    .get_static_field(
-                        "android/provider/MediaStore$MediaColumns",
-                        "MIME_TYPE",
-                        "Ljava/lang/String;",
-                    )
-                    .and_then(|value| value.l())
-                    .map_err(|e| format!("Failed to get MIME_TYPE: {e}"))?;
-                let mime_value = env
-                    .new_string(mime)
-                    .map_err(|e| format!("Failed to create MIME string: {e}"))?;
-                env.call_method(
-                    &values,
-                    "put",
-                    "(Ljava/lang/String;Ljava/lang/String;)V",
-                    &[JValue::Object(&mime_key), JValue::Object(&mime_value)],
+        .and_then(|value| value.l())
+        .map_err(|e| format!("Failed to query downloads: {e}"))?;
+    if cursor.is_null() {
+        return Ok(Vec::new());
+    }
+
+    let mut entries = Vec::new();
+    let result = (|| -> Result<(), String> {
+        while env
+            .call_method(&cursor, "moveToNext", "()Z", &[])
+            .and_then(|value| value.z())
+            .map_err(|e| format!("Failed to read downloads: {e}"))?
+        {
+            let id = env
+                .call_method(&cursor, "getLong", "(I)J", &[JValue::Int(0)])
+                .and_then(|value| value.j())
+                .map_err(|e| format!("Failed to read _id: {e}"))?;
+            let name = env
+                .call_method(
+                    &cursor,
+                    "getString",
+                    "(I)Ljava/lang/String;",
+                    &[JValue::Int(1)],
                 )
-                .map_err(|e| format!("Failed to set MIME type: {e}"))?;
-            }
+                .and_then(|value| value.l())
+                .map_err(|e| format!("Failed to read display name: {e}"))?;
+            let mime = env
+                .call_method(
+                    &cursor,
+                    "getString",
+                    "(I)Ljava/lang/String;",
+                    &[JValue::Int(2)],
+                )
+                .and_then(|value| value.l())
+                .map_err(|e| format!("Failed to read MIME type: {e}"))?;
+            let size = env
+                .call_method(&cursor, "getLong", "(I)J", &[JValue::Int(3)])
+                .and_then(|value| value.j())
+                .map_err(|e| format!("Failed to read size: {e}"))?;
+
+            let uri = env
+                .call_static_method(
+                    "android/content/ContentUris",
+                    "withAppendedId",
+                    "(Landroid/net/Uri;J)Landroid/net/Uri;",
+                    &[JValue::Object(collection), JValue::Long(id)],
+                )
+                .and_then(|value| value.l())
+                .map_err(|e| format!("Failed to build entry URI: {e}"))?;
+            let uri_string = env
+                .call_method(&uri, "toString", "()Ljava/lang/String;", &[])
+                .and_then(|value| value.l())
+                .map_err(|e| format!("Failed to read URI string: {e}"))?;
+            let _ = env.delete_local_ref(uri);
+
+            let (Some(uri), Some(name)) = (java_string(env, uri_string)?, java_string(env, name)?)
+            else {
+                continue;
+            };
+            entries.push(MediaEntry {
+                uri,
+                name,
+                mime: java_string(env, mime)?,
+                size: size.max(0) as u64,
+            });
         }
+        Ok(())
+    })();
+    let _ = env.call_method(&cursor, "close", "()V", &[]);
+    result.map(|_| entries)
+}
 
-        let relative_path_key = env
-            .get_static_field(
-                "android/provider/MediaStore$MediaColumns",
-                "RELATIVE_PATH",
-                "Ljava/lang/String;",
-            )
-            .and_then(|value| value.l())
-            .map_err(|e| format!("Failed to get RELATIVE_PATH: {e}"))?;
-
-        let mut relative_path = args.relative_path.unwrap_or_else(|| "Download".to_string());
-        if !relative_path.ends_with('/') {
-            relative_path.push('/');
-        }
-        let relative_value = env
-            .new_string(relative_path)
-            .map_err(|e| format!("Failed to create RELATIVE_PATH string: {e}"))?;
-        env.call_method(
-            &values,
-            "put",
-            "(Ljava/lang/String;Ljava/lang/String;)V",
-            &[
-                JValue::Object(&relative_path_key),
-                JValue::Object(&relative_value),
-            ],
+/// Size in bytes of the entry at `uri`, as recorded by MediaStore.
+#[cfg(target_os = "android")]
+fn entry_size(env: &mut JNIEnv, resolver: &JObject, uri: &JObject) -> Result<u64, String> {
+    let mode = env
+        .new_string("r")
+        .map_err(|e| format!("Failed to create mode string: {e}"))?;
+    let descriptor = env
+        .call_method(
+            resolver,
+            "openFileDescriptor",
+            "(Landroid/net/Uri;Ljava/lang/String;)Landroid/os/ParcelFileDescriptor;",
+            &[JValue::Object(uri), JValue::Object(&mode)],
         )
-        .map_err(|e| format!("Failed to set RELATIVE_PATH: {e}"))?;
+        .and_then(|value| value.l())
+        .map_err(|e| format!("Failed to open download: {e}"))?;
+    let size = env
+        .call_method(&descriptor, "getStatSize", "()J", &[])
+        .and_then(|value| value.j())
+        .map_err(|e| format!("Failed to read download size: {e}"));
+    let _ = env.call_method(&descriptor, "close", "()V", &[]);
+    size.map(|size| size.max(0) as u64)
+}
 
-        let is_pending_key = env
-            .get_static_field(
-                "android/provider/MediaStore$MediaColumns",
-                "IS_PENDING",
-                "Ljava/lang/String;",
-            )
-            .and_then(|value| value.l())
-            .map_err(|e| format!("Failed to get IS_PENDING: {e}"))?;
-        let pending_value = env
-            .new_object("java/lang/Integer", "(I)V", &[JValue::Int(1)])
-            .map_err(|e| format!("Failed to create pending Integer: {e}"))?;
-        env.call_method(
-            &values,
-            "put",
-            "(Ljava/lang/String;Ljava/lang/Integer;)V",
-            &[
-                JValue::Object(&is_pending_key),
-                JValue::Object(&pending_value),
-            ],
+/// Opens the entry at `uri` for writing from the start, as a global
+/// reference that can be kept between JNI calls.
+#[cfg(target_os = "android")]
+fn open_output_stream(
+    env: &mut JNIEnv,
+    resolver: &JObject,
+    uri: &str,
+) -> Result<GlobalRef, String> {
+    let uri = parse_uri(env, uri)?;
+    let mode = env
+        .new_string("w")
+        .map_err(|e| format!("Failed to create mode string: {e}"))?;
+    let output_stream = env
+        .call_method(
+            resolver,
+            "openOutputStream",
+            "(Landroid/net/Uri;Ljava/lang/String;)Ljava/io/OutputStream;",
+            &[JValue::Object(&uri), JValue::Object(&mode)],
         )
-        .map_err(|e| format!("Failed to set IS_PENDING: {e}"))?;
-
-        let volume = env
-            .new_string("external_primary")
-            .map_err(|e| format!("Failed to create volume string: {e}"))?;
-        let downloads_class = env
-            .find_class("android/provider/MediaStore$Downloads")
-            .map_err(|e| format!("Failed to find MediaStore.Downloads: {e}"))?;
-        let collection = env
-            .call_static_method(
-                downloads_class,
-                "getContentUri",
-                "(Ljava/lang/String;)Landroid/net/Uri;",
-                &[JValue::Object(&volume)],
-            )
-            .and_then(|value| value.l())
-            .map_err(|e| format!("Failed to get Downloads collection: {e}"))?;
-
-        let uri = env
-            .call_method(
-                resolver,
-                "insert",
-                "(Landroid/net/Uri;Landroid/content/ContentValues;)Landroid/net/Uri;",
-                &[JValue::Object(&collection), JValue::Object(&values)],
-            )
-            .and_then(|value| value.l())
-            .map_err(|e| format!("Failed to insert download: {e}"))?;
+        .and_then(|value| value.l())
+        .map_err(|e| format!("Failed to open output stream: {e}"))?;
+    if output_stream.is_null() {
+        return Err("Failed to open output stream".to_string());
+    }
+    env.new_global_ref(output_stream)
+        .map_err(|e| format!("Failed to keep output stream: {e}"))
+}
 
-        if uri.is_null() {
-            return Err("Failed to create download entry".to_string());
-        }
+#[cfg(target_os = "android")]
+fn delete_entry(env: &mut JNIEnv, resolver: &JObject, uri: &JObject) -> Result<(), String> {
+    let null_obj = JObject::null();
+    env.call_method(
+        resolver,
+        "delete",
+        "(Landroid/net/Uri;Ljava/lang/String;[Ljava/lang/String;)I",
+        &[
+            JValue::Object(uri),
+            JValue::Object(&null_obj),
+            JValue::Object(&null_obj),
+        ],
+    )
+    .map_err(|e| format!("Failed to delete download: {e}"))?;
+    Ok(())
+}
 
-        let uri_string = env
-            .call_method(uri, "toString", "()Ljava/lang/String;", &[])
-            .and_then(|value| value.l())
-            .map_err(|e| format!("Failed to read URI string: {e}"))?;
-        let uri_jstring = jni::objects::JString::from(uri_string);
-        let uri_rust: String = env
-            .get_string(&uri_jstring)
-            .map_err(|e| format!("Failed to convert URI string: {e}"))?
-            .into();
-
-        Ok(uri_rust)
-    })
+/// A download written next to the existing entry of the same name, settled in
+/// [`MediaStoreSink::finish`]: under [`CollisionPolicy::ReplaceIfBetter`]
+/// it replaces them if it is better, under [`CollisionPolicy::Overwrite`]
+/// always.
+#[cfg(target_os = "android")]
+#[derive(Debug)]
+struct PendingComparison {
+    filename: String,
+    mime: Option<String>,
+    existing: Vec<MediaEntry>,
+    compare: bool,
 }
 
+/// An entry being written, with its output stream kept open between chunks.
 #[cfg(target_os = "android")]
-#[tauri::command]
-pub fn android_download_write(
-    app: AppHandle,
-    args: AndroidDownloadWriteArgs,
-) -> Result<(), String> {
-    with_android_env(&app, move |env, activity, _webview| {
-        let resolver = env
-            .call_method(
-                activity,
-                "getContentResolver",
-                "()Landroid/content/ContentResolver;",
-                &[],
-            )
-            .and_then(|value| value.l())
-            .map_err(|e| format!("Failed to get ContentResolver: {e}"))?;
-
-        let uri_string = env
-            .new_string(args.uri)
-            .map_err(|e| format!("Failed to create URI string: {e}"))?;
-        let uri = env
-            .call_static_method(
-                "android/net/Uri",
-                "parse",
-                "(Ljava/lang/String;)Landroid/net/Uri;",
-                &[JValue::Object(&uri_string)],
-            )
-            .and_then(|value| value.l())
-            .map_err(|e| format!("Failed to parse URI: {e}"))?;
+struct MediaSession {
+    stream: GlobalRef,
+    written: u64,
+    last_write: Instant,
+    comparison: Option<PendingComparison>,
+}
 
-        let mode = if args.append.unwrap_or(false) {
-            "wa"
-        } else {
-            "w"
-        };
-        let mode_string = env
-            .new_string(mode)
-            .map_err(|e| format!("Failed to create mode string: {e}"))?;
-        let output_stream = env
-            .call_method(
-                resolver,
-                "openOutputStream",
-                "(Landroid/net/Uri;Ljava/lang/String;)Ljava/io/OutputStream;",
-                &[JValue::Object(&uri), JValue::Object(&mode_string)],
-            )
-            .and_then(|value| value.l())
-            .map_err(|e| format!("Failed to open output stream: {e}"))?;
+/// Writes into the public `MediaStore.Downloads` collection; sessions are
+/// the content URIs of pending entries.
+#[cfg(target_os = "android")]
+#[derive(Default)]
+pub struct MediaStoreSink {
+    sessions: Mutex<HashMap<String, MediaSession>>,
+}
 
-        if output_stream.is_null() {
-            return Err("Failed to open output stream".to_string());
-        }
+#[cfg(target_os = "android")]
+impl DownloadSink for MediaStoreSink {
+    fn begin(&self, app: &AppHandle, args: DownloadBeginArgs) -> Result<DownloadBegin, String> {
+        let policy = collision::policy(app);
+        let (begin, comparison) = with_android_env(app, move |env, activity, _webview| {
+            let sdk_int = env
+                .get_static_field("android/os/Build$VERSION", "SDK_INT", "I")
+                .and_then(|value| value.i())
+                .unwrap_or(0);
+
+            if sdk_int < 29 {
+                return Err("Android 10+ required for public downloads".to_string());
+            }
 
-        let bytes = env
-            .byte_array_from_slice(&args.data)
-            .map_err(|e| format!("Failed to create byte array: {e}"))?;
-        env.call_method(&output_stream, "write", "([B)V", &[JValue::Object(&bytes)])
-            .map_err(|e| format!("Failed to write bytes: {e}"))?;
-        let _ = env.call_method(&output_stream, "flush", "()V", &[]);
-        let _ = env.call_method(&output_stream, "close", "()V", &[]);
+            let resolver = content_resolver(env, activity)?;
+
+            let volume = env
+                .new_string("external_primary")
+                .map_err(|e| format!("Failed to create volume string: {e}"))?;
+            let downloads_class = env
+                .find_class("android/provider/MediaStore$Downloads")
+                .map_err(|e| format!("Failed to find MediaStore.Downloads: {e}"))?;
+            let collection = env
+                .call_static_method(
+                    downloads_class,
+                    "getContentUri",
+                    "(Ljava/lang/String;)Landroid/net/Uri;",
+                    &[JValue::Object(&volume)],
+                )
+                .and_then(|value| value.l())
+                .map_err(|e| format!("Failed to get Downloads collection: {e}"))?;
 
-        Ok(())
-    })
-}
+            let mut relative_path = args.relative_path.unwrap_or_else(|| "Download".to_string());
+            if !relative_path.ends_with('/') {
+                relative_path.push('/');
+            }
 
-#[cfg(target_os = "android")]
-#[tauri::command]
-pub fn android_download_finish(
-    app: AppHandle,
-    args: AndroidDownloadFinishArgs,
-) -> Result<(), String> {
-    with_android_env(&app, move |env, activity, _webview| {
-        let sdk_int = env
-            .get_static_field("android/os/Build$VERSION", "SDK_INT", "I")
-            .and_then(|value| value.i())
-            .unwrap_or(0);
-
-        if sdk_int < 29 {
-            return Ok(());
-        }
+            // Apply the collision policy against what is already in the folder.
+            let entries = list_entries(env, &resolver, &collection, &relative_path)?;
+            let conflicts: Vec<MediaEntry> = entries
+                .iter()
+                .filter(|entry| entry.name == args.filename)
+                .cloned()
+                .collect();
+            let mut display_name = args.filename.clone();
+            let mut comparison = None;
+            if let Some(first) = conflicts.first() {
+                match policy {
+                    CollisionPolicy::Overwrite => {
+                        // Written as a new pending entry; the old one is only
+                        // replaced once the download finishes.
+                        comparison = Some(PendingComparison {
+                            filename: args.filename.clone(),
+                            mime: args.mime_type.clone(),
+                            existing: conflicts,
+                            compare: false,
+                        });
+                    }
+                    CollisionPolicy::Skip => {
+                        let begin = DownloadBegin {
+                            session: None,
+                            saved_name: first.name.clone(),
+                            skipped: true,
+                        };
+                        return Ok((begin, None));
+                    }
+                    CollisionPolicy::KeepBoth => {
+                        display_name = (2..)
+                            .map(|n| collision::numbered_name(&args.filename, n))
+                            .find(|name| !entries.iter().any(|entry| entry.name == *name))
+                            .unwrap_or(display_name);
+                    }
+                    CollisionPolicy::ReplaceIfBetter => {
+                        // MediaStore gives the new entry a free name; `finish`
+                        // renames it once it has won the comparison.
+                        comparison = Some(PendingComparison {
+                            filename: args.filename.clone(),
+                            mime: args.mime_type.clone(),
+                            existing: conflicts,
+                            compare: true,
+                        });
+                    }
+                }
+            }
+
+            let values = env
+                .new_object("android/content/ContentValues", "()V", &[])
+                .map_err(|e| format!("Failed to create ContentValues: {e}"))?;
 
-        let resolver = env
-            .call_method(
-                activity,
-                "getContentResolver",
-                "()Landroid/content/ContentResolver;",
-                &[],
+            let display_name_key = env
+                .get_static_field(
+                    "android/provider/MediaStore$MediaColumns",
+                    "DISPLAY_NAME",
+                    "Ljava/lang/String;",
+                )
+                .and_then(|value| value.l())
+                .map_err(|e| format!("Failed to get DISPLAY_NAME: {e}"))?;
+
+            let filename = env
+                .new_string(&display_name)
+                .map_err(|e| format!("Failed to create filename string: {e}"))?;
+            env.call_method(
+                &values,
+                "put",
+                "(Ljava/lang/String;Ljava/lang/String;)V",
+                &[JValue::Object(&display_name_key), JValue::Object(&filename)],
             )
-            .and_then(|value| value.l())
-            .map_err(|e| format!("Failed to get ContentResolver: {e}"))?;
-
-        let uri_string = env
-            .new_string(args.uri)
-            .map_err(|e| format!("Failed to create URI string: {e}"))?;
-        let uri = env
-            .call_static_method(
-                "android/net/Uri",
-                "parse",
-                "(Ljava/lang/String;)Landroid/net/Uri;",
-                &[JValue::Object(&uri_string)],
+            .map_err(|e| format!("Failed to set display name: {e}"))?;
+
+            if let Some(mime) = args.mime_type.as_deref() {
+                if !mime.is_empty() {
+                    let mime_key = env
+                        .get_static_field(
+                            "android/provider/MediaStore$MediaColumns",
+                            "MIME_TYPE",
+                            "Ljava/lang/String;",
+                        )
+                        .and_then(|value| value.l())
+                        .map_err(|e| format!("Failed to get MIME_TYPE: {e}"))?;
+                    let mime_value = env
+                        .new_string(mime)
+                        .map_err(|e| format!("Failed to create MIME string: {e}"))?;
+                    env.call_method(
+                        &values,
+                        "put",
+                        "(Ljava/lang/String;Ljava/lang/String;)V",
+                        &[JValue::Object(&mime_key), JValue::Object(&mime_value)],
+                    )
+                    .map_err(|e| format!("Failed to set MIME type: {e}"))?;
+                }
+            }
+
+            let relative_path_key = env
+                .get_static_field(
+                    "android/provider/MediaStore$MediaColumns",
+                    "RELATIVE_PATH",
+                    "Ljava/lang/String;",
+                )
+                .and_then(|value| value.l())
+                .map_err(|e| format!("Failed to get RELATIVE_PATH: {e}"))?;
+
+            let relative_value = env
+                .new_string(&relative_path)
+                .map_err(|e| format!("Failed to create RELATIVE_PATH string: {e}"))?;
+            env.call_method(
+                &values,
+                "put",
+                "(Ljava/lang/String;Ljava/lang/String;)V",
+                &[
+                    JValue::Object(&relative_path_key),
+                    JValue::Object(&relative_value),
+                ],
             )
-            .and_then(|value| value.l())
-            .map_err(|e| format!("Failed to parse URI: {e}"))?;
-
-        let values = env
-            .new_object("android/content/ContentValues", "()V", &[])
-            .map_err(|e| format!("Failed to create ContentValues: {e}"))?;
-        let is_pending_key = env
-            .get_static_field(
-                "android/provider/MediaStore$MediaColumns",
-                "IS_PENDING",
-                "Ljava/lang/String;",
+            .map_err(|e| format!("Failed to set RELATIVE_PATH: {e}"))?;
+
+            let is_pending_key = env
+                .get_static_field(
+                    "android/provider/MediaStore$MediaColumns",
+                    "IS_PENDING",
+                    "Ljava/lang/String;",
+                )
+                .and_then(|value| value.l())
+                .map_err(|e| format!("Failed to get IS_PENDING: {e}"))?;
+            let pending_value = env
+                .new_object("java/lang/Integer", "(I)V", &[JValue::Int(1)])
+                .map_err(|e| format!("Failed to create pending Integer: {e}"))?;
+            env.call_method(
+                &values,
+                "put",
+                "(Ljava/lang/String;Ljava/lang/Integer;)V",
+                &[
+                    JValue::Object(&is_pending_key),
+                    JValue::Object(&pending_value),
+                ],
             )
-            .and_then(|value| value.l())
-            .map_err(|e| format!("Failed to get IS_PENDING: {e}"))?;
-        let pending_value = env
-            .new_object("java/lang/Integer", "(I)V", &[JValue::Int(0)])
-            .map_err(|e| format!("Failed to create pending Integer: {e}"))?;
-        env.call_method(
-            &values,
-            "put",
-            "(Ljava/lang/String;Ljava/lang/Integer;)V",
-            &[
-                JValue::Object(&is_pending_key),
-                JValue::Object(&pending_value),
-            ],
-        )
-        .map_err(|e| format!("Failed to update IS_PENDING: {e}"))?;
+            .map_err(|e| format!("Failed to set IS_PENDING: {e}"))?;
+
+            let uri = env
+                .call_method(
+                    resolver,
+                    "insert",
+                    "(Landroid/net/Uri;Landroid/content/ContentValues;)Landroid/net/Uri;",
+                    &[JValue::Object(&collection), JValue::Object(&values)],
+                )
+                .and_then(|value| value.l())
+                .map_err(|e| format!("Failed to insert download: {e}"))?;
 
-        let null_obj = JObject::null();
-        let _ = env.call_method(
+            if uri.is_null() {
+                return Err("Failed to create download entry".to_string());
+            }
+
+            let uri_string = env
+                .call_method(uri, "toString", "()Ljava/lang/String;", &[])
+                .and_then(|value| value.l())
+                .map_err(|e| format!("Failed to read URI string: {e}"))?;
+            let uri_jstring = jni::objects::JString::from(uri_string);
+            let uri_rust: String = env
+                .get_string(&uri_jstring)
+                .map_err(|e| format!("Failed to convert URI string: {e}"))?
+                .into();
+
+            let begin = DownloadBegin {
+                session: Some(uri_rust),
+                saved_name: display_name,
+                skipped: false,
+            };
+            Ok((begin, comparison))
+        })?;
+
+        let Some(uri) = begin.session.clone() else {
+            return Ok(begin);
+        };
+        download_sink::record(app, &uri);
+        let stream_uri = uri.clone();
+        let stream = with_android_env(app, move |env, activity, _webview| {
+            let resolver = content_resolver(env, activity)?;
+            open_output_stream(env, &resolver, &stream_uri)
+        });
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(error) => {
+                if self.discard(app, &uri).is_ok() {
+                    download_sink::forget(app, &uri);
+                }
+                return Err(error);
+            }
+        };
+        self.sessions
+            .lock()
+            .map_err(|_| "Failed to lock mutex")?
+            .insert(
+                uri,
+                MediaSession {
+                    stream,
+                    written: 0,
+                    last_write: Instant::now(),
+                    comparison,
+                },
+            );
+        Ok(begin)
+    }
+
+    fn write(&self, app: &AppHandle, session: &str, data: &[u8]) -> Result<u64, String> {
+        let stream = self
+            .sessions
+            .lock()
+            .map_err(|_| "Failed to lock mutex")?
+            .get(session)
+            .map(|session| session.stream.clone())
+            .ok_or("Unknown download")?;
+        let data = data.to_vec();
+        let len = data.len() as u64;
+        with_android_env(app, move |env, _activity, _webview| {
+            let bytes = env
+                .byte_array_from_slice(&data)
+                .map_err(|e| format!("Failed to create byte array: {e}"))?;
+            env.call_method(stream.as_obj(), "write", "([B)V", &[JValue::Object(&bytes)])
+                .map_err(|e| format!("Failed to write bytes: {e}"))?;
+            let _ = env.delete_local_ref(bytes);
+            Ok(())
+        })?;
+
+        let mut sessions = self.sessions.lock().map_err(|_| "Failed to lock mutex")?;
+        let session = sessions.get_mut(session).ok_or("Unknown download")?;
+        session.written += len;
+        session.last_write = Instant::now();
+        Ok(session.written)
+    }
+
+    /// Closes and publishes a written download. `kept` is `false` when it
+    /// lost a quality comparison and was discarded in favour of the
+    /// existing file.
+    fn finish(&self, app: &AppHandle, session: &str) -> Result<DownloadFinish, String> {
+        let MediaSession {
+            stream,
+            written,
+            comparison,
+            ..
+        } = self
+            .sessions
+            .lock()
+            .map_err(|_| "Failed to lock mutex")?
+            .remove(session)
+            .ok_or("Unknown download")?;
+        let uri = session.to_string();
+        // Left recorded when publishing fails, so the next run discards it.
+        let kept = with_android_env(app, move |env, activity, _webview| {
+            env.call_method(stream.as_obj(), "flush", "()V", &[])
+                .map_err(|e| format!("Failed to flush download: {e}"))?;
+            env.call_method(stream.as_obj(), "close", "()V", &[])
+                .map_err(|e| format!("Failed to close download: {e}"))?;
+
+            let sdk_int = env
+                .get_static_field("android/os/Build$VERSION", "SDK_INT", "I")
+                .and_then(|value| value.i())
+                .unwrap_or(0);
+
+            if sdk_int < 29 {
+                return Ok(true);
+            }
+
+            let resolver = content_resolver(env, activity)?;
+            let uri = parse_uri(env, &uri)?;
+
+            let values = env
+                .new_object("android/content/ContentValues", "()V", &[])
+                .map_err(|e| format!("Failed to create ContentValues: {e}"))?;
+
+            if let Some(comparison) = comparison {
+                let beats_all = !comparison.compare || {
+                    let new = Candidate {
+                        name: &comparison.filename,
+                        mime: comparison.mime.as_deref(),
+                        size: entry_size(env, &resolver, &uri)?,
+                    };
+                    comparison.existing.iter().all(|entry| {
+                        let old = Candidate {
+                            name: &entry.name,
+                            mime: entry.mime.as_deref(),
+                            size: entry.size,
+                        };
+                        collision::is_better(&new, &old)
+                    })
+                };
+                if !beats_all {
+                    delete_entry(env, &resolver, &uri)?;
+                    return Ok(false);
+                }
+                for entry in &comparison.existing {
+                    let existing = parse_uri(env, &entry.uri)?;
+                    delete_entry(env, &resolver, &existing)?;
+                }
+
+                // Take over the name the new entry could not get while the old
+                // file was still there.
+                let display_name_key = env
+                    .get_static_field(
+                        "android/provider/MediaStore$MediaColumns",
+                        "DISPLAY_NAME",
+                        "Ljava/lang/String;",
+                    )
+                    .and_then(|value| value.l())
+                    .map_err(|e| format!("Failed to get DISPLAY_NAME: {e}"))?;
+                let filename = env
+                    .new_string(&comparison.filename)
+                    .map_err(|e| format!("Failed to create filename string: {e}"))?;
+                env.call_method(
+                    &values,
+                    "put",
+                    "(Ljava/lang/String;Ljava/lang/String;)V",
+                    &[JValue::Object(&display_name_key), JValue::Object(&filename)],
+                )
+                .map_err(|e| format!("Failed to set display name: {e}"))?;
+            }
+
+            let is_pending_key = env
+                .get_static_field(
+                    "android/provider/MediaStore$MediaColumns",
+                    "IS_PENDING",
+                    "Ljava/lang/String;",
+                )
+                .and_then(|value| value.l())
+                .map_err(|e| format!("Failed to get IS_PENDING: {e}"))?;
+            let pending_value = env
+                .new_object("java/lang/Integer", "(I)V", &[JValue::Int(0)])
+                .map_err(|e| format!("Failed to create pending Integer: {e}"))?;
+            env.call_method(
+                &values,
+                "put",
+                "(Ljava/lang/String;Ljava/lang/Integer;)V",
+                &[
+                    JValue::Object(&is_pending_key),
+                    JValue::Object(&pending_value),
+                ],
+            )
+            .map_err(|e| format!("Failed to update IS_PENDING: {e}"))?;
+
+            let null_obj = JObject::null();
+            let _ = env.call_method(
             resolver,
             "update",
             "(Landroid/net/Uri;Landroid/content/ContentValues;Ljava/lang/String;[Ljava/lang/String;)I",
@@ -364,6 +703,53 @@ pub fn android_download_finish(
             ],
         );
 
-        Ok(())
-    })
+            Ok(true)
+        })?;
+        download_sink::forget(app, session);
+        Ok(DownloadFinish {
+            kept,
+            bytes: written,
+        })
+    }
+
+    fn cancel(&self, app: &AppHandle, session: &str) -> Result<(), String> {
+        let MediaSession { stream, .. } = self
+            .sessions
+            .lock()
+            .map_err(|_| "Failed to lock mutex")?
+            .remove(session)
+            .ok_or("Unknown download")?;
+        let uri = session.to_string();
+        let result = with_android_env(app, move |env, activity, _webview| {
+            let _ = env.call_method(stream.as_obj(), "close", "()V", &[]);
+            let resolver = content_resolver(env, activity)?;
+            let uri = parse_uri(env, &uri)?;
+            delete_entry(env, &resolver, &uri)
+        });
+        if result.is_ok() {
+            download_sink::forget(app, session);
+        }
+        result
+    }
+
+    fn idle_sessions(&self, idle: Duration) -> Vec<String> {
+        let Ok(sessions) = self.sessions.lock() else {
+            return Vec::new();
+        };
+        sessions
+            .iter()
+            .filter(|(_, session)| session.last_write.elapsed() > idle)
+            .map(|(uri, _)| uri.clone())
+            .collect()
+    }
+
+    /// Deletes an entry a previous run left `IS_PENDING`.
+    fn discard(&self, app: &AppHandle, location: &str) -> Result<(), String> {
+        let uri = location.to_string();
+        with_android_env(app, move |env, activity, _webview| {
+            let resolver = content_resolver(env, activity)?;
+            let uri = parse_uri(env, &uri)?;
+            delete_entry(env, &resolver, &uri)
+        })
+    }
 }
@@ -0,0 +1,246 @@
+use serde::{Deserialize, Serialize};
+#[cfg(desktop)]
+use std::fs;
+#[cfg(desktop)]
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager};
+
+const SETTINGS_FILE: &str = "collision_settings.json";
+
+/// Extensions of formats that store audio without loss.
+const LOSSLESS_EXTENSIONS: [&str; 6] = ["flac", "wav", "aiff", "aif", "alac", "wv"];
+const LOSSY_EXTENSIONS: [&str; 6] = ["m4a", "mp4", "aac", "ogg", "opus", "mp3"];
+
+// ---------------------------------------------------------------------------
+// Settings
+// ---------------------------------------------------------------------------
+
+/// What to do when a download would be saved under a name that is taken.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CollisionPolicy {
+    Overwrite,
+    Skip,
+    /// Save the new file as `Name (2).ext`, `Name (3).ext`, ...
+    #[default]
+    KeepBoth,
+    /// Download next to the existing file and keep whichever is better; see
+    /// [`is_better`].
+    ReplaceIfBetter,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CollisionSettings {
+    pub policy: CollisionPolicy,
+}
+
+#[derive(Default)]
+pub struct CollisionState {
+    settings: Mutex<CollisionSettings>,
+}
+
+pub fn load(app: &AppHandle) {
+    let state = app.state::<CollisionState>();
+    *state.settings.lock().unwrap() = crate::store::load_config(app, SETTINGS_FILE);
+}
+
+pub fn policy(app: &AppHandle) -> CollisionPolicy {
+    app.state::<CollisionState>()
+        .settings
+        .lock()
+        .unwrap()
+        .policy
+}
+
+// ---------------------------------------------------------------------------
+// Naming and quality
+// ---------------------------------------------------------------------------
+
+fn split_extension(name: &str) -> (&str, Option<&str>) {
+    match name.rfind('.') {
+        Some(index) if index > 0 => (&name[..index], Some(&name[index + 1..])),
+        _ => (name, None),
+    }
+}
+
+/// `Name.ext` -> `Name (n).ext`.
+pub fn numbered_name(name: &str, n: u32) -> String {
+    match split_extension(name) {
+        (stem, Some(ext)) => format!("{} ({}).{}", stem, n, ext),
+        (stem, None) => format!("{} ({})", stem, n),
+    }
+}
+
+/// 2 for lossless, 1 for lossy audio, 0 for anything unrecognised. The MIME
+/// type is consulted when the extension is missing or unknown.
+fn format_rank(ext: &str, mime: Option<&str>) -> u8 {
+    let ext = ext.to_ascii_lowercase();
+    if LOSSLESS_EXTENSIONS.contains(&ext.as_str()) {
+        return 2;
+    }
+    if LOSSY_EXTENSIONS.contains(&ext.as_str()) {
+        return 1;
+    }
+    match mime.unwrap_or_default() {
+        "audio/flac" | "audio/x-flac" | "audio/wav" | "audio/x-wav" | "audio/aiff" => 2,
+        mime if mime.starts_with("audio/") => 1,
+        _ => 0,
+    }
+}
+
+/// A file taking part in a quality comparison.
+#[derive(Debug, Clone)]
+pub struct Candidate<'a> {
+    pub name: &'a str,
+    pub mime: Option<&'a str>,
+    pub size: u64,
+}
+
+/// Lossless beats lossy; within the same class the larger file is taken to
+/// have the higher bitrate. Ties keep the existing file.
+pub fn is_better(new: &Candidate, existing: &Candidate) -> bool {
+    let rank = |candidate: &Candidate| {
+        let ext = split_extension(candidate.name).1.unwrap_or_default();
+        format_rank(ext, candidate.mime)
+    };
+    (rank(new), new.size) > (rank(existing), existing.size)
+}
+
+// ---------------------------------------------------------------------------
+// Filesystem
+// ---------------------------------------------------------------------------
+
+/// Where a download should be written under a given policy.
+#[cfg(desktop)]
+pub enum Resolution {
+    /// Write here; the path is free or may be overwritten.
+    Write(PathBuf),
+    /// Leave the existing file alone and don't download.
+    Skip(PathBuf),
+    /// Write to `staging`, then let [`settle`] keep the better of it and
+    /// `existing`, ending up at `target`.
+    Compare {
+        staging: PathBuf,
+        target: PathBuf,
+        existing: Vec<PathBuf>,
+    },
+}
+
+#[cfg(desktop)]
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Files that `target` would collide with: only itself. Copies in other
+/// formats are left alone; they may be kept on purpose, e.g. by a transcode
+/// profile that keeps the original.
+#[cfg(desktop)]
+fn existing(target: &Path) -> Vec<PathBuf> {
+    target
+        .is_file()
+        .then(|| target.to_path_buf())
+        .into_iter()
+        .collect()
+}
+
+/// First `Name (n).ext` next to `path` that doesn't exist yet.
+#[cfg(desktop)]
+pub fn free_path(path: &Path) -> PathBuf {
+    if !path.exists() {
+        return path.to_path_buf();
+    }
+    let name = file_name(path);
+    (2..)
+        .map(|n| path.with_file_name(numbered_name(&name, n)))
+        .find(|candidate| !candidate.exists())
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+#[cfg(desktop)]
+pub fn resolve(policy: CollisionPolicy, target: &Path) -> Resolution {
+    let existing = existing(target);
+    if existing.is_empty() {
+        return Resolution::Write(target.to_path_buf());
+    }
+    match policy {
+        CollisionPolicy::Overwrite => Resolution::Write(target.to_path_buf()),
+        CollisionPolicy::Skip => Resolution::Skip(target.to_path_buf()),
+        CollisionPolicy::KeepBoth => Resolution::Write(free_path(target)),
+        CollisionPolicy::ReplaceIfBetter => {
+            let mut staging = target.as_os_str().to_owned();
+            staging.push(".incoming");
+            Resolution::Compare {
+                staging: PathBuf::from(staging),
+                target: target.to_path_buf(),
+                existing,
+            }
+        }
+    }
+}
+
+/// Finishes a [`Resolution::Compare`]: if the staged download is better
+/// than every existing file they are removed and it is moved to `target`,
+/// otherwise it is discarded. Returns where the kept file is and whether
+/// it is the new one.
+#[cfg(desktop)]
+pub fn settle(
+    staging: &Path,
+    target: &Path,
+    existing: &[PathBuf],
+) -> Result<(PathBuf, bool), String> {
+    let size = |path: &Path| fs::metadata(path).map(|meta| meta.len()).unwrap_or(0);
+    let target_name = file_name(target);
+    let new = Candidate {
+        name: &target_name,
+        mime: None,
+        size: size(staging),
+    };
+
+    let winner = existing.iter().filter(|path| path.is_file()).find(|path| {
+        let name = file_name(path);
+        let old = Candidate {
+            name: &name,
+            mime: None,
+            size: size(path),
+        };
+        !is_better(&new, &old)
+    });
+    if let Some(winner) = winner {
+        let _ = fs::remove_file(staging);
+        return Ok((winner.clone(), false));
+    }
+
+    // Replaces `target` in one step, so a failed rename leaves the old file.
+    fs::rename(staging, target).map_err(|e| e.to_string())?;
+    for path in existing.iter().filter(|path| path.as_path() != target) {
+        let _ = fs::remove_file(path);
+    }
+    Ok((target.to_path_buf(), true))
+}
+
+// ---------------------------------------------------------------------------
+// Tauri commands
+// ---------------------------------------------------------------------------
+
+#[tauri::command]
+pub fn get_collision_settings(
+    state: tauri::State<CollisionState>,
+) -> Result<CollisionSettings, String> {
+    let settings = state.settings.lock().map_err(|_| "Failed to lock mutex")?;
+    Ok(settings.clone())
+}
+
+#[tauri::command]
+pub fn set_collision_settings(
+    app: AppHandle,
+    state: tauri::State<CollisionState>,
+    settings: CollisionSettings,
+) -> Result<(), String> {
+    crate::store::save_config(&app, SETTINGS_FILE, &settings)?;
+    *state.settings.lock().map_err(|_| "Failed to lock mutex")? = settings;
+    Ok(())
+}
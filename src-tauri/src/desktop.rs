@@ -1,8 +1,10 @@
+use crate::collision::{self, Resolution};
 use crate::{get_source_url, open_external, set_source_url};
 use discord_rich_presence::{DiscordIpc, DiscordIpcClient};
 use serde_json::json;
+use std::collections::{HashMap, VecDeque};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Mutex;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tauri::image::Image;
@@ -11,7 +13,28 @@ use tauri::tray::{MouseButton, MouseButtonState, TrayIconBuilder};
 use tauri::{AppHandle, Emitter, Manager, WebviewUrl, WebviewWindowBuilder};
 use tauri_plugin_dialog::DialogExt;
 use tauri_plugin_global_shortcut::{GlobalShortcutExt, ShortcutState};
-use tauri_plugin_notification::NotificationExt;
+
+mod cover_cache;
+mod device_sync;
+mod download_folder;
+mod download_history;
+mod download_manager;
+mod duplicates;
+mod history;
+mod integrity;
+mod library;
+mod loudness;
+mod naming;
+mod notifications;
+mod now_playing;
+mod offline;
+mod playlist_import;
+mod playlist_sync;
+mod playlists;
+mod shell_cache;
+mod storage;
+mod tagging;
+mod transcoding;
 
 // ---------------------------------------------------------------------------
 // State
@@ -22,8 +45,26 @@ struct DiscordState {
     last_song: Mutex<Option<String>>,
 }
 
+/// A webview download between its `Requested` and `Finished` events.
+struct WebviewDownload {
+    /// History ID.
+    id: String,
+    /// Where the webview writes the file.
+    destination: PathBuf,
+    metadata: Option<download_manager::TrackMetadata>,
+    /// Set when the file goes to a staging file that is compared against the
+    /// existing copy once it finishes.
+    comparison: Option<Resolution>,
+}
+
 struct DownloadState {
     path: Mutex<Option<PathBuf>>,
+    /// Webview downloads in flight by URL, oldest first; the same URL can be
+    /// downloaded more than once at a time.
+    active: Mutex<HashMap<String, VecDeque<WebviewDownload>>>,
+    /// Metadata registered by the web app for downloads it is about to start,
+    /// by URL in registration order, used to name and tag the file.
+    pending_metadata: Mutex<HashMap<String, VecDeque<download_manager::TrackMetadata>>>,
 }
 
 // ---------------------------------------------------------------------------
@@ -52,6 +93,173 @@ fn load_download_path(app: &AppHandle) -> Option<PathBuf> {
     None
 }
 
+/// URL the main window opens for the instance at `source`; see
+/// [`shell_cache::entry_url`].
+pub fn entry_url(app: &AppHandle, source: tauri::Url) -> tauri::Url {
+    shell_cache::entry_url(app, source)
+}
+
+/// Folder new downloads are saved to: the one the user picked, or the
+/// system Downloads folder when none is set or it is unavailable.
+pub fn download_dir(app: &AppHandle) -> Option<PathBuf> {
+    download_folder::resolve(app)
+}
+
+/// Makes room for a download of `bytes` under the download quota; see
+/// [`storage::reserve`].
+pub fn reserve_download_space(app: &AppHandle, bytes: u64) -> Result<(), String> {
+    storage::reserve(app, bytes)
+}
+
+/// Metadata the web app registered for its next download of `url`.
+fn take_pending_metadata(app: &AppHandle, url: &str) -> Option<download_manager::TrackMetadata> {
+    let state = app.state::<DownloadState>();
+    let mut pending = state.pending_metadata.lock().unwrap();
+    let metadata = pending.get_mut(url).and_then(VecDeque::pop_front);
+    if pending.get(url).is_some_and(VecDeque::is_empty) {
+        pending.remove(url);
+    }
+    metadata
+}
+
+/// Verifies, places and tags a webview or sink download once it has finished.
+/// Corrupt files are quarantined and, when enabled, downloaded again
+/// through the download manager. Returns the new file, or `None` when it
+/// lost to an existing one.
+async fn complete_download(
+    app: AppHandle,
+    id: String,
+    url: String,
+    path: Option<PathBuf>,
+    comparison: Option<Resolution>,
+    metadata: Option<download_manager::TrackMetadata>,
+) -> Result<Option<PathBuf>, String> {
+    let (written, target) = match &comparison {
+        Some(Resolution::Compare {
+            staging, target, ..
+        }) => (Some(staging.clone()), Some(target.clone())),
+        _ => (path.clone(), path),
+    };
+
+    if let Some(file) = &written {
+        if let Err(reason) = integrity::verify(&app, file, None).await {
+            if integrity::quarantine(&app, &id, file, &reason, 1) {
+                let filename = target
+                    .as_ref()
+                    .and_then(|target| target.file_name())
+                    .map(|name| name.to_string_lossy().into_owned());
+                // `blob:` and `data:` URLs only exist inside the page that
+                // made them, so the download manager can't fetch them.
+                let fetchable = tauri::Url::parse(&url)
+                    .is_ok_and(|parsed| matches!(parsed.scheme(), "http" | "https"));
+                let retried = if fetchable {
+                    download_manager::enqueue(&app, url.clone(), filename, metadata, 1).map(|_| ())
+                } else {
+                    Err("Downloads created by the page can't be retried; start it again.".into())
+                };
+                if let Err(error) = retried {
+                    let retry = download_history::start(&app, &url, target);
+                    download_history::fail(&app, &retry, &error);
+                }
+            }
+            return Err(reason);
+        }
+    }
+
+    let path = match comparison {
+        Some(Resolution::Compare {
+            staging,
+            target,
+            existing,
+        }) => match collision::settle(&staging, &target, &existing) {
+            Ok((kept, true)) => Some(kept),
+            Ok((kept, false)) => {
+                download_history::skip(&app, &id, kept);
+                return Ok(None);
+            }
+            Err(error) => {
+                download_history::fail(&app, &id, &error);
+                return Err(error);
+            }
+        },
+        _ => written,
+    };
+    if let Some(path) = &path {
+        tagging::tag_download(&app, path, metadata.as_ref()).await;
+    }
+    download_history::finish(&app, &id, path.clone());
+    if let Some(path) = &path {
+        download_manager::post_process(&app, path.clone());
+    }
+    Ok(path)
+}
+
+/// A download the page streams through the download sink. It is named,
+/// recorded, verified, tagged and post-processed like a webview download.
+pub struct SinkDownload {
+    id: String,
+    url: String,
+    metadata: Option<download_manager::TrackMetadata>,
+}
+
+impl SinkDownload {
+    /// Names a download of `url` that would otherwise be saved at `path`
+    /// and records it in the history. Returns where to write it.
+    pub fn begin(app: &AppHandle, url: &str, path: &Path) -> (PathBuf, Self) {
+        let metadata = take_pending_metadata(app, url);
+        let path = match (path.parent(), path.file_name()) {
+            (Some(base), Some(name)) => {
+                naming::destination(app, base, metadata.as_ref(), &name.to_string_lossy())
+            }
+            _ => path.to_path_buf(),
+        };
+        let id = download_history::start(app, url, Some(path.clone()));
+        let download = SinkDownload {
+            id,
+            url: url.to_string(),
+            metadata,
+        };
+        (path, download)
+    }
+
+    /// The download was skipped for `existing`.
+    pub fn skip(self, app: &AppHandle, existing: PathBuf) {
+        download_history::skip(app, &self.id, existing);
+    }
+
+    pub fn fail(self, app: &AppHandle, error: &str) {
+        download_history::fail(app, &self.id, error);
+    }
+
+    pub fn cancel(self, app: &AppHandle) {
+        download_history::cancel(app, &self.id);
+    }
+
+    /// Finishes a written download, either at `path` or as the staged side
+    /// of a `comparison`. Returns whether the new file was kept.
+    pub async fn complete(
+        self,
+        app: &AppHandle,
+        path: Option<PathBuf>,
+        comparison: Option<Resolution>,
+    ) -> Result<bool, String> {
+        let kept = complete_download(
+            app.clone(),
+            self.id,
+            self.url,
+            path,
+            comparison,
+            self.metadata,
+        )
+        .await?;
+        // The page may look the file up in the library straight away.
+        if let Some(path) = &kept {
+            library::refresh(app, [path.clone()]);
+        }
+        Ok(kept.is_some())
+    }
+}
+
 // ---------------------------------------------------------------------------
 // Tauri commands
 // ---------------------------------------------------------------------------
@@ -66,10 +274,17 @@ fn update_discord_presence(
     is_paused: bool,
     current_sec: f64,
 ) -> Result<(), String> {
-    let mut client_guard = state.client.lock().map_err(|_| "Failed to lock mutex")?;
-    let client = client_guard
-        .as_mut()
-        .ok_or("Discord client not initialized")?;
+    let cover = Some(image.clone()).filter(|image| image != "logo");
+    now_playing::publish(
+        &app,
+        &now_playing::Track {
+            title: details.clone(),
+            artist: status.clone(),
+            image: cover.clone(),
+            is_paused,
+            current_sec,
+        },
+    );
 
     let details = if details.len() < 2 {
         format!("{}  ", details)
@@ -110,18 +325,8 @@ fn update_discord_presence(
 
         if last_song_guard.as_deref() != Some(&current_song_key) {
             *last_song_guard = Some(current_song_key);
-
-            let window = app.get_webview_window("main");
-            if let Some(win) = window {
-                if !win.is_focused().unwrap_or(false) {
-                    let _ = app
-                        .notification()
-                        .builder()
-                        .title("Now Playing")
-                        .body(format!("{}\n{}", details, status))
-                        .show();
-                }
-            }
+            history::record_play(&app, &details, &status);
+            notifications::track_changed(&app, &details, &status, cover);
         }
     }
 
@@ -134,6 +339,11 @@ fn update_discord_presence(
         "nonce": format!("{}", SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis())
     });
 
+    let mut client_guard = state.client.lock().map_err(|_| "Failed to lock mutex")?;
+    let client = client_guard
+        .as_mut()
+        .ok_or("Discord client not initialized")?;
+
     if let Err(e) = client.send(payload.clone(), 1) {
         let _ = client.close();
         if client.connect().is_ok() {
@@ -146,6 +356,24 @@ fn update_discord_presence(
     Ok(())
 }
 
+/// Lets the web app attach track metadata to a download it is about to start
+/// through the webview, so the file can be named and tagged from it.
+#[tauri::command]
+fn register_download_metadata(
+    state: tauri::State<DownloadState>,
+    url: String,
+    metadata: download_manager::TrackMetadata,
+) -> Result<(), String> {
+    state
+        .pending_metadata
+        .lock()
+        .map_err(|_| "Failed to lock mutex")?
+        .entry(url)
+        .or_default()
+        .push_back(metadata);
+    Ok(())
+}
+
 // ---------------------------------------------------------------------------
 // Builder configuration (plugins, state, commands)
 // ---------------------------------------------------------------------------
@@ -170,18 +398,154 @@ pub fn configure(builder: tauri::Builder<tauri::Wry>) -> tauri::Builder<tauri::W
                 }
             },
         ))
+        .register_asynchronous_uri_scheme_protocol(
+            cover_cache::SCHEME,
+            |ctx, request, responder| {
+                let app = ctx.app_handle().clone();
+                tauri::async_runtime::spawn_blocking(move || {
+                    responder.respond(cover_cache::handle(&app, &request));
+                });
+            },
+        )
+        .register_asynchronous_uri_scheme_protocol(offline::SCHEME, |ctx, request, responder| {
+            let app = ctx.app_handle().clone();
+            tauri::async_runtime::spawn_blocking(move || {
+                responder.respond(offline::handle(&app, &request));
+            });
+        })
+        .register_asynchronous_uri_scheme_protocol(
+            shell_cache::SCHEME,
+            |ctx, request, responder| {
+                let app = ctx.app_handle().clone();
+                tauri::async_runtime::spawn(async move {
+                    responder.respond(shell_cache::handle(app, request).await);
+                });
+            },
+        )
         .manage(DiscordState {
             client: Mutex::new(client),
             last_song: Mutex::new(None),
         })
         .manage(DownloadState {
             path: Mutex::new(None),
+            active: Mutex::new(HashMap::new()),
+            pending_metadata: Mutex::new(HashMap::new()),
         })
+        .manage(device_sync::DeviceSyncState::default())
+        .manage(download_folder::DownloadFolderState::default())
+        .manage(download_history::DownloadHistoryState::default())
+        .manage(download_manager::DownloadManagerState::default())
+        .manage(crate::download_sink::DownloadSinkState::default())
+        .manage(collision::CollisionState::default())
+        .manage(cover_cache::CoverCacheState::default())
+        .manage(duplicates::DuplicateState::default())
+        .manage(history::HistoryState::default())
+        .manage(integrity::IntegrityState::default())
+        .manage(library::LibraryState::default())
+        .manage(loudness::LoudnessState::default())
+        .manage(naming::NamingState::default())
+        .manage(now_playing::NowPlayingState::default())
+        .manage(notifications::NotificationState::default())
+        .manage(playlist_import::PlaylistImportState::default())
+        .manage(playlist_sync::PlaylistSyncState::default())
+        .manage(playlists::PlaylistState::default())
+        .manage(shell_cache::ShellCacheState::default())
+        .manage(storage::StorageState::default())
+        .manage(tagging::TaggingState::default())
+        .manage(transcoding::TranscodingState::default())
         .invoke_handler(tauri::generate_handler![
             update_discord_presence,
             open_external,
             get_source_url,
-            set_source_url
+            set_source_url,
+            register_download_metadata,
+            cover_cache::get_cover_art,
+            cover_cache::get_cover_cache_stats,
+            cover_cache::set_cover_cache_settings,
+            cover_cache::clear_cover_cache,
+            device_sync::get_device_sync_settings,
+            device_sync::set_device_sync_settings,
+            device_sync::pick_device_folder,
+            device_sync::sync_to_device,
+            device_sync::cancel_device_sync,
+            download_folder::get_download_dir,
+            download_folder::reset_download_dir,
+            download_folder::pick_download_dir,
+            download_folder::reveal_in_file_manager,
+            download_history::get_download_history,
+            download_history::clear_download_history,
+            duplicates::scan_duplicates,
+            duplicates::resolve_duplicates,
+            collision::get_collision_settings,
+            collision::set_collision_settings,
+            download_manager::enqueue_download,
+            download_manager::list_downloads,
+            download_manager::pause_download,
+            download_manager::resume_download,
+            download_manager::cancel_download,
+            download_manager::get_download_manager_settings,
+            download_manager::set_download_manager_settings,
+            crate::download_sink::download_begin,
+            crate::download_sink::download_write,
+            crate::download_sink::download_finish,
+            crate::download_sink::download_cancel,
+            history::import_listening_history,
+            history::get_listening_history,
+            integrity::get_integrity_settings,
+            integrity::set_integrity_settings,
+            integrity::verify_audio_file,
+            integrity::clear_quarantine,
+            library::rescan_library,
+            library::search_library,
+            library::get_library_artists,
+            library::get_library_albums,
+            library::get_library_tracks,
+            loudness::get_loudness_settings,
+            loudness::set_loudness_settings,
+            loudness::scan_loudness,
+            naming::get_naming_settings,
+            naming::set_naming_settings,
+            naming::preview_download_path,
+            now_playing::get_now_playing_settings,
+            now_playing::set_now_playing_settings,
+            now_playing::pick_now_playing_folder,
+            now_playing::reset_now_playing_folder,
+            notifications::get_notification_settings,
+            notifications::set_notification_settings,
+            offline::open_offline_library,
+            playlist_import::import_playlist_files,
+            playlist_import::report_playlist_matches,
+            playlist_sync::watch_playlist,
+            playlist_sync::unwatch_playlist,
+            playlist_sync::list_watched_playlists,
+            playlist_sync::sync_watched_playlist,
+            playlist_sync::report_playlist_contents,
+            playlist_sync::fail_playlist_sync,
+            playlist_sync::get_playlist_sync_log,
+            playlist_sync::clear_playlist_sync_log,
+            playlists::export_playlist,
+            playlists::regenerate_playlists,
+            playlists::list_exported_playlists,
+            playlists::remove_exported_playlist,
+            shell_cache::get_shell_cache_settings,
+            shell_cache::set_shell_cache_settings,
+            shell_cache::get_shell_cache_stats,
+            shell_cache::clear_shell_cache,
+            storage::get_storage_settings,
+            storage::set_storage_settings,
+            storage::get_storage_usage,
+            tagging::get_tagging_settings,
+            tagging::set_tagging_settings,
+            tagging::retag_file,
+            transcoding::get_transcoding_settings,
+            transcoding::set_transcoding_settings,
+            transcoding::get_ffmpeg_path,
+            transcoding::pick_ffmpeg_path,
+            transcoding::clear_ffmpeg_path,
+            transcoding::pick_transcode_output_dir,
+            transcoding::reset_transcode_output_dir,
+            transcoding::transcode_files,
+            transcoding::list_transcode_queue
         ])
 }
 
@@ -198,6 +562,27 @@ pub fn setup(app: &mut tauri::App) -> Result<(), Box<dyn std::error::Error>> {
 
     let state = app.state::<DownloadState>();
     *state.path.lock().unwrap() = load_download_path(app.handle());
+    download_folder::load(app.handle());
+    cover_cache::load(app.handle());
+    download_history::load(app.handle());
+    history::load(app.handle());
+    naming::load(app.handle());
+    now_playing::load(app.handle());
+    notifications::load(app.handle());
+    shell_cache::load(app.handle());
+    storage::load(app.handle());
+    playlists::load(app.handle());
+    device_sync::load(app.handle());
+    playlist_sync::load(app.handle());
+    tagging::load(app.handle());
+    transcoding::load(app.handle());
+    integrity::load(app.handle());
+    loudness::load(app.handle());
+    collision::load(app.handle());
+    crate::download_sink::load(app.handle());
+    duplicates::load(app.handle());
+    library::load(app.handle());
+    download_manager::load(app.handle());
 
     // System tray
     let quit = MenuItemBuilder::with_id("quit", "Quit Monochrome").build(app)?;
@@ -225,11 +610,10 @@ pub fn setup(app: &mut tauri::App) -> Result<(), Box<dyn std::error::Error>> {
             "change_dl" => {
                 let app_handle = app.clone();
                 app.dialog().file().pick_folder(move |folder| {
-                    if let Some(path) = folder {
-                        let path = path.into_path().unwrap();
-                        let state = app_handle.state::<DownloadState>();
-                        *state.path.lock().unwrap() = Some(path.clone());
-                        save_download_path(&app_handle, &path);
+                    if let Some(path) = folder.and_then(|folder| folder.into_path().ok()) {
+                        if let Err(error) = download_folder::set(&app_handle, Some(path)) {
+                            notifications::error(&app_handle, "Download Folder Not Set", &error);
+                        }
                     }
                 });
             }
@@ -270,29 +654,136 @@ pub fn setup(app: &mut tauri::App) -> Result<(), Box<dyn std::error::Error>> {
         "../scripts/desktop/discord_presence_bridge.js"
     ));
     init_script.push('\n');
+    init_script.push_str(include_str!(
+        "../scripts/desktop/download_platform_adapter.js"
+    ));
+    init_script.push('\n');
+    init_script.push_str(include_str!("../scripts/mobile/download_interceptor.js"));
+    init_script.push('\n');
     init_script.push_str(include_str!("../scripts/mobile/external_link_router.js"));
     init_script.push('\n');
     let fallback_script = include_str!("../scripts/mobile/source_url_fallback.js")
         .replace("__EXPECTED_URL__", &source_url)
-        .replace("__DEFAULT_URL__", crate::DEFAULT_SOURCE_URL);
+        .replace("__DEFAULT_URL__", crate::DEFAULT_SOURCE_URL)
+        .replace("__OFFLINE_LIBRARY__", "true");
     init_script.push_str(&fallback_script);
     let window = WebviewWindowBuilder::new(
         app,
         "main",
-        WebviewUrl::External(source_url.parse().unwrap()),
+        WebviewUrl::External(shell_cache::entry_url(
+            app.handle(),
+            source_url.parse().unwrap(),
+        )),
     )
     .title("Monochrome")
     .inner_size(1200.0, 800.0)
     .initialization_script(&init_script)
     .on_download(move |_webview, event| {
-        if let tauri::webview::DownloadEvent::Requested { destination, .. } = event {
-            let state = app_handle.state::<DownloadState>();
-            let path_guard = state.path.lock().unwrap();
-            if let Some(path) = &*path_guard {
-                if let Some(name) = destination.file_name() {
-                    *destination = path.join(name);
+        let state = app_handle.state::<DownloadState>();
+        match event {
+            tauri::webview::DownloadEvent::Requested { url, destination } => {
+                let metadata = take_pending_metadata(&app_handle, url.as_str());
+                let base =
+                    download_dir(&app_handle).or_else(|| destination.parent().map(PathBuf::from));
+                let name = destination
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned());
+                if let (Some(base), Some(name)) = (base, name) {
+                    *destination =
+                        naming::destination(&app_handle, &base, metadata.as_ref(), &name);
+                    if let Some(parent) = destination.parent() {
+                        let _ = fs::create_dir_all(parent);
+                    }
+                }
+                // The size isn't known yet; this only stops downloads once
+                // the folder is already over its quota.
+                if let Err(error) = storage::admit(&app_handle) {
+                    let id = download_history::start(
+                        &app_handle,
+                        url.as_str(),
+                        Some(destination.clone()),
+                    );
+                    download_history::fail(&app_handle, &id, &error);
+                    return false;
+                }
+                let resolution = collision::resolve(collision::policy(&app_handle), destination);
+                let comparison = match resolution {
+                    Resolution::Write(path) => {
+                        *destination = path;
+                        None
+                    }
+                    Resolution::Skip(existing) => {
+                        let id = download_history::start(
+                            &app_handle,
+                            url.as_str(),
+                            Some(destination.clone()),
+                        );
+                        download_history::skip(&app_handle, &id, existing);
+                        return false;
+                    }
+                    Resolution::Compare { ref staging, .. } => {
+                        *destination = staging.clone();
+                        Some(resolution)
+                    }
+                };
+                let id =
+                    download_history::start(&app_handle, url.as_str(), Some(destination.clone()));
+                state
+                    .active
+                    .lock()
+                    .unwrap()
+                    .entry(url.to_string())
+                    .or_default()
+                    .push_back(WebviewDownload {
+                        id,
+                        destination: destination.clone(),
+                        metadata,
+                        comparison,
+                    });
+            }
+            tauri::webview::DownloadEvent::Finished { url, path, success } => {
+                // Matched by the file it was written to, falling back to the
+                // oldest download of the URL when the platform doesn't say.
+                let download = {
+                    let mut active = state.active.lock().unwrap();
+                    let download = active.get_mut(url.as_str()).and_then(|queue| {
+                        let index = path
+                            .as_ref()
+                            .and_then(|path| {
+                                queue
+                                    .iter()
+                                    .position(|download| &download.destination == path)
+                            })
+                            .unwrap_or(0);
+                        queue.remove(index)
+                    });
+                    if active.get(url.as_str()).is_some_and(VecDeque::is_empty) {
+                        active.remove(url.as_str());
+                    }
+                    download
+                };
+                let (id, metadata, comparison) = match download {
+                    Some(download) => (download.id, download.metadata, download.comparison),
+                    None => (
+                        download_history::start(&app_handle, url.as_str(), path.clone()),
+                        None,
+                        None,
+                    ),
+                };
+                if !success {
+                    download_history::fail(&app_handle, &id, "The download did not complete.");
+                    return true;
                 }
+                tauri::async_runtime::spawn(complete_download(
+                    app_handle.clone(),
+                    id,
+                    url.to_string(),
+                    path,
+                    comparison,
+                    metadata,
+                ));
             }
+            _ => {}
         }
         true
     })
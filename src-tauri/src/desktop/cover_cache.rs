@@ -0,0 +1,442 @@
+use crate::desktop::storage;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tauri::http::{header, Request, Response, StatusCode};
+use tauri::{AppHandle, Manager};
+
+const SETTINGS_FILE: &str = "cover_cache_settings.json";
+const INDEX_FILE: &str = "index.json";
+const ORIGINAL_FILE: &str = "original";
+
+/// Scheme cached covers are served from, so the remote-origin webview can
+/// show them; it can't load `file://` URLs.
+pub const SCHEME: &str = "cover";
+
+/// Sizes (square, in pixels) the cache renders. Requests are rounded up to the
+/// next size so callers asking for slightly different sizes share files.
+pub const SIZES: [u32; 4] = [64, 128, 256, 512];
+
+/// Covers larger than this are not cached.
+const MAX_DOWNLOAD_BYTES: u64 = 20 * 1024 * 1024;
+const FETCH_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// How often use times recorded by cache hits are written to the index.
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+// ---------------------------------------------------------------------------
+// Settings
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CoverCacheSettings {
+    /// Total size the cache may grow to before least recently used covers are
+    /// evicted.
+    pub max_bytes: u64,
+}
+
+impl Default for CoverCacheSettings {
+    fn default() -> Self {
+        Self {
+            max_bytes: 200 * 1024 * 1024,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// State
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CoverEntry {
+    url: String,
+    /// File name inside the entry folder -> size in bytes.
+    files: BTreeMap<String, u64>,
+    last_used: u64,
+}
+
+impl CoverEntry {
+    fn bytes(&self) -> u64 {
+        self.files.values().sum()
+    }
+}
+
+pub struct CoverCacheState {
+    settings: Mutex<CoverCacheSettings>,
+    /// Keyed by the hash of the cover URL, which is also the folder name.
+    index: Mutex<HashMap<String, CoverEntry>>,
+    /// Set when `last_used` changed since the index was last written.
+    dirty: AtomicBool,
+    client: reqwest::Client,
+}
+
+impl Default for CoverCacheState {
+    fn default() -> Self {
+        Self {
+            settings: Mutex::default(),
+            index: Mutex::default(),
+            dirty: AtomicBool::new(false),
+            client: reqwest::Client::builder()
+                .timeout(FETCH_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CoverArt {
+    path: PathBuf,
+    uri: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CoverCacheStats {
+    entries: usize,
+    bytes: u64,
+    max_bytes: u64,
+}
+
+pub fn load(app: &AppHandle) {
+    let state = app.state::<CoverCacheState>();
+    *state.settings.lock().unwrap() = crate::store::load_config(app, SETTINGS_FILE);
+    if let Some(dir) = cache_dir(app) {
+        *state.index.lock().unwrap() =
+            crate::store::read_json(&dir.join(INDEX_FILE)).unwrap_or_default();
+    }
+
+    let app = app.clone();
+    std::thread::spawn(move || loop {
+        std::thread::sleep(SAVE_INTERVAL);
+        let state = app.state::<CoverCacheState>();
+        if state.dirty.swap(false, Ordering::SeqCst) {
+            if let Some(dir) = cache_dir(&app) {
+                save_index(&dir, &state.index.lock().unwrap());
+            }
+        }
+    });
+}
+
+// ---------------------------------------------------------------------------
+// Helpers
+// ---------------------------------------------------------------------------
+
+fn cache_dir(app: &AppHandle) -> Option<PathBuf> {
+    app.path()
+        .app_cache_dir()
+        .ok()
+        .map(|dir| dir.join("covers"))
+}
+
+fn cache_key(url: &str) -> String {
+    Sha256::digest(url.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn variant_name(size: Option<u32>) -> String {
+    match size {
+        Some(size) => {
+            let size = SIZES
+                .iter()
+                .copied()
+                .find(|s| *s >= size)
+                .unwrap_or(SIZES[SIZES.len() - 1]);
+            format!("{}.jpg", size)
+        }
+        None => ORIGINAL_FILE.to_string(),
+    }
+}
+
+fn variant_size(name: &str) -> Option<u32> {
+    name.strip_suffix(".jpg")?.parse().ok()
+}
+
+/// URL of a cached file on the [`SCHEME`] protocol, in the form
+/// `convertFileSrc` produces.
+fn cover_uri(key: &str, name: &str) -> String {
+    if cfg!(windows) {
+        format!("http://{}.localhost/{}/{}", SCHEME, key, name)
+    } else {
+        format!("{}://localhost/{}/{}", SCHEME, key, name)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn save_index(dir: &Path, index: &HashMap<String, CoverEntry>) {
+    let _ = crate::store::write_json(&dir.join(INDEX_FILE), index);
+}
+
+/// Marks `key` as used, optionally recording a newly written file. Only new
+/// files are saved right away; use times are saved in the background.
+fn touch(app: &AppHandle, key: &str, url: &str, written: Option<(&str, u64)>) {
+    let Some(dir) = cache_dir(app) else {
+        return;
+    };
+    let state = app.state::<CoverCacheState>();
+    let mut index = state.index.lock().unwrap();
+    let entry = index.entry(key.to_string()).or_default();
+    entry.url = url.to_string();
+    entry.last_used = now();
+    match written {
+        Some((name, bytes)) => {
+            entry.files.insert(name.to_string(), bytes);
+            state.dirty.store(false, Ordering::SeqCst);
+            save_index(&dir, &index);
+        }
+        None => state.dirty.store(true, Ordering::SeqCst),
+    }
+}
+
+/// Drops least recently used covers until the cache fits `max_bytes` and
+/// the app cache quota. The entry in `keep` survives even if it alone
+/// exceeds the limit.
+fn evict(app: &AppHandle, keep: Option<&str>) {
+    let Some(dir) = cache_dir(app) else {
+        return;
+    };
+    let state = app.state::<CoverCacheState>();
+    let max_bytes = state
+        .settings
+        .lock()
+        .unwrap()
+        .max_bytes
+        .min(storage::cover_budget(app));
+    let mut index = state.index.lock().unwrap();
+
+    let mut total: u64 = index.values().map(CoverEntry::bytes).sum();
+    if total <= max_bytes {
+        return;
+    }
+    state.dirty.store(false, Ordering::SeqCst);
+
+    let mut by_age: Vec<(String, u64, u64)> = index
+        .iter()
+        .filter(|(key, _)| Some(key.as_str()) != keep)
+        .map(|(key, entry)| (key.clone(), entry.last_used, entry.bytes()))
+        .collect();
+    by_age.sort_by_key(|(_, last_used, _)| *last_used);
+
+    for (key, _, bytes) in by_age {
+        if total <= max_bytes {
+            break;
+        }
+        let _ = fs::remove_dir_all(dir.join(&key));
+        index.remove(&key);
+        total = total.saturating_sub(bytes);
+    }
+    save_index(&dir, &index);
+}
+
+/// Total size of the cached covers.
+pub fn bytes(app: &AppHandle) -> u64 {
+    let state = app.state::<CoverCacheState>();
+    let index = state.index.lock().unwrap();
+    index.values().map(CoverEntry::bytes).sum()
+}
+
+/// Evicts covers until the cache is within its limits again.
+pub fn trim(app: &AppHandle) {
+    evict(app, None);
+}
+
+fn render_variant(original: &Path, target: &Path, size: u32) -> Result<u64, String> {
+    let data = fs::read(original).map_err(|e| e.to_string())?;
+    let image = image::load_from_memory(&data).map_err(|e| e.to_string())?;
+    let resized = DynamicImage::ImageRgb8(image.resize(size, size, FilterType::Lanczos3).to_rgb8());
+    let mut encoded = Vec::new();
+    resized
+        .write_with_encoder(JpegEncoder::new_with_quality(&mut encoded, 88))
+        .map_err(|e| e.to_string())?;
+    crate::store::write_atomic(target, &encoded)?;
+    Ok(encoded.len() as u64)
+}
+
+// ---------------------------------------------------------------------------
+// Lookup
+// ---------------------------------------------------------------------------
+
+/// The cover at `url`, unless it is larger than [`MAX_DOWNLOAD_BYTES`].
+async fn download(app: &AppHandle, url: &str) -> Option<Vec<u8>> {
+    let client = app.state::<CoverCacheState>().client.clone();
+    let mut response = client.get(url).send().await.ok()?.error_for_status().ok()?;
+    if response
+        .content_length()
+        .is_some_and(|length| length > MAX_DOWNLOAD_BYTES)
+    {
+        return None;
+    }
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response.chunk().await.ok()? {
+        if (bytes.len() + chunk.len()) as u64 > MAX_DOWNLOAD_BYTES {
+            return None;
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Some(bytes)
+}
+
+/// Returns a local copy of the cover at `url`, downloading it on first use.
+/// With a `size` the cover is scaled to fit the next size in [`SIZES`] and
+/// re-encoded as JPEG; without one the original bytes are returned.
+pub async fn fetch(app: &AppHandle, url: &str, size: Option<u32>) -> Option<PathBuf> {
+    let dir = cache_dir(app)?;
+    let key = cache_key(url);
+    let entry_dir = dir.join(&key);
+    let name = variant_name(size);
+    let path = entry_dir.join(&name);
+
+    if path.exists() {
+        touch(app, &key, url, None);
+        return Some(path);
+    }
+
+    let original = entry_dir.join(ORIGINAL_FILE);
+    if !original.exists() {
+        let bytes = download(app, url).await?;
+        crate::store::write_atomic(&original, &bytes).ok()?;
+        touch(app, &key, url, Some((ORIGINAL_FILE, bytes.len() as u64)));
+    }
+
+    if let Some(size) = variant_size(&name) {
+        let target = path.clone();
+        let bytes =
+            tauri::async_runtime::spawn_blocking(move || render_variant(&original, &target, size))
+                .await
+                .ok()?
+                .ok()?;
+        touch(app, &key, url, Some((&name, bytes)));
+    }
+
+    evict(app, Some(&key));
+    Some(path)
+}
+
+// ---------------------------------------------------------------------------
+// Protocol
+// ---------------------------------------------------------------------------
+
+/// Sniffs the image type; originals are stored as the server sent them.
+fn image_type(data: &[u8]) -> &'static str {
+    match data {
+        [0xFF, 0xD8, ..] => "image/jpeg",
+        [0x89, b'P', b'N', b'G', ..] => "image/png",
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Answers `/<key>/<variant>` on the [`SCHEME`] protocol. Only files the
+/// index knows are served.
+pub fn handle(app: &AppHandle, request: &Request<Vec<u8>>) -> Response<Vec<u8>> {
+    let file = || -> Option<Vec<u8>> {
+        let (key, name) = request
+            .uri()
+            .path()
+            .trim_start_matches('/')
+            .split_once('/')?;
+        let url = {
+            let state = app.state::<CoverCacheState>();
+            let index = state.index.lock().unwrap();
+            let entry = index.get(key)?;
+            if !entry.files.contains_key(name) {
+                return None;
+            }
+            entry.url.clone()
+        };
+        touch(app, key, &url, None);
+        fs::read(cache_dir(app)?.join(key).join(name)).ok()
+    };
+    match file() {
+        Some(data) => Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, image_type(&data))
+            .body(data),
+        None => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Vec::new()),
+    }
+    .unwrap_or_default()
+}
+
+// ---------------------------------------------------------------------------
+// Tauri commands
+// ---------------------------------------------------------------------------
+
+#[tauri::command]
+pub async fn get_cover_art(
+    app: AppHandle,
+    url: String,
+    size: Option<u32>,
+) -> Result<CoverArt, String> {
+    if !(url.starts_with("https://") || url.starts_with("http://")) {
+        return Err("unsupported url scheme".into());
+    }
+    let path = fetch(&app, &url, size)
+        .await
+        .ok_or("Failed to fetch cover art")?;
+    let uri = cover_uri(&cache_key(&url), &variant_name(size));
+    Ok(CoverArt { path, uri })
+}
+
+#[tauri::command]
+pub fn get_cover_cache_stats(
+    state: tauri::State<CoverCacheState>,
+) -> Result<CoverCacheStats, String> {
+    let max_bytes = state
+        .settings
+        .lock()
+        .map_err(|_| "Failed to lock mutex")?
+        .max_bytes;
+    let index = state.index.lock().map_err(|_| "Failed to lock mutex")?;
+    Ok(CoverCacheStats {
+        entries: index.len(),
+        bytes: index.values().map(CoverEntry::bytes).sum(),
+        max_bytes,
+    })
+}
+
+#[tauri::command]
+pub fn set_cover_cache_settings(
+    app: AppHandle,
+    state: tauri::State<CoverCacheState>,
+    settings: CoverCacheSettings,
+) -> Result<(), String> {
+    crate::store::save_config(&app, SETTINGS_FILE, &settings)?;
+    *state.settings.lock().map_err(|_| "Failed to lock mutex")? = settings;
+    evict(&app, None);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn clear_cover_cache(
+    app: AppHandle,
+    state: tauri::State<CoverCacheState>,
+) -> Result<(), String> {
+    let dir = cache_dir(&app).ok_or("Cache directory unavailable")?;
+    let mut index = state.index.lock().map_err(|_| "Failed to lock mutex")?;
+    if dir.exists() {
+        fs::remove_dir_all(&dir).map_err(|e| e.to_string())?;
+    }
+    index.clear();
+    Ok(())
+}
@@ -0,0 +1,619 @@
+use crate::desktop::transcoding::{self, TranscodeProfile};
+use crate::desktop::{library, naming, playlists};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+use tauri::{AppHandle, Emitter, Manager};
+use tauri_plugin_dialog::DialogExt;
+
+const SETTINGS_FILE: &str = "device_sync_settings.json";
+
+/// Kept in the target folder; lists the files a sync put there so later
+/// runs copy only what changed and never touch anything else.
+const MANIFEST_FILE: &str = ".monochrome-sync.json";
+
+/// FAT32 can't hold files of 4 GiB or more.
+const FAT32_MAX_FILE_BYTES: u64 = u32::MAX as u64;
+
+/// FAT32 long names hold 255 UTF-16 units; counting bytes stays under that.
+const MAX_NAME_BYTES: usize = 255;
+
+/// Folder on the device for tracks from outside the download folder.
+const OTHER_DIR: &str = "Other";
+
+// ---------------------------------------------------------------------------
+// Settings
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlbumRef {
+    pub album: String,
+    /// Album artist; any artist when unset.
+    #[serde(default)]
+    pub artist: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DeviceSyncSettings {
+    /// Device folder to mirror into.
+    pub target: Option<PathBuf>,
+    /// IDs of exported playlists.
+    pub playlists: Vec<String>,
+    pub albums: Vec<AlbumRef>,
+    /// Transcoding profile applied on the way; files are copied as they are
+    /// when unset.
+    pub profile: Option<String>,
+    /// Write an M3U8 file per playlist to the device root.
+    pub write_playlists: bool,
+}
+
+impl Default for DeviceSyncSettings {
+    fn default() -> Self {
+        Self {
+            target: None,
+            playlists: Vec::new(),
+            albums: Vec::new(),
+            profile: None,
+            write_playlists: true,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// State
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ManifestEntry {
+    source: PathBuf,
+    size: u64,
+    modified: u64,
+    #[serde(default)]
+    profile: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct Manifest {
+    /// By path relative to the target, with `/` separators.
+    files: HashMap<String, ManifestEntry>,
+    playlists: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceSyncReport {
+    pub target: PathBuf,
+    pub copied: usize,
+    pub transcoded: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+    pub playlists: Vec<PathBuf>,
+    /// `file: reason` for every file that could not be synced.
+    pub failed: Vec<String>,
+    pub cancelled: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProgressPayload<'a> {
+    done: usize,
+    total: usize,
+    file: &'a str,
+}
+
+#[derive(Default)]
+pub struct DeviceSyncState {
+    settings: Mutex<DeviceSyncSettings>,
+    running: AtomicBool,
+    cancel: AtomicBool,
+}
+
+pub fn load(app: &AppHandle) {
+    let state = app.state::<DeviceSyncState>();
+    *state.settings.lock().unwrap() = crate::store::load_config(app, SETTINGS_FILE);
+}
+
+/// Clears the running flag however the sync ends.
+struct RunningGuard<'a>(&'a AtomicBool);
+
+impl Drop for RunningGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Planning
+// ---------------------------------------------------------------------------
+
+/// One file to put on the device.
+struct Item {
+    source: PathBuf,
+    /// Relative to the target, with `/` separators.
+    target: String,
+    entry: ManifestEntry,
+    /// Profile to convert with; copied as it is when `None`.
+    profile: Option<TranscodeProfile>,
+}
+
+fn modified(metadata: &fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|time| time.as_secs())
+        .unwrap_or(0)
+}
+
+/// Album tracks in disc and track order.
+fn album_tracks(app: &AppHandle, album: &AlbumRef) -> Vec<PathBuf> {
+    let name = album.album.to_lowercase();
+    let artist = album.artist.as_ref().map(|artist| artist.to_lowercase());
+    let mut tracks: Vec<library::LibraryTrack> = library::tracks(app)
+        .into_iter()
+        .filter(|track| {
+            track
+                .album
+                .as_ref()
+                .is_some_and(|value| value.to_lowercase() == name)
+        })
+        .filter(|track| {
+            artist.as_ref().is_none_or(|artist| {
+                track
+                    .album_artist
+                    .as_ref()
+                    .or(track.artist.as_ref())
+                    .is_some_and(|value| value.to_lowercase() == *artist)
+            })
+        })
+        .collect();
+    tracks.sort_by(|a, b| {
+        (a.disc_number, a.track_number, &a.path).cmp(&(b.disc_number, b.track_number, &b.path))
+    });
+    tracks.into_iter().map(|track| track.path).collect()
+}
+
+/// Device path for `source`: its place below the download folder, every
+/// component made FAT32-safe, with the profile's extension when converted.
+fn target_path(root: Option<&Path>, source: &Path, extension: Option<&str>) -> Vec<String> {
+    let relative = root
+        .and_then(|root| source.strip_prefix(root).ok())
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| Path::new(OTHER_DIR).join(source.file_name().unwrap_or_default()));
+    let mut components: Vec<String> = relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    if let (Some(name), Some(extension)) = (components.last_mut(), extension) {
+        let stem = Path::new(name.as_str())
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        *name = format!("{}.{}", stem, extension);
+    }
+    components
+        .iter()
+        .map(|component| {
+            naming::truncate_file_name(&naming::sanitize_component(component), MAX_NAME_BYTES)
+        })
+        .collect()
+}
+
+/// `components` joined with `/`, renamed to `name (2)` etc. while another
+/// file takes the name; FAT32 ignores case, so names are compared without.
+fn unique(components: Vec<String>, taken: &mut HashSet<String>) -> String {
+    let joined = components.join("/");
+    if taken.insert(joined.to_lowercase()) {
+        return joined;
+    }
+    let (dir, name) = components.split_at(components.len() - 1);
+    let name = Path::new(&name[0]);
+    let stem = name
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = name
+        .extension()
+        .map(|ext| ext.to_string_lossy().into_owned());
+    for n in 2.. {
+        let candidate = match &extension {
+            Some(extension) => format!("{} ({}).{}", stem, n, extension),
+            None => format!("{} ({})", stem, n),
+        };
+        let mut path = dir.to_vec();
+        path.push(naming::truncate_file_name(&candidate, MAX_NAME_BYTES));
+        let joined = path.join("/");
+        if taken.insert(joined.to_lowercase()) {
+            return joined;
+        }
+    }
+    unreachable!()
+}
+
+/// Works out the device file of every selected track, each source once.
+fn plan(
+    app: &AppHandle,
+    settings: &DeviceSyncSettings,
+    profile: Option<&TranscodeProfile>,
+) -> (Vec<Item>, HashMap<PathBuf, String>) {
+    let mut sources: Vec<PathBuf> = Vec::new();
+    for id in &settings.playlists {
+        if let Some((_, files)) = playlists::resolved(app, id) {
+            sources.extend(files.into_iter().flatten());
+        }
+    }
+    for album in &settings.albums {
+        sources.extend(album_tracks(app, album));
+    }
+
+    let root = crate::desktop::download_dir(app);
+    let mut seen = HashSet::new();
+    let mut taken = HashSet::new();
+    let mut items = Vec::new();
+    let mut targets = HashMap::new();
+    for source in sources {
+        if !seen.insert(source.clone()) {
+            continue;
+        }
+        let Ok(metadata) = fs::metadata(&source) else {
+            continue;
+        };
+        let profile = profile.filter(|profile| transcoding::converts(profile, &source));
+        let extension = profile.map(|profile| profile.codec.extension());
+        let target = unique(target_path(root.as_deref(), &source, extension), &mut taken);
+        targets.insert(source.clone(), target.clone());
+        items.push(Item {
+            entry: ManifestEntry {
+                source: source.clone(),
+                size: metadata.len(),
+                modified: modified(&metadata),
+                profile: profile.map(|profile| profile.id.clone()),
+            },
+            source,
+            target,
+            profile: profile.cloned(),
+        });
+    }
+    (items, targets)
+}
+
+// ---------------------------------------------------------------------------
+// Copying
+// ---------------------------------------------------------------------------
+
+/// `relative` below `target`, or `None` when it would leave it. The
+/// manifest lives on the device, so its keys aren't trusted.
+fn device_path(target: &Path, relative: &str) -> Option<PathBuf> {
+    let mut path = target.to_path_buf();
+    for part in relative.split('/') {
+        let mut components = Path::new(part).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(name)), None) => path.push(name),
+            _ => return None,
+        }
+    }
+    path.starts_with(target).then_some(path)
+}
+
+fn read_manifest(target: &Path) -> Manifest {
+    crate::store::read_json(&target.join(MANIFEST_FILE)).unwrap_or_default()
+}
+
+/// Copies or converts one file through a temporary name, so an unplugged
+/// device never holds a truncated track under the real name.
+fn put(app: &AppHandle, item: &Item, output: &Path) -> Result<(), String> {
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let name = output
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or("Invalid target path")?;
+    let (stem, extension) = name.rsplit_once('.').unwrap_or((&name, ""));
+    // Keeps the real extension so the tag library recognises the file.
+    let temp = output.with_file_name(format!("{}.syncing.{}", stem, extension));
+
+    let result = match &item.profile {
+        Some(profile) => transcoding::convert(app, &item.source, &temp, profile),
+        None if item.entry.size > FAT32_MAX_FILE_BYTES => {
+            Err("Larger than the 4 GB FAT32 limit".into())
+        }
+        None => fs::copy(&item.source, &temp)
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+    };
+    let result = result.and_then(|_| match fs::metadata(&temp) {
+        Ok(metadata) if metadata.len() > FAT32_MAX_FILE_BYTES => {
+            Err("Larger than the 4 GB FAT32 limit".into())
+        }
+        _ => fs::rename(&temp, output).map_err(|e| e.to_string()),
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Removes `file` and then every folder above it that is left empty, up to
+/// `target`.
+fn remove(target: &Path, file: &Path) -> bool {
+    if !file.starts_with(target) {
+        return false;
+    }
+    if fs::remove_file(file).is_err() && file.exists() {
+        return false;
+    }
+    let mut dir = file.parent();
+    while let Some(path) = dir {
+        if path == target || !path.starts_with(target) || fs::remove_dir(path).is_err() {
+            break;
+        }
+        dir = path.parent();
+    }
+    true
+}
+
+/// Writes an M3U8 per selected playlist to the device root, with paths
+/// relative to it. Unchanged files are left alone.
+fn write_playlists(
+    app: &AppHandle,
+    settings: &DeviceSyncSettings,
+    target: &Path,
+    targets: &HashMap<PathBuf, String>,
+    taken: &mut HashSet<String>,
+) -> Vec<(String, PathBuf)> {
+    let mut written = Vec::new();
+    for id in &settings.playlists {
+        let Some((playlist, files)) = playlists::resolved(app, id) else {
+            continue;
+        };
+        let entries: Vec<(&playlists::PlaylistTrack, PathBuf)> = playlist
+            .tracks
+            .iter()
+            .zip(&files)
+            .filter_map(|(track, file)| {
+                let relative = targets.get(file.as_ref()?)?;
+                Some((track, PathBuf::from_iter(relative.split('/'))))
+            })
+            .collect();
+        let name = naming::truncate_file_name(
+            &naming::sanitize_component(&format!("{}.m3u8", playlist.name)),
+            MAX_NAME_BYTES,
+        );
+        let relative = unique(vec![name], taken);
+        let Some(path) = device_path(target, &relative) else {
+            continue;
+        };
+        let content = playlists::m3u8(&playlist, &entries);
+        if fs::read(&path).ok().as_deref() != Some(content.as_bytes())
+            && crate::store::write_atomic(&path, content.as_bytes()).is_err()
+        {
+            continue;
+        }
+        written.push((relative, path));
+    }
+    written
+}
+
+fn sync(app: &AppHandle, settings: &DeviceSyncSettings) -> Result<DeviceSyncReport, String> {
+    let target = settings.target.clone().ok_or("No device folder selected")?;
+    if !target.is_dir() {
+        return Err(format!("{} is not connected", target.display()));
+    }
+    // Resolved, so links and `..` can't hide an overlap.
+    let target = fs::canonicalize(&target).map_err(|e| e.to_string())?;
+    if let Some(root) = crate::desktop::download_dir(app) {
+        let root = fs::canonicalize(&root).unwrap_or(root);
+        if target.starts_with(&root) || root.starts_with(&target) {
+            return Err("The device folder can't overlap the download folder".into());
+        }
+    }
+    let profile = match &settings.profile {
+        Some(id) => Some(transcoding::profile(app, id).ok_or("Unknown profile")?),
+        None => None,
+    };
+
+    let (items, targets) = plan(app, settings, profile.as_ref());
+    let old = read_manifest(&target);
+    let pending: Vec<&Item> = items
+        .iter()
+        .filter(|item| {
+            old.files.get(&item.target) != Some(&item.entry)
+                || !device_path(&target, &item.target).is_some_and(|path| path.is_file())
+        })
+        .collect();
+    // Converted files come out smaller, so this errs on the safe side.
+    let needed: u64 = pending.iter().map(|item| item.entry.size).sum();
+    if let Ok(free) = fs4::available_space(&target) {
+        if needed > free {
+            return Err(format!(
+                "The device needs {} MB free but has {} MB",
+                needed / (1024 * 1024),
+                free / (1024 * 1024)
+            ));
+        }
+    }
+
+    let state = app.state::<DeviceSyncState>();
+    let mut report = DeviceSyncReport {
+        target: target.clone(),
+        unchanged: items.len() - pending.len(),
+        ..Default::default()
+    };
+    let mut manifest = Manifest::default();
+    for item in &items {
+        if old.files.get(&item.target) == Some(&item.entry) {
+            manifest
+                .files
+                .insert(item.target.clone(), item.entry.clone());
+        }
+    }
+    for (done, item) in pending.iter().enumerate() {
+        if state.cancel.load(Ordering::SeqCst) {
+            report.cancelled = true;
+            break;
+        }
+        let _ = app.emit(
+            "device-sync-progress",
+            ProgressPayload {
+                done,
+                total: pending.len(),
+                file: &item.target,
+            },
+        );
+        let output = device_path(&target, &item.target).ok_or("Invalid target path".to_string());
+        match output.and_then(|output| put(app, item, &output)) {
+            Ok(()) => {
+                if item.profile.is_some() {
+                    report.transcoded += 1;
+                } else {
+                    report.copied += 1;
+                }
+                manifest
+                    .files
+                    .insert(item.target.clone(), item.entry.clone());
+            }
+            Err(e) => {
+                manifest.files.remove(&item.target);
+                report
+                    .failed
+                    .push(format!("{}: {}", item.source.display(), e))
+            }
+        }
+    }
+
+    let current: HashSet<&String> = targets.values().collect();
+    let mut stale: Vec<String> = old
+        .files
+        .keys()
+        .filter(|path| !current.contains(path))
+        .cloned()
+        .collect();
+    if report.cancelled {
+        // Files still listed in the old manifest stay owned by the sync, so
+        // the next complete run can clean them up.
+        for path in stale.drain(..) {
+            manifest
+                .files
+                .insert(path.clone(), old.files[&path].clone());
+        }
+        manifest.playlists = old.playlists.clone();
+    } else {
+        let mut taken: HashSet<String> = targets.values().map(|path| path.to_lowercase()).collect();
+        let written = if settings.write_playlists {
+            write_playlists(app, settings, &target, &targets, &mut taken)
+        } else {
+            Vec::new()
+        };
+        for relative in &old.playlists {
+            if !written.iter().any(|(path, _)| path == relative) {
+                stale.push(relative.clone());
+            }
+        }
+        manifest.playlists = written.iter().map(|(path, _)| path.clone()).collect();
+        report.playlists = written.into_iter().map(|(_, path)| path).collect();
+    }
+    for path in stale {
+        let Some(file) = device_path(&target, &path) else {
+            // Not something the sync could have written; forget it.
+            continue;
+        };
+        if remove(&target, &file) {
+            report.removed += 1;
+        } else if let Some(entry) = old.files.get(&path) {
+            manifest.files.insert(path, entry.clone());
+        } else {
+            manifest.playlists.push(path);
+        }
+    }
+
+    crate::store::write_json(&target.join(MANIFEST_FILE), &manifest)?;
+    Ok(report)
+}
+
+// ---------------------------------------------------------------------------
+// Tauri commands
+// ---------------------------------------------------------------------------
+
+#[tauri::command]
+pub fn get_device_sync_settings(
+    state: tauri::State<DeviceSyncState>,
+) -> Result<DeviceSyncSettings, String> {
+    let settings = state.settings.lock().map_err(|_| "Failed to lock mutex")?;
+    Ok(settings.clone())
+}
+
+#[tauri::command]
+pub fn set_device_sync_settings(
+    app: AppHandle,
+    state: tauri::State<DeviceSyncState>,
+    mut settings: DeviceSyncSettings,
+) -> Result<(), String> {
+    let mut current = state.settings.lock().map_err(|_| "Failed to lock mutex")?;
+    // The device folder is only set through the native picker.
+    settings.target = current.target.clone();
+    crate::store::save_config(&app, SETTINGS_FILE, &settings)?;
+    *current = settings;
+    Ok(())
+}
+
+/// Asks the user for the device folder and stores it.
+#[tauri::command]
+pub async fn pick_device_folder(app: AppHandle) -> Result<PathBuf, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let path = app
+            .dialog()
+            .file()
+            .set_title("Choose Device Folder")
+            .blocking_pick_folder()
+            .ok_or("No folder selected")?
+            .into_path()
+            .map_err(|e| e.to_string())?;
+        let state = app.state::<DeviceSyncState>();
+        let mut settings = state.settings.lock().map_err(|_| "Failed to lock mutex")?;
+        settings.target = Some(path.clone());
+        crate::store::save_config(&app, SETTINGS_FILE, &*settings)?;
+        Ok(path)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Mirrors the selected playlists and albums into the device folder. Files
+/// the last sync put there and that are no longer selected are deleted;
+/// anything else on the device is left alone.
+#[tauri::command]
+pub async fn sync_to_device(app: AppHandle) -> Result<DeviceSyncReport, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<DeviceSyncState>();
+        if state.running.swap(true, Ordering::SeqCst) {
+            return Err("A device sync is already running".into());
+        }
+        let _guard = RunningGuard(&state.running);
+        state.cancel.store(false, Ordering::SeqCst);
+        let settings = state
+            .settings
+            .lock()
+            .map_err(|_| "Failed to lock mutex")?
+            .clone();
+        let report = sync(&app, &settings)?;
+        let _ = app.emit("device-sync-finished", &report);
+        Ok(report)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Stops a running sync after the file in progress.
+#[tauri::command]
+pub fn cancel_device_sync(state: tauri::State<DeviceSyncState>) {
+    state.cancel.store(true, Ordering::SeqCst);
+}
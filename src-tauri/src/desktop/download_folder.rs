@@ -0,0 +1,274 @@
+use crate::desktop::{library, notifications, save_download_path, DownloadState};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{AppHandle, Emitter, Manager};
+use tauri_plugin_dialog::DialogExt;
+use tauri_plugin_opener::OpenerExt;
+
+const PROBE_FILE: &str = ".monochrome-write-test";
+const MOUNT_FILE: &str = "download_mount.json";
+
+/// Below this much free space the folder is reported as low on space.
+const LOW_SPACE_BYTES: u64 = 1024 * 1024 * 1024;
+
+// ---------------------------------------------------------------------------
+// State
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadFolder {
+    /// Where downloads are saved right now.
+    pub path: Option<PathBuf>,
+    /// The folder picked by the user, if any.
+    pub configured: Option<PathBuf>,
+    /// The configured folder is missing or unmounted and `path` is the
+    /// system Downloads folder instead.
+    pub fallback: bool,
+    pub writable: bool,
+    pub free_bytes: Option<u64>,
+    pub total_bytes: Option<u64>,
+    pub low_space: bool,
+}
+
+#[derive(Default)]
+pub struct DownloadFolderState {
+    /// Missing folder the user was last warned about, so the warning is
+    /// shown once rather than for every download.
+    warned: Mutex<Option<PathBuf>>,
+    /// Mount point of the configured folder when it is not on the root
+    /// filesystem. An unmounted drive often leaves an empty folder behind,
+    /// so existing alone doesn't mean the folder is there.
+    mount: Mutex<Option<PathBuf>>,
+}
+
+pub fn load(app: &AppHandle) {
+    let configured = app.state::<DownloadState>().path.lock().unwrap().clone();
+    let mount = crate::store::config_file(app, MOUNT_FILE)
+        .and_then(|file| crate::store::read_json(&file))
+        // Folders set before mounts were remembered.
+        .or_else(|| configured.as_deref().and_then(mount_point));
+    *app.state::<DownloadFolderState>().mount.lock().unwrap() = mount;
+}
+
+// ---------------------------------------------------------------------------
+// Checks
+// ---------------------------------------------------------------------------
+
+/// Creates and removes a file in `dir`; permission bits alone miss
+/// read-only mounts and ACLs.
+fn is_writable(dir: &Path) -> bool {
+    let probe = dir.join(PROBE_FILE);
+    let ok = fs::write(&probe, b"").is_ok();
+    let _ = fs::remove_file(&probe);
+    ok
+}
+
+/// Topmost folder above `dir` on the same device, unless that is the root
+/// of the filesystem tree.
+#[cfg(unix)]
+fn mount_point(dir: &Path) -> Option<PathBuf> {
+    use std::os::unix::fs::MetadataExt;
+    let dir = fs::canonicalize(dir).ok()?;
+    let device = fs::metadata(&dir).ok()?.dev();
+    let mut mount = dir.as_path();
+    while let Some(parent) = mount.parent() {
+        if fs::metadata(parent).ok()?.dev() != device {
+            return Some(mount.to_path_buf());
+        }
+        mount = parent;
+    }
+    None
+}
+
+#[cfg(not(unix))]
+fn mount_point(_dir: &Path) -> Option<PathBuf> {
+    None
+}
+
+/// Whether `mount` is still a mount point rather than the empty folder it
+/// was mounted on.
+#[cfg(unix)]
+fn is_mounted(mount: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    let device = |path: &Path| fs::metadata(path).map(|meta| meta.dev()).ok();
+    match (device(mount), mount.parent().and_then(device)) {
+        (Some(mount), Some(parent)) => mount != parent,
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_mounted(mount: &Path) -> bool {
+    mount.is_dir()
+}
+
+/// Whether the configured folder is there and on the drive it was set on.
+fn is_available(app: &AppHandle, configured: &Path) -> bool {
+    let mount = app
+        .state::<DownloadFolderState>()
+        .mount
+        .lock()
+        .unwrap()
+        .clone();
+    configured.is_dir() && mount.is_none_or(|mount| is_mounted(&mount))
+}
+
+/// The configured folder when it is usable, otherwise the system Downloads
+/// folder. Warns once per missing folder.
+pub fn resolve(app: &AppHandle) -> Option<PathBuf> {
+    let configured = app.state::<DownloadState>().path.lock().unwrap().clone();
+    let system = || app.path().download_dir().ok();
+    let Some(configured) = configured else {
+        return system();
+    };
+    if is_available(app, &configured) {
+        *app.state::<DownloadFolderState>().warned.lock().unwrap() = None;
+        return Some(configured);
+    }
+
+    let state = app.state::<DownloadFolderState>();
+    let mut warned = state.warned.lock().unwrap();
+    if warned.as_ref() != Some(&configured) {
+        *warned = Some(configured.clone());
+        let _ = app.emit("download-dir-unavailable", &configured);
+        notifications::error(
+            app,
+            "Download Folder Unavailable",
+            &format!(
+                "{} is missing or not mounted. Saving to the system Downloads folder instead.",
+                configured.display()
+            ),
+        );
+    }
+    system()
+}
+
+/// Whether `path` is inside the download folder. Resolved, so `..` can't
+/// point outside it.
+pub fn contains(app: &AppHandle, path: &Path) -> bool {
+    resolve(app)
+        .and_then(|dir| {
+            Some(
+                fs::canonicalize(path)
+                    .ok()?
+                    .starts_with(fs::canonicalize(dir).ok()?),
+            )
+        })
+        .unwrap_or(false)
+}
+
+fn describe(app: &AppHandle) -> DownloadFolder {
+    let configured = app.state::<DownloadState>().path.lock().unwrap().clone();
+    let path = resolve(app);
+    let fallback = configured.is_some() && path != configured;
+    let (writable, free_bytes, total_bytes) = match &path {
+        Some(path) => (
+            is_writable(path),
+            fs4::available_space(path).ok(),
+            fs4::total_space(path).ok(),
+        ),
+        None => (false, None, None),
+    };
+    DownloadFolder {
+        path,
+        configured,
+        fallback,
+        writable,
+        free_bytes,
+        total_bytes,
+        low_space: free_bytes.is_some_and(|free| free < LOW_SPACE_BYTES),
+    }
+}
+
+/// Validates and stores a new download folder; `None` goes back to the
+/// system Downloads folder. Only called for folders the user picked
+/// natively, never with a path from the webview.
+pub fn set(app: &AppHandle, path: Option<PathBuf>) -> Result<DownloadFolder, String> {
+    if let Some(path) = &path {
+        if !path.is_absolute() {
+            return Err("The download folder must be an absolute path".into());
+        }
+        fs::create_dir_all(path).map_err(|e| format!("Failed to create folder: {}", e))?;
+        if !is_writable(path) {
+            return Err(format!("{} is not writable", path.display()));
+        }
+    }
+
+    match &path {
+        Some(path) => save_download_path(app, path),
+        None => {
+            if let Some(file) = crate::store::config_file(app, "download_path.txt") {
+                let _ = fs::remove_file(file);
+            }
+        }
+    }
+    let mount = path.as_deref().and_then(mount_point);
+    if let Some(file) = crate::store::config_file(app, MOUNT_FILE) {
+        let _ = crate::store::write_json(&file, &mount);
+    }
+    *app.state::<DownloadState>().path.lock().unwrap() = path;
+    let state = app.state::<DownloadFolderState>();
+    *state.warned.lock().unwrap() = None;
+    *state.mount.lock().unwrap() = mount;
+    library::watch(app);
+
+    let folder = describe(app);
+    let _ = app.emit("download-dir-changed", &folder);
+    Ok(folder)
+}
+
+// ---------------------------------------------------------------------------
+// Tauri commands
+// ---------------------------------------------------------------------------
+
+#[tauri::command]
+pub fn get_download_dir(app: AppHandle) -> DownloadFolder {
+    describe(&app)
+}
+
+/// Goes back to the system Downloads folder.
+#[tauri::command]
+pub fn reset_download_dir(app: AppHandle) -> Result<DownloadFolder, String> {
+    set(&app, None)
+}
+
+/// Asks the user for a folder and makes it the download folder.
+#[tauri::command]
+pub async fn pick_download_dir(app: AppHandle) -> Result<DownloadFolder, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let mut dialog = app.dialog().file().set_title("Set Download Folder");
+        if let Some(current) = resolve(&app) {
+            dialog = dialog.set_directory(current);
+        }
+        let path = dialog
+            .blocking_pick_folder()
+            .ok_or("No folder selected")?
+            .into_path()
+            .map_err(|e| e.to_string())?;
+        set(&app, Some(path))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Shows `path`, or the download folder, selected in the system file
+/// manager. Only library files and whatever is inside the download folder
+/// can be shown.
+#[tauri::command]
+pub fn reveal_in_file_manager(app: AppHandle, path: Option<String>) -> Result<(), String> {
+    let dir = resolve(&app).ok_or("No download folder")?;
+    let path = path.map(PathBuf::from).unwrap_or(dir);
+    // Checked first, so nothing is revealed about files elsewhere.
+    if !contains(&app, &path) && !library::contains(&app, &path) {
+        return Err("Only downloads and library files can be shown".into());
+    }
+    if !path.exists() {
+        return Err(format!("{} does not exist", path.display()));
+    }
+    app.opener()
+        .reveal_item_in_dir(&path)
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,308 @@
+use crate::desktop::notifications;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Emitter, Manager};
+
+const HISTORY_FILE: &str = "download_history.json";
+
+/// Oldest records are dropped once the history grows past this.
+const MAX_RECORDS: usize = 2000;
+
+// ---------------------------------------------------------------------------
+// State
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DownloadStatus {
+    InProgress,
+    Completed,
+    Failed,
+    Cancelled,
+    /// Not saved because an existing file was kept instead.
+    Skipped,
+    /// Failed verification and was moved to the quarantine folder.
+    Quarantined,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadRecord {
+    pub id: String,
+    pub url: String,
+    pub path: Option<PathBuf>,
+    pub status: DownloadStatus,
+    pub started_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Default)]
+pub struct DownloadHistoryState {
+    records: Mutex<Vec<DownloadRecord>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DownloadEventPayload<'a> {
+    id: &'a str,
+    url: &'a str,
+    path: Option<&'a PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+}
+
+pub fn load(app: &AppHandle) {
+    let mut records: Vec<DownloadRecord> = crate::store::data_file(app, HISTORY_FILE)
+        .and_then(|path| crate::store::read_json(&path))
+        .unwrap_or_default();
+    // Anything still in progress was interrupted by the last shutdown.
+    for record in records.iter_mut() {
+        if record.status == DownloadStatus::InProgress {
+            record.status = DownloadStatus::Failed;
+            record.error = Some("Interrupted".into());
+        }
+    }
+    let state = app.state::<DownloadHistoryState>();
+    *state.records.lock().unwrap() = records;
+}
+
+// ---------------------------------------------------------------------------
+// Helpers
+// ---------------------------------------------------------------------------
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn save(app: &AppHandle, records: &[DownloadRecord]) {
+    if let Some(path) = crate::store::data_file(app, HISTORY_FILE) {
+        let _ = crate::store::write_json(&path, &records);
+    }
+}
+
+fn emit(app: &AppHandle, event: &str, record: &DownloadRecord) {
+    let _ = app.emit(
+        event,
+        DownloadEventPayload {
+            id: &record.id,
+            url: &record.url,
+            path: record.path.as_ref(),
+            error: record.error.as_deref(),
+        },
+    );
+}
+
+/// Applies `update` to the record with `id` and returns a copy of the result.
+fn update<F>(app: &AppHandle, id: &str, update: F) -> Option<DownloadRecord>
+where
+    F: FnOnce(&mut DownloadRecord),
+{
+    let state = app.state::<DownloadHistoryState>();
+    let mut records = state.records.lock().unwrap();
+    let record = records.iter_mut().find(|record| record.id == id)?;
+    update(record);
+    let record = record.clone();
+    save(app, &records);
+    Some(record)
+}
+
+/// Where the latest successful download of `url` ended up: the saved file,
+/// or the existing one it was skipped for.
+pub fn path_for_url(app: &AppHandle, url: &str) -> Option<PathBuf> {
+    let state = app.state::<DownloadHistoryState>();
+    let records = state.records.lock().unwrap();
+    records
+        .iter()
+        .rev()
+        .filter(|record| record.url == url)
+        .filter(|record| {
+            matches!(
+                record.status,
+                DownloadStatus::Completed | DownloadStatus::Skipped
+            )
+        })
+        .find_map(|record| record.path.clone())
+}
+
+/// Where the latest completed download of `url` saved its file. Unlike
+/// [`path_for_url`], a file that was already there and skipped for is not
+/// counted.
+pub fn downloaded_path(app: &AppHandle, url: &str) -> Option<PathBuf> {
+    let state = app.state::<DownloadHistoryState>();
+    let records = state.records.lock().unwrap();
+    records
+        .iter()
+        .rev()
+        .filter(|record| record.url == url && record.status == DownloadStatus::Completed)
+        .find_map(|record| record.path.clone())
+}
+
+/// The files of all downloads, as [`downloaded_path`] finds them for each
+/// URL.
+pub fn downloaded_paths(app: &AppHandle) -> HashSet<PathBuf> {
+    let state = app.state::<DownloadHistoryState>();
+    let records = state.records.lock().unwrap();
+    let mut urls = HashSet::new();
+    records
+        .iter()
+        .rev()
+        .filter(|record| record.status == DownloadStatus::Completed && record.path.is_some())
+        .filter(|record| urls.insert(record.url.as_str()))
+        .filter_map(|record| record.path.clone())
+        .collect()
+}
+
+// ---------------------------------------------------------------------------
+// Lifecycle
+// ---------------------------------------------------------------------------
+
+/// Registers a new download and emits `download-started`. Returns its ID.
+pub fn start(app: &AppHandle, url: &str, path: Option<PathBuf>) -> String {
+    let id = uuid::Uuid::new_v4().to_string();
+    start_with_id(app, &id, url, path);
+    id
+}
+
+/// Like [`start`] for callers that own the ID. Starting an ID that is already
+/// recorded (a resumed download) reopens that record instead of adding one,
+/// unless the earlier attempt was quarantined; that record is kept under a
+/// new ID.
+pub fn start_with_id(app: &AppHandle, id: &str, url: &str, path: Option<PathBuf>) {
+    let record = DownloadRecord {
+        id: id.to_string(),
+        url: url.to_string(),
+        path,
+        status: DownloadStatus::InProgress,
+        started_at: now(),
+        finished_at: None,
+        error: None,
+    };
+    emit(app, "download-started", &record);
+
+    let state = app.state::<DownloadHistoryState>();
+    let mut records = state.records.lock().unwrap();
+    for existing in records.iter_mut() {
+        if existing.id == id && existing.status == DownloadStatus::Quarantined {
+            existing.id = uuid::Uuid::new_v4().to_string();
+        }
+    }
+    records.retain(|existing| existing.id != id);
+    records.push(record);
+    if records.len() > MAX_RECORDS {
+        let excess = records.len() - MAX_RECORDS;
+        records.drain(..excess);
+    }
+    save(app, &records);
+}
+
+/// Marks a download as completed, emits `download-finished` and shows the
+/// completion notification. `path` overrides the destination recorded at start.
+pub fn finish(app: &AppHandle, id: &str, path: Option<PathBuf>) {
+    let record = update(app, id, |record| {
+        record.status = DownloadStatus::Completed;
+        record.finished_at = Some(now());
+        if path.is_some() {
+            record.path = path;
+        }
+    });
+    if let Some(record) = record {
+        emit(app, "download-finished", &record);
+        notifications::download_complete(app, record.path.as_deref());
+    }
+}
+
+pub fn fail(app: &AppHandle, id: &str, error: &str) {
+    let record = update(app, id, |record| {
+        record.status = DownloadStatus::Failed;
+        record.finished_at = Some(now());
+        record.error = Some(error.to_string());
+    });
+    if let Some(record) = record {
+        emit(app, "download-failed", &record);
+        let name = record
+            .path
+            .as_ref()
+            .and_then(|p| p.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| record.url.clone());
+        notifications::error(app, "Download Failed", &format!("{}\n{}", name, error));
+    }
+}
+
+pub fn cancel(app: &AppHandle, id: &str) {
+    let record = update(app, id, |record| {
+        record.status = DownloadStatus::Cancelled;
+        record.finished_at = Some(now());
+    });
+    if let Some(record) = record {
+        emit(app, "download-cancelled", &record);
+    }
+}
+
+/// Marks a download as corrupt, emits `download-quarantined` and shows an
+/// error notification. `path` is the quarantined copy, if it could be kept.
+pub fn quarantine(app: &AppHandle, id: &str, path: Option<PathBuf>, reason: &str) {
+    let record = update(app, id, |record| {
+        record.status = DownloadStatus::Quarantined;
+        record.finished_at = Some(now());
+        record.path = path;
+        record.error = Some(reason.to_string());
+    });
+    if let Some(record) = record {
+        emit(app, "download-quarantined", &record);
+        notifications::error(app, "Download Corrupt", reason);
+    }
+}
+
+/// Marks a download as skipped in favour of the file already at `existing`
+/// and emits `download-skipped`.
+pub fn skip(app: &AppHandle, id: &str, existing: PathBuf) {
+    let record = update(app, id, |record| {
+        record.status = DownloadStatus::Skipped;
+        record.finished_at = Some(now());
+        record.path = Some(existing);
+    });
+    if let Some(record) = record {
+        emit(app, "download-skipped", &record);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tauri commands
+// ---------------------------------------------------------------------------
+
+/// Returns the most recent downloads, newest first.
+#[tauri::command]
+pub fn get_download_history(
+    state: tauri::State<DownloadHistoryState>,
+    limit: Option<usize>,
+) -> Result<Vec<DownloadRecord>, String> {
+    let records = state.records.lock().map_err(|_| "Failed to lock mutex")?;
+    Ok(records
+        .iter()
+        .rev()
+        .take(limit.unwrap_or(MAX_RECORDS))
+        .cloned()
+        .collect())
+}
+
+/// Removes finished and failed records; downloads still running are kept.
+#[tauri::command]
+pub fn clear_download_history(
+    app: AppHandle,
+    state: tauri::State<DownloadHistoryState>,
+) -> Result<(), String> {
+    let mut records = state.records.lock().map_err(|_| "Failed to lock mutex")?;
+    records.retain(|record| record.status == DownloadStatus::InProgress);
+    save(&app, &records);
+    Ok(())
+}
@@ -0,0 +1,720 @@
+use crate::collision::{self, CollisionPolicy, Resolution};
+use crate::desktop::{
+    download_history, integrity, loudness, naming, storage, tagging, transcoding,
+};
+use reqwest::header::{CONTENT_RANGE, RANGE};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Emitter, Manager};
+
+const SETTINGS_FILE: &str = "download_manager_settings.json";
+const QUEUE_FILE: &str = "download_queue.json";
+
+/// Minimum delay between two `download-progress` events for the same job.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A connection that delivers nothing for this long is given up, so a stalled
+/// transfer doesn't hold its slot forever.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Values of a running transfer's control flag.
+const CONTROL_RUN: u8 = 0;
+const CONTROL_PAUSE: u8 = 1;
+const CONTROL_CANCEL: u8 = 2;
+
+// ---------------------------------------------------------------------------
+// Settings
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DownloadManagerSettings {
+    pub max_concurrent: usize,
+}
+
+impl Default for DownloadManagerSettings {
+    fn default() -> Self {
+        Self { max_concurrent: 3 }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// State
+// ---------------------------------------------------------------------------
+
+/// Track details the web app sends along with a download request.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TrackMetadata {
+    pub title: Option<String>,
+    pub artists: Vec<String>,
+    pub album_artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<u32>,
+    pub track_total: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub disc_total: Option<u32>,
+    pub isrc: Option<String>,
+    /// `YYYY`, `YYYY-MM` or `YYYY-MM-DD`.
+    pub release_date: Option<String>,
+    pub cover_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum JobStatus {
+    Queued,
+    Downloading,
+    Paused,
+    Completed,
+    Failed,
+    Cancelled,
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadJob {
+    pub id: String,
+    pub url: String,
+    #[serde(default)]
+    pub filename: Option<String>,
+    #[serde(default)]
+    pub metadata: Option<TrackMetadata>,
+    /// Final location, resolved when the job first starts.
+    #[serde(default)]
+    pub destination: Option<PathBuf>,
+    pub status: JobStatus,
+    #[serde(default)]
+    pub bytes_downloaded: u64,
+    #[serde(default)]
+    pub total_bytes: Option<u64>,
+    #[serde(default)]
+    pub error: Option<String>,
+    /// Times the file was downloaded again after failing verification.
+    #[serde(default)]
+    pub retries: u32,
+    pub created_at: u64,
+}
+
+pub struct DownloadManagerState {
+    settings: Mutex<DownloadManagerSettings>,
+    /// Unfinished jobs in queue order. Completed, cancelled and skipped jobs
+    /// are dropped; their outcome lives on in the download history.
+    jobs: Mutex<Vec<DownloadJob>>,
+    /// Control flags of running transfers, keyed by job ID.
+    controls: Mutex<HashMap<String, Arc<AtomicU8>>>,
+    client: reqwest::Client,
+}
+
+impl Default for DownloadManagerState {
+    fn default() -> Self {
+        Self {
+            settings: Default::default(),
+            jobs: Default::default(),
+            controls: Default::default(),
+            client: reqwest::Client::builder()
+                .read_timeout(READ_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProgressPayload<'a> {
+    id: &'a str,
+    bytes_downloaded: u64,
+    total_bytes: Option<u64>,
+    bytes_per_sec: u64,
+    eta_secs: Option<u64>,
+}
+
+enum Outcome {
+    Completed(PathBuf),
+    Paused,
+    Cancelled,
+    /// The file already existed and was kept; holds its path.
+    Skipped(PathBuf),
+    /// The downloaded file failed verification.
+    Corrupt {
+        file: PathBuf,
+        reason: String,
+    },
+}
+
+pub fn load(app: &AppHandle) {
+    let state = app.state::<DownloadManagerState>();
+    *state.settings.lock().unwrap() = crate::store::load_config(app, SETTINGS_FILE);
+
+    let mut jobs: Vec<DownloadJob> = crate::store::data_file(app, QUEUE_FILE)
+        .and_then(|path| crate::store::read_json(&path))
+        .unwrap_or_default();
+    // Transfers cut off by the last shutdown pick up from their partial file.
+    for job in jobs.iter_mut() {
+        if job.status == JobStatus::Downloading {
+            job.status = JobStatus::Queued;
+        }
+    }
+    *state.jobs.lock().unwrap() = jobs;
+
+    schedule(app);
+}
+
+// ---------------------------------------------------------------------------
+// Helpers
+// ---------------------------------------------------------------------------
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn save_queue(app: &AppHandle, jobs: &[DownloadJob]) {
+    if let Some(path) = crate::store::data_file(app, QUEUE_FILE) {
+        let _ = crate::store::write_json(&path, &jobs);
+    }
+}
+
+/// Applies `update` to the job with `id`, persists the queue and emits
+/// `download-job-updated`. Jobs that end up completed, cancelled or skipped
+/// are removed from the queue.
+fn update_job<F>(app: &AppHandle, id: &str, update: F) -> Option<DownloadJob>
+where
+    F: FnOnce(&mut DownloadJob),
+{
+    let state = app.state::<DownloadManagerState>();
+    let mut jobs = state.jobs.lock().unwrap();
+    let job = jobs.iter_mut().find(|job| job.id == id)?;
+    update(job);
+    let job = job.clone();
+    if matches!(
+        job.status,
+        JobStatus::Completed | JobStatus::Cancelled | JobStatus::Skipped
+    ) {
+        jobs.retain(|existing| existing.id != id);
+    }
+    save_queue(app, &jobs);
+    drop(jobs);
+
+    let _ = app.emit("download-job-updated", &job);
+    Some(job)
+}
+
+fn part_path(destination: &Path) -> PathBuf {
+    let mut part = destination.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+/// Parses `bytes <start>-<end>/<total>` or `bytes */<total>` into the start
+/// and the total, either of which may be unknown.
+fn content_range(response: &reqwest::Response) -> Option<(Option<u64>, Option<u64>)> {
+    let value = response.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    let (range, total) = value.strip_prefix("bytes ")?.trim().split_once('/')?;
+    let start = match range {
+        "*" => None,
+        range => Some(range.split_once('-')?.0.parse().ok()?),
+    };
+    Some((start, total.parse().ok()))
+}
+
+fn file_name(job: &DownloadJob) -> String {
+    let from_url = url::Url::parse(&job.url).ok().and_then(|url| {
+        url.path_segments()
+            .and_then(|mut segments| segments.next_back().map(str::to_string))
+    });
+    let name = job
+        .filename
+        .clone()
+        .or(from_url)
+        .unwrap_or_default()
+        .replace(['/', '\\', '\0'], "_");
+    let name = name.trim();
+    if name.is_empty() {
+        "download".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Scheduler
+// ---------------------------------------------------------------------------
+
+/// Starts queued jobs until `max_concurrent` transfers are running.
+fn schedule(app: &AppHandle) {
+    let state = app.state::<DownloadManagerState>();
+    let max_concurrent = state.settings.lock().unwrap().max_concurrent.max(1);
+    let mut jobs = state.jobs.lock().unwrap();
+    let mut controls = state.controls.lock().unwrap();
+
+    let mut running = jobs
+        .iter()
+        .filter(|job| job.status == JobStatus::Downloading)
+        .count();
+    let mut started = Vec::new();
+    for job in jobs.iter_mut() {
+        if running >= max_concurrent {
+            break;
+        }
+        if job.status != JobStatus::Queued {
+            continue;
+        }
+        job.status = JobStatus::Downloading;
+        job.error = None;
+        let control = Arc::new(AtomicU8::new(CONTROL_RUN));
+        controls.insert(job.id.clone(), control.clone());
+        started.push((job.clone(), control));
+        running += 1;
+    }
+    if started.is_empty() {
+        return;
+    }
+    save_queue(app, &jobs);
+    drop(controls);
+    drop(jobs);
+
+    for (job, control) in started {
+        let _ = app.emit("download-job-updated", &job);
+        let app = app.clone();
+        tauri::async_runtime::spawn(async move {
+            run(app, job, control).await;
+        });
+    }
+}
+
+async fn run(app: AppHandle, job: DownloadJob, control: Arc<AtomicU8>) {
+    let outcome = transfer(&app, &job, &control).await;
+    app.state::<DownloadManagerState>()
+        .controls
+        .lock()
+        .unwrap()
+        .remove(&job.id);
+
+    match outcome {
+        Ok(Outcome::Completed(path)) => {
+            tagging::tag_download(&app, &path, job.metadata.as_ref()).await;
+            update_job(&app, &job.id, |job| {
+                job.status = JobStatus::Completed;
+                job.destination = Some(path.clone());
+            });
+            download_history::finish(&app, &job.id, Some(path.clone()));
+            post_process(&app, path);
+        }
+        Ok(Outcome::Paused) => {
+            update_job(&app, &job.id, |job| job.status = JobStatus::Paused);
+        }
+        Ok(Outcome::Skipped(path)) => {
+            update_job(&app, &job.id, |job| {
+                job.status = JobStatus::Skipped;
+                job.destination = Some(path.clone());
+            });
+            download_history::skip(&app, &job.id, path);
+        }
+        Ok(Outcome::Corrupt { file, reason }) => {
+            let retry = integrity::quarantine(&app, &job.id, &file, &reason, job.retries + 1);
+            update_job(&app, &job.id, |job| {
+                job.error = Some(reason.clone());
+                if retry {
+                    job.status = JobStatus::Queued;
+                    job.retries += 1;
+                    job.bytes_downloaded = 0;
+                } else {
+                    job.status = JobStatus::Failed;
+                }
+            });
+        }
+        Ok(Outcome::Cancelled) => {
+            update_job(&app, &job.id, |job| job.status = JobStatus::Cancelled);
+            download_history::cancel(&app, &job.id);
+        }
+        Err(error) => {
+            update_job(&app, &job.id, |job| {
+                job.status = JobStatus::Failed;
+                job.error = Some(error.clone());
+            });
+            download_history::fail(&app, &job.id, &error);
+        }
+    }
+
+    schedule(&app);
+}
+
+/// Runs the loudness scan and the automatic transcode for a finished
+/// download in the background, so the next download doesn't wait on them.
+pub fn post_process(app: &AppHandle, path: PathBuf) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        loudness::after_download(&app, &path).await;
+        transcoding::after_download(&app, &path);
+    });
+}
+
+// ---------------------------------------------------------------------------
+// Transfer
+// ---------------------------------------------------------------------------
+
+/// Downloads into `<destination>.part`, continuing from its current length
+/// with a `Range` request when one exists, and renames it into place once
+/// the body is complete.
+async fn transfer(
+    app: &AppHandle,
+    job: &DownloadJob,
+    control: &AtomicU8,
+) -> Result<Outcome, String> {
+    let destination = match &job.destination {
+        Some(destination) => destination.clone(),
+        None => {
+            let dir = crate::desktop::download_dir(app).ok_or("No download folder available")?;
+            let destination =
+                naming::destination(app, &dir, job.metadata.as_ref(), &file_name(job));
+            update_job(app, &job.id, |job| {
+                job.destination = Some(destination.clone())
+            });
+            destination
+        }
+    };
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    download_history::start_with_id(app, &job.id, &job.url, Some(destination.clone()));
+    if collision::policy(app) == CollisionPolicy::Skip && destination.exists() {
+        return Ok(Outcome::Skipped(destination));
+    }
+
+    let part = part_path(&destination);
+    let mut offset = fs::metadata(&part).map(|meta| meta.len()).unwrap_or(0);
+
+    let client = app.state::<DownloadManagerState>().client.clone();
+    let response = loop {
+        let mut request = client.get(&job.url);
+        if offset > 0 {
+            request = request.header(RANGE, format!("bytes={}-", offset));
+        }
+        let response = request.send().await.map_err(|e| e.to_string())?;
+        if offset == 0 {
+            break response;
+        }
+        let range = content_range(&response);
+        match response.status() {
+            // The partial file holds the whole body, if it is as long as
+            // the server's copy.
+            StatusCode::RANGE_NOT_SATISFIABLE => {
+                if let Some((_, Some(total))) = range {
+                    if total == offset {
+                        return place(app, &part, &destination, Some(total)).await;
+                    }
+                }
+            }
+            StatusCode::PARTIAL_CONTENT => {
+                if range.is_some_and(|(start, _)| start == Some(offset)) {
+                    break response;
+                }
+            }
+            // Servers that ignore `Range` answer 200 with the full body.
+            _ => break response,
+        }
+        // The partial file doesn't fit the server's copy; start over.
+        let _ = fs::remove_file(&part);
+        offset = 0;
+    };
+    let mut response = response.error_for_status().map_err(|e| e.to_string())?;
+
+    let resumed = offset > 0 && response.status() == StatusCode::PARTIAL_CONTENT;
+    let start_offset = if resumed { offset } else { 0 };
+    let total = match content_range(&response) {
+        Some((_, Some(total))) if resumed => Some(total),
+        _ => response.content_length().map(|len| len + start_offset),
+    };
+    storage::reserve(app, response.content_length().unwrap_or(0))?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resumed)
+        .truncate(!resumed)
+        .open(&part)
+        .map_err(|e| e.to_string())?;
+
+    let started = Instant::now();
+    let mut last_progress = started;
+    let mut written = start_offset;
+    loop {
+        match control.load(Ordering::SeqCst) {
+            CONTROL_PAUSE => {
+                let _ = file.flush();
+                return Ok(Outcome::Paused);
+            }
+            CONTROL_CANCEL => {
+                drop(file);
+                let _ = fs::remove_file(&part);
+                return Ok(Outcome::Cancelled);
+            }
+            _ => {}
+        }
+
+        let Some(chunk) = response.chunk().await.map_err(|e| e.to_string())? else {
+            break;
+        };
+        file.write_all(&chunk).map_err(|e| e.to_string())?;
+        written += chunk.len() as u64;
+
+        if last_progress.elapsed() >= PROGRESS_INTERVAL {
+            last_progress = Instant::now();
+            let elapsed = started.elapsed().as_secs_f64().max(0.001);
+            let bytes_per_sec = ((written - start_offset) as f64 / elapsed) as u64;
+            let eta_secs = total
+                .filter(|_| bytes_per_sec > 0)
+                .map(|total| total.saturating_sub(written) / bytes_per_sec);
+            report_progress(app, &job.id, written, total, bytes_per_sec, eta_secs);
+        }
+    }
+    file.flush().map_err(|e| e.to_string())?;
+    drop(file);
+
+    if let Some(total) = total {
+        if written < total {
+            return Err("Connection closed before the download completed".into());
+        }
+    }
+    report_progress(app, &job.id, written, total, 0, Some(0));
+    place(app, &part, &destination, total).await
+}
+
+/// Verifies a finished partial file and moves it into place, applying the
+/// collision policy to whatever appeared at `destination` while it was
+/// downloading. Under "replace if better" the partial file itself serves as
+/// the staging copy, so a corrupt download never replaces a good file.
+async fn place(
+    app: &AppHandle,
+    part: &Path,
+    destination: &Path,
+    expected_size: Option<u64>,
+) -> Result<Outcome, String> {
+    if let Err(reason) = integrity::verify(app, part, expected_size).await {
+        return Ok(Outcome::Corrupt {
+            file: part.to_path_buf(),
+            reason,
+        });
+    }
+    match collision::resolve(collision::policy(app), destination) {
+        Resolution::Write(path) => {
+            fs::rename(part, &path).map_err(|e| e.to_string())?;
+            Ok(Outcome::Completed(path))
+        }
+        Resolution::Skip(existing) => {
+            let _ = fs::remove_file(part);
+            Ok(Outcome::Skipped(existing))
+        }
+        Resolution::Compare {
+            target, existing, ..
+        } => match collision::settle(part, &target, &existing)? {
+            (path, true) => Ok(Outcome::Completed(path)),
+            (path, false) => Ok(Outcome::Skipped(path)),
+        },
+    }
+}
+
+/// Emits `download-progress` and mirrors the counters into the in-memory job
+/// (the queue file is not rewritten for progress; the partial file is the
+/// source of truth on resume).
+fn report_progress(
+    app: &AppHandle,
+    id: &str,
+    written: u64,
+    total: Option<u64>,
+    bytes_per_sec: u64,
+    eta_secs: Option<u64>,
+) {
+    let state = app.state::<DownloadManagerState>();
+    if let Some(job) = state
+        .jobs
+        .lock()
+        .unwrap()
+        .iter_mut()
+        .find(|job| job.id == id)
+    {
+        job.bytes_downloaded = written;
+        job.total_bytes = total;
+    }
+    let _ = app.emit(
+        "download-progress",
+        ProgressPayload {
+            id,
+            bytes_downloaded: written,
+            total_bytes: total,
+            bytes_per_sec,
+            eta_secs,
+        },
+    );
+}
+
+// ---------------------------------------------------------------------------
+// Queue
+// ---------------------------------------------------------------------------
+
+/// Queues `url` for download and returns the job ID. `retries` counts
+/// earlier downloads of the same file that failed verification.
+pub fn enqueue(
+    app: &AppHandle,
+    url: String,
+    filename: Option<String>,
+    metadata: Option<TrackMetadata>,
+    retries: u32,
+) -> Result<String, String> {
+    let parsed = url::Url::parse(&url).map_err(|e| format!("Invalid URL: {}", e))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err("unsupported url scheme".into());
+    }
+
+    let job = DownloadJob {
+        id: uuid::Uuid::new_v4().to_string(),
+        url,
+        filename,
+        metadata,
+        destination: None,
+        status: JobStatus::Queued,
+        bytes_downloaded: 0,
+        total_bytes: None,
+        error: None,
+        retries,
+        created_at: now(),
+    };
+    let id = job.id.clone();
+    {
+        let state = app.state::<DownloadManagerState>();
+        let mut jobs = state.jobs.lock().map_err(|_| "Failed to lock mutex")?;
+        jobs.push(job.clone());
+        save_queue(app, &jobs);
+    }
+    let _ = app.emit("download-job-updated", &job);
+    schedule(app);
+    Ok(id)
+}
+
+/// Whether an unfinished job for `url` is in the queue.
+pub fn queued(app: &AppHandle, url: &str) -> bool {
+    let state = app.state::<DownloadManagerState>();
+    let jobs = state.jobs.lock().unwrap();
+    jobs.iter().any(|job| job.url == url)
+}
+
+// ---------------------------------------------------------------------------
+// Tauri commands
+// ---------------------------------------------------------------------------
+
+/// Queues `url` for download and returns the job ID. `filename` overrides the
+/// name taken from the URL.
+#[tauri::command]
+pub fn enqueue_download(
+    app: AppHandle,
+    url: String,
+    filename: Option<String>,
+    metadata: Option<TrackMetadata>,
+) -> Result<String, String> {
+    enqueue(&app, url, filename, metadata, 0)
+}
+
+#[tauri::command]
+pub fn list_downloads(
+    state: tauri::State<DownloadManagerState>,
+) -> Result<Vec<DownloadJob>, String> {
+    let jobs = state.jobs.lock().map_err(|_| "Failed to lock mutex")?;
+    Ok(jobs.clone())
+}
+
+#[tauri::command]
+pub fn pause_download(
+    app: AppHandle,
+    state: tauri::State<DownloadManagerState>,
+    id: String,
+) -> Result<(), String> {
+    let control = state
+        .controls
+        .lock()
+        .map_err(|_| "Failed to lock mutex")?
+        .get(&id)
+        .cloned();
+    match control {
+        Some(control) => control.store(CONTROL_PAUSE, Ordering::SeqCst),
+        None => {
+            update_job(&app, &id, |job| {
+                if job.status == JobStatus::Queued {
+                    job.status = JobStatus::Paused;
+                }
+            })
+            .ok_or("Unknown download")?;
+        }
+    }
+    Ok(())
+}
+
+/// Re-queues a paused or failed job.
+#[tauri::command]
+pub fn resume_download(app: AppHandle, id: String) -> Result<(), String> {
+    update_job(&app, &id, |job| {
+        if matches!(job.status, JobStatus::Paused | JobStatus::Failed) {
+            job.status = JobStatus::Queued;
+            job.error = None;
+        }
+    })
+    .ok_or("Unknown download")?;
+    schedule(&app);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn cancel_download(
+    app: AppHandle,
+    state: tauri::State<DownloadManagerState>,
+    id: String,
+) -> Result<(), String> {
+    let control = state
+        .controls
+        .lock()
+        .map_err(|_| "Failed to lock mutex")?
+        .get(&id)
+        .cloned();
+    if let Some(control) = control {
+        control.store(CONTROL_CANCEL, Ordering::SeqCst);
+        return Ok(());
+    }
+
+    let job =
+        update_job(&app, &id, |job| job.status = JobStatus::Cancelled).ok_or("Unknown download")?;
+    if let Some(destination) = &job.destination {
+        let _ = fs::remove_file(part_path(destination));
+    }
+    download_history::cancel(&app, &id);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_download_manager_settings(
+    state: tauri::State<DownloadManagerState>,
+) -> Result<DownloadManagerSettings, String> {
+    let settings = state.settings.lock().map_err(|_| "Failed to lock mutex")?;
+    Ok(settings.clone())
+}
+
+#[tauri::command]
+pub fn set_download_manager_settings(
+    app: AppHandle,
+    state: tauri::State<DownloadManagerState>,
+    settings: DownloadManagerSettings,
+) -> Result<(), String> {
+    crate::store::save_config(&app, SETTINGS_FILE, &settings)?;
+    *state.settings.lock().map_err(|_| "Failed to lock mutex")? = settings;
+    schedule(&app);
+    Ok(())
+}
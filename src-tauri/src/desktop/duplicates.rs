@@ -0,0 +1,493 @@
+use crate::desktop::{history, library};
+use lofty::file::TaggedFileExt;
+use lofty::tag::ItemKey;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use symphonia::core::codecs::CODEC_TYPE_NULL;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use tauri::{AppHandle, Emitter, Manager};
+
+const FINGERPRINTS_FILE: &str = "duplicate_fingerprints.json";
+
+/// Copies tagged alike only count as duplicates when their lengths differ
+/// by no more than this, so a live or extended version is kept apart.
+const DURATION_TOLERANCE_MS: u64 = 3000;
+
+// ---------------------------------------------------------------------------
+// State
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DuplicateReason {
+    /// Same artist, album and title, all of them set.
+    Tags,
+    Isrc,
+    /// Identical audio stream, whatever the tags say.
+    Content,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicateCopy {
+    pub path: PathBuf,
+    pub format: String,
+    pub size: u64,
+    /// Modification time at the scan, in seconds since the epoch.
+    pub modified: u64,
+    pub duration_ms: u64,
+    pub lossless: bool,
+    pub bitrate_kbps: Option<u32>,
+    pub sample_rate: Option<u32>,
+    pub bit_depth: Option<u8>,
+    pub isrc: Option<String>,
+    /// Positions in the group of the copies this one is a duplicate of
+    /// itself, rather than through another copy.
+    pub linked: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicateGroup {
+    pub id: String,
+    pub title: String,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub reasons: Vec<DuplicateReason>,
+    /// Best copy first.
+    pub copies: Vec<DuplicateCopy>,
+    /// Bytes freed by keeping only the best copy and deleting the copies
+    /// linked to it.
+    pub reclaimable_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicateReport {
+    pub scanned: usize,
+    pub groups: Vec<DuplicateGroup>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicateChoice {
+    pub group: String,
+    /// Copy to keep; the best one when unset.
+    #[serde(default)]
+    pub keep: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicateResolution {
+    pub removed: Vec<PathBuf>,
+    pub freed_bytes: u64,
+    /// `file: reason` for every copy that could not be deleted.
+    pub failed: Vec<String>,
+}
+
+/// What a file is identified by beyond the library index. Reading it means
+/// opening the file, so it is kept until the file changes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Fingerprint {
+    path: PathBuf,
+    size: u64,
+    modified: u64,
+    isrc: Option<String>,
+    audio_hash: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProgressPayload {
+    done: usize,
+    total: usize,
+}
+
+#[derive(Default)]
+pub struct DuplicateState {
+    fingerprints: Mutex<HashMap<PathBuf, Fingerprint>>,
+    /// Groups from the last scan, for `resolve_duplicates`.
+    groups: Mutex<Vec<DuplicateGroup>>,
+}
+
+pub fn load(app: &AppHandle) {
+    let fingerprints: Vec<Fingerprint> = crate::store::data_file(app, FINGERPRINTS_FILE)
+        .and_then(|path| crate::store::read_json(&path))
+        .unwrap_or_default();
+    *app.state::<DuplicateState>().fingerprints.lock().unwrap() = fingerprints
+        .into_iter()
+        .map(|fingerprint| (fingerprint.path.clone(), fingerprint))
+        .collect();
+}
+
+fn save_fingerprints(app: &AppHandle, fingerprints: &HashMap<PathBuf, Fingerprint>) {
+    if let Some(path) = crate::store::data_file(app, FINGERPRINTS_FILE) {
+        let list: Vec<&Fingerprint> = fingerprints.values().collect();
+        let _ = crate::store::write_json(&path, &list);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Fingerprints
+// ---------------------------------------------------------------------------
+
+fn read_isrc(path: &Path) -> Option<String> {
+    let file = lofty::read_from_path(path).ok()?;
+    let tag = file.primary_tag().or_else(|| file.first_tag())?;
+    let isrc = tag.get_string(&ItemKey::Isrc)?.trim().to_uppercase();
+    (!isrc.is_empty()).then_some(isrc)
+}
+
+/// SHA-256 of the encoded audio packets, leaving tags and artwork out.
+/// `None` for formats Symphonia can't demux (e.g. Opus).
+fn audio_hash(path: &Path) -> Option<String> {
+    let file = File::open(path).ok()?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(ext);
+    }
+    let probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .ok()?;
+    let mut format = probed.format;
+    let track_id = format
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)?
+        .id;
+
+    let mut hasher = Sha256::new();
+    loop {
+        match format.next_packet() {
+            Ok(packet) if packet.track_id() == track_id => hasher.update(&packet.data),
+            Ok(_) => {}
+            Err(SymphoniaError::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(_) => return None,
+        }
+    }
+    Some(
+        hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect(),
+    )
+}
+
+fn fingerprint(track: &library::LibraryTrack) -> Fingerprint {
+    Fingerprint {
+        path: track.path.clone(),
+        size: track.size,
+        modified: track.modified,
+        isrc: read_isrc(&track.path),
+        audio_hash: audio_hash(&track.path),
+    }
+}
+
+/// Fingerprints of `tracks`, reading only files that changed since the last
+/// scan. Entries of files no longer indexed are dropped.
+fn fingerprints(app: &AppHandle, tracks: &[library::LibraryTrack]) -> Vec<Fingerprint> {
+    let state = app.state::<DuplicateState>();
+    let known = state.fingerprints.lock().unwrap().clone();
+    let mut current = HashMap::new();
+    let mut list = Vec::with_capacity(tracks.len());
+    for (done, track) in tracks.iter().enumerate() {
+        let fingerprint = match known.get(&track.path) {
+            Some(known) if known.size == track.size && known.modified == track.modified => {
+                known.clone()
+            }
+            _ => fingerprint(track),
+        };
+        current.insert(track.path.clone(), fingerprint.clone());
+        list.push(fingerprint);
+        let _ = app.emit(
+            "duplicate-scan-progress",
+            ProgressPayload {
+                done: done + 1,
+                total: tracks.len(),
+            },
+        );
+    }
+    save_fingerprints(app, &current);
+    *state.fingerprints.lock().unwrap() = current;
+    list
+}
+
+// ---------------------------------------------------------------------------
+// Grouping
+// ---------------------------------------------------------------------------
+
+fn find(parents: &mut [usize], index: usize) -> usize {
+    let mut root = index;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    let mut index = index;
+    while parents[index] != root {
+        let next = parents[index];
+        parents[index] = root;
+        index = next;
+    }
+    root
+}
+
+fn is_lossless(track: &library::LibraryTrack) -> bool {
+    match track.format.as_str() {
+        "flac" | "wav" | "aiff" | "aif" | "wv" | "ape" => true,
+        // ALAC reports a bit depth, AAC does not.
+        "m4a" | "mp4" => track.bit_depth.is_some(),
+        _ => false,
+    }
+}
+
+/// Orders copies best first: lossless, then bit depth, sample rate,
+/// bitrate and size.
+fn quality(track: &library::LibraryTrack) -> impl Ord {
+    (
+        is_lossless(track),
+        track.bit_depth,
+        track.sample_rate,
+        track.bitrate_kbps,
+        track.size,
+    )
+}
+
+/// Groups `tracks` that share tags, an ISRC or their audio. Two copies
+/// linked by any of those end up in the same group, possibly through
+/// others; each copy lists the ones it is linked to directly.
+fn group(tracks: Vec<library::LibraryTrack>, prints: Vec<Fingerprint>) -> Vec<DuplicateGroup> {
+    let mut parents: Vec<usize> = (0..tracks.len()).collect();
+    // A member of each link, with why it was made.
+    let mut links: Vec<(usize, DuplicateReason)> = Vec::new();
+    // Every linked pair, lower index first.
+    let mut pairs: HashSet<(usize, usize)> = HashSet::new();
+
+    let mut by_tags: HashMap<(String, String, String), Vec<usize>> = HashMap::new();
+    let mut by_isrc: HashMap<&str, Vec<usize>> = HashMap::new();
+    let mut by_hash: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (index, (track, print)) in tracks.iter().zip(&prints).enumerate() {
+        let artist = track.album_artist.as_ref().or(track.artist.as_ref());
+        if let (Some(artist), Some(album), Some(title)) = (artist, &track.album, &track.title) {
+            let key = (
+                history::normalize(artist),
+                history::normalize(album),
+                history::normalize(title),
+            );
+            // Singles and untagged files would otherwise all match by title.
+            if !key.0.is_empty() && !key.1.is_empty() && !key.2.is_empty() {
+                by_tags.entry(key).or_default().push(index);
+            }
+        }
+        if let Some(isrc) = &print.isrc {
+            by_isrc.entry(isrc).or_default().push(index);
+        }
+        if let Some(hash) = &print.audio_hash {
+            by_hash.entry(hash).or_default().push(index);
+        }
+    }
+
+    let mut link = |a: usize, b: usize, reason| {
+        pairs.insert((a.min(b), a.max(b)));
+        let (a, b) = (find(&mut parents, a), find(&mut parents, b));
+        links.push((a, reason));
+        if a != b {
+            parents[b] = a;
+        }
+    };
+    for indices in by_tags.values() {
+        for (n, &a) in indices.iter().enumerate() {
+            for &b in &indices[n + 1..] {
+                if tracks[a].duration_ms.abs_diff(tracks[b].duration_ms) <= DURATION_TOLERANCE_MS {
+                    link(a, b, DuplicateReason::Tags);
+                }
+            }
+        }
+    }
+    let shared = by_isrc
+        .values()
+        .map(|indices| (indices, DuplicateReason::Isrc))
+        .chain(
+            by_hash
+                .values()
+                .map(|indices| (indices, DuplicateReason::Content)),
+        );
+    for (indices, reason) in shared {
+        for (n, &a) in indices.iter().enumerate() {
+            for &b in &indices[n + 1..] {
+                link(a, b, reason);
+            }
+        }
+    }
+
+    let mut members: HashMap<usize, Vec<usize>> = HashMap::new();
+    for index in 0..tracks.len() {
+        let root = find(&mut parents, index);
+        members.entry(root).or_default().push(index);
+    }
+    let mut group_reasons: HashMap<usize, Vec<DuplicateReason>> = HashMap::new();
+    for (index, reason) in links {
+        let root = find(&mut parents, index);
+        let entry = group_reasons.entry(root).or_default();
+        if !entry.contains(&reason) {
+            entry.push(reason);
+        }
+    }
+
+    let mut groups: Vec<DuplicateGroup> = members
+        .into_iter()
+        .filter(|(_, indices)| indices.len() > 1)
+        .map(|(root, mut indices)| {
+            indices.sort_by(|&a, &b| quality(&tracks[b]).cmp(&quality(&tracks[a])));
+            let best = &tracks[indices[0]];
+            let copies: Vec<DuplicateCopy> = indices
+                .iter()
+                .map(|&index| {
+                    let track = &tracks[index];
+                    let linked = indices
+                        .iter()
+                        .enumerate()
+                        .filter(|&(_, &other)| {
+                            pairs.contains(&(index.min(other), index.max(other)))
+                        })
+                        .map(|(position, _)| position)
+                        .collect();
+                    DuplicateCopy {
+                        path: track.path.clone(),
+                        format: track.format.clone(),
+                        size: track.size,
+                        modified: track.modified,
+                        duration_ms: track.duration_ms,
+                        lossless: is_lossless(track),
+                        bitrate_kbps: track.bitrate_kbps,
+                        sample_rate: track.sample_rate,
+                        bit_depth: track.bit_depth,
+                        isrc: prints[index].isrc.clone(),
+                        linked,
+                    }
+                })
+                .collect();
+            let mut reasons = group_reasons.remove(&root).unwrap_or_default();
+            reasons.sort_by_key(|reason| *reason as u8);
+            DuplicateGroup {
+                id: uuid::Uuid::new_v4().to_string(),
+                title: best.title.clone().unwrap_or_else(|| {
+                    best.path
+                        .file_stem()
+                        .map(|stem| stem.to_string_lossy().into_owned())
+                        .unwrap_or_default()
+                }),
+                artist: best.artist.clone(),
+                album: best.album.clone(),
+                reasons,
+                reclaimable_bytes: copies[0]
+                    .linked
+                    .iter()
+                    .map(|&position| copies[position].size)
+                    .sum(),
+                copies,
+            }
+        })
+        .collect();
+    groups.sort_by(|a, b| {
+        b.reclaimable_bytes
+            .cmp(&a.reclaimable_bytes)
+            .then_with(|| a.title.cmp(&b.title))
+    });
+    groups
+}
+
+// ---------------------------------------------------------------------------
+// Tauri commands
+// ---------------------------------------------------------------------------
+
+/// Scans the library for copies of the same recording, emitting
+/// `duplicate-scan-progress` as files are read.
+#[tauri::command]
+pub async fn scan_duplicates(app: AppHandle) -> Result<DuplicateReport, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let tracks = library::tracks(&app);
+        let prints = fingerprints(&app, &tracks);
+        let scanned = tracks.len();
+        let groups = group(tracks, prints);
+        *app.state::<DuplicateState>()
+            .groups
+            .lock()
+            .map_err(|_| "Failed to lock mutex")? = groups.clone();
+        Ok(DuplicateReport { scanned, groups })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Keeps one copy in each chosen group of the last scan and deletes the
+/// copies directly linked to it, unless they changed since the scan.
+#[tauri::command]
+pub fn resolve_duplicates(
+    app: AppHandle,
+    state: tauri::State<DuplicateState>,
+    choices: Vec<DuplicateChoice>,
+) -> Result<DuplicateResolution, String> {
+    let mut groups = state.groups.lock().map_err(|_| "Failed to lock mutex")?;
+    let mut resolution = DuplicateResolution::default();
+    for choice in &choices {
+        let Some(index) = groups.iter().position(|group| group.id == choice.group) else {
+            resolution
+                .failed
+                .push(format!("{}: Unknown duplicate group", choice.group));
+            continue;
+        };
+        let group = &groups[index];
+        let keep = choice.keep.as_ref().unwrap_or(&group.copies[0].path);
+        let Some(kept) = group.copies.iter().find(|copy| &copy.path == keep) else {
+            resolution
+                .failed
+                .push(format!("{}: Not a copy in this group", keep.display()));
+            continue;
+        };
+        for copy in kept.linked.iter().map(|&position| &group.copies[position]) {
+            let unchanged = fs::metadata(&copy.path).is_ok_and(|meta| {
+                meta.len() == copy.size && library::modified_secs(&meta) == copy.modified
+            });
+            if !unchanged {
+                resolution
+                    .failed
+                    .push(format!("{}: Changed since the scan", copy.path.display()));
+                continue;
+            }
+            match fs::remove_file(&copy.path) {
+                Ok(()) => {
+                    resolution.freed_bytes += copy.size;
+                    resolution.removed.push(copy.path.clone());
+                }
+                Err(e) => resolution
+                    .failed
+                    .push(format!("{}: {}", copy.path.display(), e)),
+            }
+        }
+        groups.remove(index);
+    }
+    drop(groups);
+
+    if !resolution.removed.is_empty() {
+        library::refresh(&app, resolution.removed.clone());
+        let _ = app.emit("duplicates-resolved", &resolution.removed);
+    }
+    Ok(resolution)
+}
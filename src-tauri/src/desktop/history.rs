@@ -0,0 +1,441 @@
+use chrono::{DateTime, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Manager};
+use tauri_plugin_dialog::DialogExt;
+
+const HISTORY_FILE: &str = "listening_history.jsonl";
+
+/// Two plays of the same track closer than this are treated as the same
+/// listen. Exports disagree on whether they record the start or the end of a
+/// play, so exact timestamp matching would miss most duplicates.
+const DEDUPE_WINDOW_SECS: i64 = 90;
+
+/// Spotify logs every stream, including skips; only count what Spotify itself
+/// would count as a play.
+const SPOTIFY_MIN_PLAYED_MS: u64 = 30_000;
+
+const PREVIEW_LIMIT: usize = 50;
+
+// ---------------------------------------------------------------------------
+// State
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    /// Unix timestamp (seconds) of when playback started.
+    pub played_at: i64,
+    pub artist: String,
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub album: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub isrc: Option<String>,
+    pub source: HistorySource,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistorySource {
+    Monochrome,
+    ListenBrainz,
+    LastFm,
+    Spotify,
+}
+
+#[derive(Default)]
+pub struct HistoryState {
+    entries: Mutex<Vec<HistoryEntry>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    format: HistorySource,
+    dry_run: bool,
+    /// Rows found in the export.
+    total: usize,
+    /// Rows that were (or, in a dry run, would be) added.
+    imported: usize,
+    /// Rows already present in the local history or repeated in the file.
+    duplicates: usize,
+    /// Rows that could not be used (missing fields, podcasts, skips).
+    skipped: usize,
+    /// The first few entries that were (or would be) imported.
+    preview: Vec<HistoryEntry>,
+}
+
+// ---------------------------------------------------------------------------
+// Persistence
+// ---------------------------------------------------------------------------
+
+pub fn load(app: &AppHandle) {
+    let entries = crate::store::data_file(app, HISTORY_FILE)
+        .map(|path| crate::store::read_json_lines(&path))
+        .unwrap_or_default();
+    let state = app.state::<HistoryState>();
+    *state.entries.lock().unwrap() = entries;
+}
+
+fn append(app: &AppHandle, entries: &[HistoryEntry]) -> Result<(), String> {
+    let path = crate::store::data_file(app, HISTORY_FILE).ok_or("Data directory unavailable")?;
+    crate::store::append_json_lines(&path, entries)?;
+    let state = app.state::<HistoryState>();
+    state
+        .entries
+        .lock()
+        .map_err(|_| "Failed to lock mutex")?
+        .extend_from_slice(entries);
+    Ok(())
+}
+
+/// Records a play reported by the web player.
+pub fn record_play(app: &AppHandle, title: &str, artist: &str) {
+    let played_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let entry = HistoryEntry {
+        played_at,
+        artist: artist.trim().to_string(),
+        title: title.trim().to_string(),
+        album: None,
+        duration_ms: None,
+        isrc: None,
+        source: HistorySource::Monochrome,
+    };
+    let _ = append(app, &[entry]);
+}
+
+// ---------------------------------------------------------------------------
+// Export parsers
+// ---------------------------------------------------------------------------
+
+struct Parsed {
+    entries: Vec<HistoryEntry>,
+    total: usize,
+}
+
+fn detect_format(path: &Path, content: &str) -> Result<HistorySource, String> {
+    let is_csv = path
+        .extension()
+        .map(|ext| ext.eq_ignore_ascii_case("csv"))
+        .unwrap_or(false);
+    if is_csv {
+        return Ok(HistorySource::LastFm);
+    }
+
+    let first = json_records(content)?
+        .into_iter()
+        .next()
+        .ok_or("The file contains no listens")?;
+    if first.get("listened_at").is_some() || first.get("track_metadata").is_some() {
+        Ok(HistorySource::ListenBrainz)
+    } else if first.get("ts").is_some() && first.get("ms_played").is_some() {
+        Ok(HistorySource::Spotify)
+    } else {
+        Err("Unrecognized history export format".into())
+    }
+}
+
+/// Accepts either a JSON array or JSON Lines (ListenBrainz ships both).
+fn json_records(content: &str) -> Result<Vec<Value>, String> {
+    let trimmed = content.trim_start();
+    if trimmed.starts_with('[') {
+        return serde_json::from_str(trimmed).map_err(|e| format!("Invalid JSON: {}", e));
+    }
+    trimmed
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).map_err(|e| format!("Invalid JSON line: {}", e)))
+        .collect()
+}
+
+fn str_field(value: &Value, key: &str) -> Option<String> {
+    value
+        .get(key)
+        .and_then(Value::as_str)
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+}
+
+fn parse_listenbrainz(content: &str) -> Result<Parsed, String> {
+    let records = json_records(content)?;
+    let total = records.len();
+    let entries = records
+        .iter()
+        .filter_map(|record| {
+            let played_at = record.get("listened_at")?.as_i64()?;
+            let meta = record.get("track_metadata")?;
+            let info = meta.get("additional_info");
+            Some(HistoryEntry {
+                played_at,
+                artist: str_field(meta, "artist_name")?,
+                title: str_field(meta, "track_name")?,
+                album: str_field(meta, "release_name"),
+                duration_ms: info
+                    .and_then(|i| i.get("duration_ms"))
+                    .and_then(Value::as_u64),
+                isrc: info.and_then(|i| str_field(i, "isrc")),
+                source: HistorySource::ListenBrainz,
+            })
+        })
+        .collect();
+    Ok(Parsed { entries, total })
+}
+
+fn parse_spotify(content: &str) -> Result<Parsed, String> {
+    let records = json_records(content)?;
+    let total = records.len();
+    let entries = records
+        .iter()
+        .filter_map(|record| {
+            let ms_played = record.get("ms_played")?.as_u64()?;
+            if ms_played < SPOTIFY_MIN_PLAYED_MS {
+                return None;
+            }
+            // `ts` marks when the stream ended.
+            let ended = DateTime::parse_from_rfc3339(record.get("ts")?.as_str()?).ok()?;
+            Some(HistoryEntry {
+                played_at: ended.timestamp() - (ms_played / 1000) as i64,
+                artist: str_field(record, "master_metadata_album_artist_name")?,
+                title: str_field(record, "master_metadata_track_name")?,
+                album: str_field(record, "master_metadata_album_album_name"),
+                duration_ms: None,
+                isrc: None,
+                source: HistorySource::Spotify,
+            })
+        })
+        .collect();
+    Ok(Parsed { entries, total })
+}
+
+/// Handles the two common Last.fm dump layouts: headerless
+/// `artist,album,title,date` rows (date like `31 Jan 2021 18:04`, UTC) and the
+/// headed `uts,utc_time,artist,...,track,...` variant.
+fn parse_lastfm(content: &str) -> Result<Parsed, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(content.as_bytes());
+    let mut rows = reader.records().peekable();
+
+    let mut columns: Option<HashMap<String, usize>> = None;
+    if let Some(Ok(first)) = rows.peek() {
+        if first.iter().any(|field| field.eq_ignore_ascii_case("uts")) {
+            columns = Some(
+                first
+                    .iter()
+                    .enumerate()
+                    .map(|(i, name)| (name.trim().to_ascii_lowercase(), i))
+                    .collect(),
+            );
+            rows.next();
+        }
+    }
+
+    let mut total = 0;
+    let mut entries = Vec::new();
+    for row in rows {
+        total += 1;
+        let Ok(row) = row else { continue };
+        let field = |i: usize| {
+            row.get(i)
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+        };
+
+        let parsed = match &columns {
+            Some(cols) => {
+                let col = |name: &str| cols.get(name).and_then(|&i| field(i));
+                col("uts")
+                    .and_then(|uts| uts.parse::<i64>().ok())
+                    .zip(col("artist"))
+                    .zip(col("track"))
+                    .map(|((played_at, artist), title)| (played_at, artist, title, col("album")))
+            }
+            None => field(3)
+                .and_then(|date| NaiveDateTime::parse_from_str(&date, "%d %b %Y %H:%M").ok())
+                .zip(field(0))
+                .zip(field(2))
+                .map(|((date, artist), title)| {
+                    (date.and_utc().timestamp(), artist, title, field(1))
+                }),
+        };
+
+        if let Some((played_at, artist, title, album)) = parsed {
+            entries.push(HistoryEntry {
+                played_at,
+                artist,
+                title,
+                album,
+                duration_ms: None,
+                isrc: None,
+                source: HistorySource::LastFm,
+            });
+        }
+    }
+    Ok(Parsed { entries, total })
+}
+
+// ---------------------------------------------------------------------------
+// De-duplication
+// ---------------------------------------------------------------------------
+
+pub fn normalize(value: &str) -> String {
+    value
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// When each track was last played, keyed by normalized artist and title.
+pub fn last_played(app: &AppHandle) -> HashMap<(String, String), i64> {
+    let state = app.state::<HistoryState>();
+    let entries = state.entries.lock().unwrap();
+    let mut last = HashMap::new();
+    for entry in entries.iter() {
+        let played = last
+            .entry((normalize(&entry.artist), normalize(&entry.title)))
+            .or_insert(entry.played_at);
+        *played = (*played).max(entry.played_at);
+    }
+    last
+}
+
+struct Seen(HashMap<(String, String), Vec<i64>>);
+
+impl Seen {
+    fn new(entries: &[HistoryEntry]) -> Self {
+        let mut seen = Seen(HashMap::new());
+        for entry in entries {
+            seen.insert(entry);
+        }
+        seen
+    }
+
+    fn key(entry: &HistoryEntry) -> (String, String) {
+        (normalize(&entry.artist), normalize(&entry.title))
+    }
+
+    fn contains(&self, entry: &HistoryEntry) -> bool {
+        self.0
+            .get(&Self::key(entry))
+            .map(|times| {
+                times
+                    .iter()
+                    .any(|t| (t - entry.played_at).abs() <= DEDUPE_WINDOW_SECS)
+            })
+            .unwrap_or(false)
+    }
+
+    fn insert(&mut self, entry: &HistoryEntry) {
+        self.0
+            .entry(Self::key(entry))
+            .or_default()
+            .push(entry.played_at);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tauri commands
+// ---------------------------------------------------------------------------
+
+fn import_file(
+    app: &AppHandle,
+    path: &Path,
+    format: Option<HistorySource>,
+    dry_run: bool,
+) -> Result<ImportReport, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("Failed to read file: {}", e))?;
+    let format = match format {
+        Some(format) => format,
+        None => detect_format(path, &content)?,
+    };
+    let parsed = match format {
+        HistorySource::ListenBrainz => parse_listenbrainz(&content)?,
+        HistorySource::Spotify => parse_spotify(&content)?,
+        HistorySource::LastFm => parse_lastfm(&content)?,
+        HistorySource::Monochrome => return Err("Unsupported import format".into()),
+    };
+
+    let mut seen = {
+        let state = app.state::<HistoryState>();
+        let entries = state.entries.lock().map_err(|_| "Failed to lock mutex")?;
+        Seen::new(&entries)
+    };
+
+    let mut fresh = Vec::new();
+    let mut duplicates = 0;
+    for entry in &parsed.entries {
+        if seen.contains(entry) {
+            duplicates += 1;
+        } else {
+            seen.insert(entry);
+            fresh.push(entry.clone());
+        }
+    }
+    fresh.sort_by_key(|entry| entry.played_at);
+
+    if !dry_run && !fresh.is_empty() {
+        append(app, &fresh)?;
+    }
+
+    Ok(ImportReport {
+        format,
+        dry_run,
+        total: parsed.total,
+        imported: fresh.len(),
+        duplicates,
+        skipped: parsed.total - parsed.entries.len(),
+        preview: fresh.into_iter().take(PREVIEW_LIMIT).collect(),
+    })
+}
+
+/// Imports a ListenBrainz, Last.fm or Spotify export the user picks, so the
+/// page can't read any other file; `format` is detected when omitted.
+#[tauri::command]
+pub async fn import_listening_history(
+    app: AppHandle,
+    format: Option<HistorySource>,
+    dry_run: Option<bool>,
+) -> Result<ImportReport, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let path = app
+            .dialog()
+            .file()
+            .set_title("Import Listening History")
+            .add_filter("History export", &["json", "jsonl", "csv"])
+            .blocking_pick_file()
+            .ok_or("No file selected")?
+            .into_path()
+            .map_err(|e| e.to_string())?;
+        import_file(&app, &path, format, dry_run.unwrap_or(false))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Returns the most recent plays, newest first.
+#[tauri::command]
+pub fn get_listening_history(
+    state: tauri::State<HistoryState>,
+    limit: Option<usize>,
+) -> Result<Vec<HistoryEntry>, String> {
+    let entries = state.entries.lock().map_err(|_| "Failed to lock mutex")?;
+    let mut recent: Vec<HistoryEntry> = entries.clone();
+    recent.sort_by_key(|entry| std::cmp::Reverse(entry.played_at));
+    recent.truncate(limit.unwrap_or(500));
+    Ok(recent)
+}
@@ -0,0 +1,269 @@
+use crate::desktop::{download_history, library};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use tauri::{AppHandle, Manager};
+
+const SETTINGS_FILE: &str = "integrity_settings.json";
+
+// ---------------------------------------------------------------------------
+// Settings
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct IntegritySettings {
+    pub enabled: bool,
+    /// Decode every frame (checking the STREAMINFO MD5 for FLAC) instead of
+    /// only comparing sizes and reading the headers.
+    pub full_decode: bool,
+    /// Download corrupt files again automatically.
+    pub requeue: bool,
+    /// Attempts per file, including the first, before giving up.
+    pub max_attempts: u32,
+}
+
+impl Default for IntegritySettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            full_decode: true,
+            requeue: false,
+            max_attempts: 3,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct IntegrityState {
+    settings: Mutex<IntegritySettings>,
+}
+
+pub fn load(app: &AppHandle) {
+    let state = app.state::<IntegrityState>();
+    *state.settings.lock().unwrap() = crate::store::load_config(app, SETTINGS_FILE);
+}
+
+fn settings(app: &AppHandle) -> IntegritySettings {
+    app.state::<IntegrityState>()
+        .settings
+        .lock()
+        .unwrap()
+        .clone()
+}
+
+fn quarantine_dir(app: &AppHandle) -> Option<PathBuf> {
+    app.path()
+        .app_data_dir()
+        .ok()
+        .map(|dir| dir.join("quarantine"))
+}
+
+// ---------------------------------------------------------------------------
+// Checks
+// ---------------------------------------------------------------------------
+
+/// Probes `path` and, with `full_decode`, decodes it to the end. Returns why
+/// the file is corrupt. Formats Symphonia can't read (e.g. Opus) only get
+/// the size check.
+fn check_audio(path: &Path, full_decode: bool) -> Result<(), String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    let probed = match symphonia::default::get_probe().format(
+        &hint,
+        stream,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    ) {
+        Ok(probed) => probed,
+        Err(SymphoniaError::Unsupported(_)) => return Ok(()),
+        Err(e) => return Err(format!("Unreadable audio: {}", e)),
+    };
+    let mut format = probed.format;
+    let Some(track) = format
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+    else {
+        return Err("No audio track found".into());
+    };
+    if !full_decode {
+        return Ok(());
+    }
+
+    let track_id = track.id;
+    let expected_frames = track.codec_params.n_frames;
+    // Encoder delay and padding make some formats decode slightly fewer
+    // frames than they announce; allow up to a second of difference.
+    let slack = track.codec_params.sample_rate.unwrap_or(44_100) as u64;
+    let mut decoder = match symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions { verify: true })
+    {
+        Ok(decoder) => decoder,
+        Err(SymphoniaError::Unsupported(_)) => return Ok(()),
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let mut frames: u64 = 0;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(format!("Unreadable audio: {}", e)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        match decoder.decode(&packet) {
+            Ok(buffer) => frames += buffer.frames() as u64,
+            Err(e) => return Err(format!("Decode error after {} samples: {}", frames, e)),
+        }
+    }
+
+    if decoder.finalize().verify_ok == Some(false) {
+        return Err("Audio checksum mismatch".into());
+    }
+    if let Some(expected) = expected_frames {
+        if frames + slack < expected {
+            return Err(format!(
+                "File is truncated ({} of {} samples)",
+                frames, expected
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Checks a finished download. `expected_size` is the length the server
+/// announced, when known. Returns why the file is corrupt; files always pass
+/// while verification is disabled.
+pub async fn verify(
+    app: &AppHandle,
+    path: &Path,
+    expected_size: Option<u64>,
+) -> Result<(), String> {
+    let settings = settings(app);
+    if !settings.enabled {
+        return Ok(());
+    }
+
+    let size = fs::metadata(path).map_err(|e| e.to_string())?.len();
+    if size == 0 {
+        return Err("File is empty".into());
+    }
+    if let Some(expected) = expected_size.filter(|expected| *expected != size) {
+        return Err(format!("Expected {} bytes but got {}", expected, size));
+    }
+
+    let path = path.to_path_buf();
+    tauri::async_runtime::spawn_blocking(move || check_audio(&path, settings.full_decode))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// Moves a corrupt download out of the library into the quarantine folder
+/// and records it in the history. Returns whether it should be downloaded
+/// again, given how many `attempts` it has had so far.
+pub fn quarantine(app: &AppHandle, id: &str, file: &Path, reason: &str, attempts: u32) -> bool {
+    let name = file
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    // Drop the suffix of partial and staged downloads.
+    let name = name
+        .strip_suffix(".part")
+        .or_else(|| name.strip_suffix(".incoming"))
+        .unwrap_or(&name);
+    let target = quarantine_dir(app).map(|dir| {
+        let prefix: String = id.chars().take(8).collect();
+        dir.join(format!("{}-{}", prefix, name))
+    });
+
+    let moved = target.filter(|target| {
+        if let Some(parent) = target.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        // Fall back to copying when the library is on another drive.
+        fs::rename(file, target).is_ok()
+            || (fs::copy(file, target).is_ok() && fs::remove_file(file).is_ok())
+    });
+    if moved.is_none() {
+        let _ = fs::remove_file(file);
+    }
+    download_history::quarantine(app, id, moved, reason);
+
+    let settings = settings(app);
+    settings.requeue && attempts < settings.max_attempts
+}
+
+// ---------------------------------------------------------------------------
+// Tauri commands
+// ---------------------------------------------------------------------------
+
+#[tauri::command]
+pub fn get_integrity_settings(
+    state: tauri::State<IntegrityState>,
+) -> Result<IntegritySettings, String> {
+    let settings = state.settings.lock().map_err(|_| "Failed to lock mutex")?;
+    Ok(settings.clone())
+}
+
+#[tauri::command]
+pub fn set_integrity_settings(
+    app: AppHandle,
+    state: tauri::State<IntegrityState>,
+    settings: IntegritySettings,
+) -> Result<(), String> {
+    crate::store::save_config(&app, SETTINGS_FILE, &settings)?;
+    *state.settings.lock().map_err(|_| "Failed to lock mutex")? = settings;
+    Ok(())
+}
+
+/// Checks a download or library file with a full decode pass. Resolves to
+/// the reason it is corrupt, or `null` when it is intact.
+#[tauri::command]
+pub async fn verify_audio_file(app: AppHandle, path: PathBuf) -> Result<Option<String>, String> {
+    // Resolved, so `..` can't point outside the folder.
+    let inside = crate::desktop::download_dir(&app)
+        .and_then(|dir| {
+            Some(
+                fs::canonicalize(&path)
+                    .ok()?
+                    .starts_with(fs::canonicalize(dir).ok()?),
+            )
+        })
+        .unwrap_or(false);
+    if !inside && !library::contains(&app, &path) {
+        return Err("Only downloads and library files can be verified".into());
+    }
+    if !path.is_file() {
+        return Err("File not found".into());
+    }
+    let result = tauri::async_runtime::spawn_blocking(move || check_audio(&path, true))
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(result.err())
+}
+
+/// Deletes every quarantined file.
+#[tauri::command]
+pub fn clear_quarantine(app: AppHandle) -> Result<(), String> {
+    let dir = quarantine_dir(&app).ok_or("Data directory unavailable")?;
+    if dir.exists() {
+        fs::remove_dir_all(&dir).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,600 @@
+use lofty::file::{AudioFile, TaggedFileExt};
+use lofty::picture::PictureType;
+use lofty::tag::{ItemKey, Tag};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+use tauri::{AppHandle, Emitter, Manager};
+
+const INDEX_FILE: &str = "library_index.json";
+const COVERS_DIR: &str = "library_covers";
+
+const AUDIO_EXTENSIONS: [&str; 10] = [
+    "flac", "mp3", "m4a", "mp4", "ogg", "opus", "wav", "aiff", "aif", "wv",
+];
+/// Folder images used when a file has no embedded cover.
+const FOLDER_COVERS: [&str; 4] = ["cover.jpg", "cover.png", "folder.jpg", "folder.png"];
+
+/// Filesystem events are batched until the folder has been quiet this long.
+const WATCH_DEBOUNCE: Duration = Duration::from_secs(1);
+
+// ---------------------------------------------------------------------------
+// State
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LibraryTrack {
+    pub path: PathBuf,
+    pub size: u64,
+    /// Modification time in seconds; unchanged files are not re-read.
+    pub modified: u64,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub genre: Option<String>,
+    pub year: Option<String>,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub duration_ms: u64,
+    /// Lower-case file extension, e.g. `flac`.
+    pub format: String,
+    pub bitrate_kbps: Option<u32>,
+    pub sample_rate: Option<u32>,
+    pub bit_depth: Option<u8>,
+    pub cover: Option<PathBuf>,
+}
+
+impl LibraryTrack {
+    /// Title from the tags, or the file name without extension.
+    fn display_title(&self) -> String {
+        self.title.clone().unwrap_or_else(|| {
+            self.path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        })
+    }
+
+    fn display_album_artist(&self) -> Option<&str> {
+        self.album_artist.as_deref().or(self.artist.as_deref())
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LibraryIndex {
+    root: Option<PathBuf>,
+    tracks: Vec<LibraryTrack>,
+}
+
+#[derive(Default)]
+pub struct LibraryState {
+    root: Mutex<Option<PathBuf>>,
+    tracks: Mutex<BTreeMap<PathBuf, LibraryTrack>>,
+    /// Dropping the watcher stops it, so it lives as long as the index.
+    watcher: Mutex<Option<RecommendedWatcher>>,
+}
+
+pub fn load(app: &AppHandle) {
+    let index: LibraryIndex = crate::store::data_file(app, INDEX_FILE)
+        .and_then(|path| crate::store::read_json(&path))
+        .unwrap_or_default();
+    let state = app.state::<LibraryState>();
+    *state.root.lock().unwrap() = index.root;
+    *state.tracks.lock().unwrap() = index
+        .tracks
+        .into_iter()
+        .map(|track| (track.path.clone(), track))
+        .collect();
+    watch(app);
+}
+
+fn save(app: &AppHandle) {
+    let state = app.state::<LibraryState>();
+    let index = LibraryIndex {
+        root: state.root.lock().unwrap().clone(),
+        tracks: state.tracks.lock().unwrap().values().cloned().collect(),
+    };
+    if let Some(path) = crate::store::data_file(app, INDEX_FILE) {
+        let _ = crate::store::write_json(&path, &index);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Reading files
+// ---------------------------------------------------------------------------
+
+fn is_audio(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    // Skip partial downloads and conversions in progress.
+    !name.contains(".transcoding.")
+        && path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| AUDIO_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+pub fn modified_secs(meta: &fs::Metadata) -> u64 {
+    meta.modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|time| time.as_secs())
+        .unwrap_or(0)
+}
+
+fn text(tag: Option<&Tag>, key: ItemKey) -> Option<String> {
+    tag?.get_string(&key)
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// `3/12` -> 3.
+fn number(tag: Option<&Tag>, key: ItemKey) -> Option<u32> {
+    text(tag, key)?.split('/').next()?.trim().parse().ok()
+}
+
+/// Stores the embedded front cover under its content hash, so every track
+/// of an album shares one file, and falls back to a folder image.
+fn cover(tag: Option<&Tag>, path: &Path, covers_dir: Option<&Path>) -> Option<PathBuf> {
+    let embedded = tag.and_then(|tag| {
+        let pictures = tag.pictures();
+        pictures
+            .iter()
+            .find(|picture| picture.pic_type() == PictureType::CoverFront)
+            .or_else(|| pictures.first())
+    });
+    if let (Some(picture), Some(dir)) = (embedded, covers_dir) {
+        let data = picture.data();
+        let ext = if data.starts_with(b"\x89PNG") {
+            "png"
+        } else {
+            "jpg"
+        };
+        let name: String = Sha256::digest(data)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        let file = dir.join(format!("{}.{}", name, ext));
+        if file.exists()
+            || (fs::create_dir_all(dir).is_ok() && crate::store::write_atomic(&file, data).is_ok())
+        {
+            return Some(file);
+        }
+    }
+    let folder = path.parent()?;
+    FOLDER_COVERS
+        .iter()
+        .map(|name| folder.join(name))
+        .find(|file| file.is_file())
+}
+
+fn read_track(path: &Path, meta: &fs::Metadata, covers_dir: Option<&Path>) -> LibraryTrack {
+    let file = lofty::read_from_path(path).ok();
+    let tag = file
+        .as_ref()
+        .and_then(|file| file.primary_tag().or_else(|| file.first_tag()));
+    let properties = file.as_ref().map(|file| file.properties());
+
+    LibraryTrack {
+        path: path.to_path_buf(),
+        size: meta.len(),
+        modified: modified_secs(meta),
+        title: text(tag, ItemKey::TrackTitle),
+        artist: text(tag, ItemKey::TrackArtist),
+        album: text(tag, ItemKey::AlbumTitle),
+        album_artist: text(tag, ItemKey::AlbumArtist),
+        genre: text(tag, ItemKey::Genre),
+        year: text(tag, ItemKey::RecordingDate).or_else(|| text(tag, ItemKey::Year)),
+        track_number: number(tag, ItemKey::TrackNumber),
+        disc_number: number(tag, ItemKey::DiscNumber),
+        duration_ms: properties
+            .map(|properties| properties.duration().as_millis() as u64)
+            .unwrap_or(0),
+        format: path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default(),
+        bitrate_kbps: properties.and_then(|properties| properties.audio_bitrate()),
+        sample_rate: properties.and_then(|properties| properties.sample_rate()),
+        bit_depth: properties.and_then(|properties| properties.bit_depth()),
+        cover: cover(tag, path, covers_dir),
+    }
+}
+
+fn audio_files(dir: &Path, files: &mut Vec<(PathBuf, fs::Metadata)>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(meta) = entry.metadata() else {
+            continue;
+        };
+        if meta.is_dir() {
+            audio_files(&path, files);
+        } else if is_audio(&path) {
+            files.push((path, meta));
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Indexing
+// ---------------------------------------------------------------------------
+
+/// Indexes every audio file under `dir`, re-reading only files whose size or
+/// modification time changed, and drops entries under `dir` that are gone.
+/// Returns whether anything changed.
+fn scan_dir(app: &AppHandle, dir: &Path) -> bool {
+    let covers_dir = crate::store::data_file(app, COVERS_DIR);
+    let mut files = Vec::new();
+    audio_files(dir, &mut files);
+    let present: HashSet<&PathBuf> = files.iter().map(|(path, _)| path).collect();
+
+    let state = app.state::<LibraryState>();
+    let stale: Vec<(PathBuf, fs::Metadata)> = {
+        let tracks = state.tracks.lock().unwrap();
+        files
+            .iter()
+            .filter(|(path, meta)| {
+                tracks.get(path).is_none_or(|track| {
+                    track.size != meta.len() || track.modified != modified_secs(meta)
+                })
+            })
+            .cloned()
+            .collect()
+    };
+    // Tags are read without holding the lock; searches keep working meanwhile.
+    let updated: Vec<LibraryTrack> = stale
+        .iter()
+        .map(|(path, meta)| read_track(path, meta, covers_dir.as_deref()))
+        .collect();
+
+    if !in_root(app, dir) {
+        return false;
+    }
+    let mut tracks = state.tracks.lock().unwrap();
+    let before = tracks.len();
+    tracks.retain(|path, _| !path.starts_with(dir) || present.contains(path));
+    let removed = before != tracks.len();
+    let changed = !updated.is_empty();
+    for track in updated {
+        tracks.insert(track.path.clone(), track);
+    }
+    changed || removed
+}
+
+/// Brings the index in line with the paths a watcher reported.
+fn apply_changes(app: &AppHandle, paths: HashSet<PathBuf>) -> bool {
+    let covers_dir = crate::store::data_file(app, COVERS_DIR);
+    let state = app.state::<LibraryState>();
+    let mut changed = false;
+    for path in paths.into_iter().filter(|path| in_root(app, path)) {
+        match fs::metadata(&path) {
+            Ok(meta) if meta.is_dir() => changed |= scan_dir(app, &path),
+            Ok(meta) if is_audio(&path) => {
+                let track = read_track(&path, &meta, covers_dir.as_deref());
+                state.tracks.lock().unwrap().insert(path, track);
+                changed = true;
+            }
+            Ok(_) => {}
+            // Gone: a removed file, or a whole folder moved away.
+            Err(_) => {
+                let mut tracks = state.tracks.lock().unwrap();
+                let before = tracks.len();
+                tracks.retain(|track, _| !track.starts_with(&path));
+                changed |= before != tracks.len();
+            }
+        }
+    }
+    changed
+}
+
+/// Whether `path` is still under the indexed folder; a scan that was running
+/// when the download folder changed must not add its results.
+fn in_root(app: &AppHandle, path: &Path) -> bool {
+    let root = app.state::<LibraryState>().root.lock().unwrap().clone();
+    root.is_some_and(|root| path.starts_with(root))
+}
+
+/// Whether `path` is an indexed track or the cover of one; only those are
+/// served to the offline UI.
+pub fn contains(app: &AppHandle, path: &Path) -> bool {
+    let state = app.state::<LibraryState>();
+    let tracks = state.tracks.lock().unwrap();
+    tracks.contains_key(path)
+        || tracks
+            .values()
+            .any(|track| track.cover.as_deref() == Some(path))
+}
+
+/// Indexed file tagged with `title` by `artist`, ignoring case. Among
+/// several matches the one on `album` wins.
+pub fn find_track(
+    app: &AppHandle,
+    title: &str,
+    artist: Option<&str>,
+    album: Option<&str>,
+) -> Option<PathBuf> {
+    let title = title.trim().to_lowercase();
+    let artist = artist.map(|artist| artist.trim().to_lowercase());
+    let album = album.map(|album| album.trim().to_lowercase());
+    let state = app.state::<LibraryState>();
+    let tracks = state.tracks.lock().unwrap();
+    let mut candidates = tracks.values().filter(|track| {
+        track.display_title().to_lowercase() == title
+            && artist.as_ref().is_none_or(|artist| {
+                track
+                    .artist
+                    .as_ref()
+                    .is_some_and(|value| value.to_lowercase().contains(artist.as_str()))
+            })
+    });
+    let first = candidates.next()?;
+    let on_album = |track: &&LibraryTrack| {
+        album.as_ref().is_some_and(|album| {
+            track
+                .album
+                .as_ref()
+                .is_some_and(|value| value.to_lowercase() == *album)
+        })
+    };
+    if on_album(&first) {
+        return Some(first.path.clone());
+    }
+    Some(candidates.find(on_album).unwrap_or(first).path.clone())
+}
+
+/// Every indexed track.
+pub fn tracks(app: &AppHandle) -> Vec<LibraryTrack> {
+    let state = app.state::<LibraryState>();
+    let tracks = state.tracks.lock().unwrap();
+    tracks.values().cloned().collect()
+}
+
+/// Re-reads `paths` now rather than when the watcher gets to them, for
+/// callers that need the index current straight after changing files.
+pub fn refresh(app: &AppHandle, paths: impl IntoIterator<Item = PathBuf>) {
+    if apply_changes(app, paths.into_iter().collect()) {
+        notify_changed(app);
+    }
+}
+
+fn notify_changed(app: &AppHandle) {
+    save(app);
+    let count = app.state::<LibraryState>().tracks.lock().unwrap().len();
+    let _ = app.emit("library-changed", count);
+}
+
+/// Indexes the download folder and watches it for changes. Called again
+/// whenever the download folder changes; the old index is dropped then.
+pub fn watch(app: &AppHandle) {
+    let Some(root) = crate::desktop::download_dir(app) else {
+        return;
+    };
+    let state = app.state::<LibraryState>();
+    {
+        let mut current = state.root.lock().unwrap();
+        if current.as_ref() != Some(&root) {
+            state.tracks.lock().unwrap().clear();
+            *current = Some(root.clone());
+        }
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if let Ok(event) = event {
+            let _ = sender.send(event.paths);
+        }
+    })
+    .and_then(|mut watcher| {
+        watcher.watch(&root, RecursiveMode::Recursive)?;
+        Ok(watcher)
+    });
+    // Replacing the previous watcher drops its sender, ending its thread.
+    *state.watcher.lock().unwrap() = watcher.ok();
+
+    let app = app.clone();
+    thread::spawn(move || {
+        if scan_dir(&app, &root) {
+            notify_changed(&app);
+        }
+        let mut pending = HashSet::new();
+        loop {
+            match receiver.recv_timeout(WATCH_DEBOUNCE) {
+                Ok(paths) => pending.extend(paths),
+                Err(RecvTimeoutError::Timeout) if !pending.is_empty() => {
+                    if apply_changes(&app, std::mem::take(&mut pending)) {
+                        notify_changed(&app);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    });
+}
+
+// ---------------------------------------------------------------------------
+// Browsing
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LibraryArtist {
+    pub name: String,
+    pub albums: usize,
+    pub tracks: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LibraryAlbum {
+    pub title: String,
+    pub artist: Option<String>,
+    pub year: Option<String>,
+    pub tracks: usize,
+    pub duration_ms: u64,
+    pub cover: Option<PathBuf>,
+}
+
+fn sort_tracks(tracks: &mut [LibraryTrack]) {
+    tracks.sort_by(|a, b| {
+        (a.disc_number, a.track_number, a.display_title()).cmp(&(
+            b.disc_number,
+            b.track_number,
+            b.display_title(),
+        ))
+    });
+}
+
+fn matches(value: Option<&str>, filter: Option<&str>) -> bool {
+    filter.is_none_or(|filter| value.is_some_and(|value| value.eq_ignore_ascii_case(filter)))
+}
+
+// ---------------------------------------------------------------------------
+// Tauri commands
+// ---------------------------------------------------------------------------
+
+/// Re-indexes the whole download folder. Resolves to the number of tracks.
+#[tauri::command]
+pub async fn rescan_library(app: AppHandle) -> Result<usize, String> {
+    let root = crate::desktop::download_dir(&app).ok_or("No download folder available")?;
+    tauri::async_runtime::spawn_blocking(move || {
+        if scan_dir(&app, &root) {
+            notify_changed(&app);
+        }
+        app.state::<LibraryState>().tracks.lock().unwrap().len()
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
+
+/// Tracks whose title, artist, album or file name contain every word of
+/// `query`, ignoring case.
+#[tauri::command]
+pub fn search_library(
+    state: tauri::State<LibraryState>,
+    query: String,
+    limit: Option<usize>,
+) -> Result<Vec<LibraryTrack>, String> {
+    let terms: Vec<String> = query
+        .split_whitespace()
+        .map(|term| term.to_lowercase())
+        .collect();
+    let tracks = state.tracks.lock().map_err(|_| "Failed to lock mutex")?;
+    Ok(tracks
+        .values()
+        .filter(|track| {
+            let haystack = [
+                Some(track.display_title()),
+                track.artist.clone(),
+                track.album.clone(),
+                track.album_artist.clone(),
+                track
+                    .path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned()),
+            ]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join("\n")
+            .to_lowercase();
+            terms.iter().all(|term| haystack.contains(term))
+        })
+        .take(limit.unwrap_or(200))
+        .cloned()
+        .collect())
+}
+
+#[tauri::command]
+pub fn get_library_artists(
+    state: tauri::State<LibraryState>,
+) -> Result<Vec<LibraryArtist>, String> {
+    let tracks = state.tracks.lock().map_err(|_| "Failed to lock mutex")?;
+    let mut artists: BTreeMap<String, (String, HashSet<String>, usize)> = BTreeMap::new();
+    for track in tracks.values() {
+        let Some(name) = track.display_album_artist() else {
+            continue;
+        };
+        let entry = artists
+            .entry(name.to_lowercase())
+            .or_insert_with(|| (name.to_string(), HashSet::new(), 0));
+        if let Some(album) = &track.album {
+            entry.1.insert(album.to_lowercase());
+        }
+        entry.2 += 1;
+    }
+    Ok(artists
+        .into_values()
+        .map(|(name, albums, tracks)| LibraryArtist {
+            name,
+            albums: albums.len(),
+            tracks,
+        })
+        .collect())
+}
+
+/// Albums in the library, optionally only those by `artist`.
+#[tauri::command]
+pub fn get_library_albums(
+    state: tauri::State<LibraryState>,
+    artist: Option<String>,
+) -> Result<Vec<LibraryAlbum>, String> {
+    let tracks = state.tracks.lock().map_err(|_| "Failed to lock mutex")?;
+    let mut albums: BTreeMap<(String, String), LibraryAlbum> = BTreeMap::new();
+    for track in tracks.values() {
+        let Some(title) = &track.album else {
+            continue;
+        };
+        let album_artist = track.display_album_artist();
+        if !matches(album_artist, artist.as_deref()) {
+            continue;
+        }
+        let key = (
+            album_artist.unwrap_or_default().to_lowercase(),
+            title.to_lowercase(),
+        );
+        let album = albums.entry(key).or_insert_with(|| LibraryAlbum {
+            title: title.clone(),
+            artist: album_artist.map(str::to_string),
+            year: None,
+            tracks: 0,
+            duration_ms: 0,
+            cover: None,
+        });
+        album.tracks += 1;
+        album.duration_ms += track.duration_ms;
+        album.year = album.year.take().or_else(|| track.year.clone());
+        album.cover = album.cover.take().or_else(|| track.cover.clone());
+    }
+    Ok(albums.into_values().collect())
+}
+
+/// Tracks filtered by album artist and album, in disc and track order.
+#[tauri::command]
+pub fn get_library_tracks(
+    state: tauri::State<LibraryState>,
+    artist: Option<String>,
+    album: Option<String>,
+) -> Result<Vec<LibraryTrack>, String> {
+    let tracks = state.tracks.lock().map_err(|_| "Failed to lock mutex")?;
+    let mut found: Vec<LibraryTrack> = tracks
+        .values()
+        .filter(|track| matches(track.display_album_artist(), artist.as_deref()))
+        .filter(|track| matches(track.album.as_deref(), album.as_deref()))
+        .cloned()
+        .collect();
+    sort_tracks(&mut found);
+    Ok(found)
+}
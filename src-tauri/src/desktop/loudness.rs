@@ -0,0 +1,599 @@
+use crate::desktop::notifications;
+use lofty::config::WriteOptions;
+use lofty::file::{AudioFile, TaggedFileExt};
+use lofty::tag::{ItemKey, Tag};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::f64::consts::PI;
+use std::fs::{self, File};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use tauri::{AppHandle, Emitter, Manager};
+use tauri_plugin_dialog::DialogExt;
+
+const SETTINGS_FILE: &str = "loudness_settings.json";
+
+const AUDIO_EXTENSIONS: [&str; 9] = [
+    "flac", "mp3", "m4a", "mp4", "ogg", "wav", "aiff", "aif", "wv",
+];
+
+/// Blocks quieter than this never count towards the integrated loudness.
+const ABSOLUTE_GATE: f64 = -70.0;
+/// Blocks this far below the ungated loudness are dropped as well.
+const RELATIVE_GATE: f64 = -10.0;
+
+// ---------------------------------------------------------------------------
+// Settings
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LoudnessSettings {
+    /// Scan every finished download.
+    pub enabled: bool,
+    /// Also write album gain, measured over the tracks in the same folder
+    /// that share an album tag.
+    pub album_gain: bool,
+    /// Target loudness in LUFS; ReplayGain 2.0 uses -18.
+    pub reference_loudness: f64,
+}
+
+impl Default for LoudnessSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            album_gain: true,
+            reference_loudness: -18.0,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct LoudnessState {
+    settings: Mutex<LoudnessSettings>,
+    /// Folders with an album scan running, and whether another download
+    /// landed in them since it started.
+    scanning: Mutex<HashMap<PathBuf, bool>>,
+}
+
+pub fn load(app: &AppHandle) {
+    let state = app.state::<LoudnessState>();
+    *state.settings.lock().unwrap() = crate::store::load_config(app, SETTINGS_FILE);
+}
+
+fn settings(app: &AppHandle) -> LoudnessSettings {
+    app.state::<LoudnessState>()
+        .settings
+        .lock()
+        .unwrap()
+        .clone()
+}
+
+// ---------------------------------------------------------------------------
+// Measurement (EBU R128 / ITU-R BS.1770)
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x0: f64) -> f64 {
+        let y0 = self.b[0] * x0 + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x0, self.x[0]];
+        self.y = [y0, self.y[0]];
+        y0
+    }
+}
+
+/// The two K-weighting stages, a high shelf modelling the head followed by
+/// a high pass, with coefficients derived for `rate`.
+fn k_weighting(rate: f64) -> [Biquad; 2] {
+    let shelf = {
+        let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+        let k = (PI * f0 / rate).tan();
+        let vh = 10f64.powf(gain / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        Biquad {
+            b: [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    };
+    let high_pass = {
+        let (f0, q) = (38.13547087602444, 0.5003270373238773);
+        let k = (PI * f0 / rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        Biquad {
+            b: [1.0, -2.0, 1.0],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    };
+    [shelf, high_pass]
+}
+
+/// Accumulates K-weighted energy in 100 ms steps; four consecutive steps
+/// make one 400 ms gating block with 75% overlap.
+struct Meter {
+    channels: usize,
+    filters: Vec<[Biquad; 2]>,
+    weights: Vec<f64>,
+    /// Frames per 100 ms step.
+    step: usize,
+    frames: usize,
+    energy: f64,
+    steps: Vec<f64>,
+    peak: f32,
+}
+
+impl Meter {
+    fn new(rate: u32, channels: usize) -> Self {
+        // Surround channels of a 5.1 layout are weighted up, LFE is ignored.
+        let weights = (0..channels)
+            .map(|channel| match (channels, channel) {
+                (6, 3) => 0.0,
+                (6, 4..) => 1.41,
+                _ => 1.0,
+            })
+            .collect();
+        Self {
+            channels,
+            filters: vec![k_weighting(rate as f64); channels],
+            weights,
+            step: (rate as usize / 10).max(1),
+            frames: 0,
+            energy: 0.0,
+            steps: Vec::new(),
+            peak: 0.0,
+        }
+    }
+
+    fn push(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (channel, &sample) in frame.iter().enumerate() {
+                self.peak = self.peak.max(sample.abs());
+                let [shelf, high_pass] = &mut self.filters[channel];
+                let weighted = high_pass.process(shelf.process(sample as f64));
+                self.energy += self.weights[channel] * weighted * weighted;
+            }
+            self.frames += 1;
+            if self.frames == self.step {
+                self.steps.push(self.energy);
+                self.energy = 0.0;
+                self.frames = 0;
+            }
+        }
+    }
+
+    fn finish(self) -> Measurement {
+        let block_frames = (4 * self.step) as f64;
+        Measurement {
+            blocks: self
+                .steps
+                .windows(4)
+                .map(|steps| steps.iter().sum::<f64>() / block_frames)
+                .collect(),
+            peak: self.peak,
+        }
+    }
+}
+
+/// Mean-square power of each gating block, and the sample peak.
+struct Measurement {
+    blocks: Vec<f64>,
+    peak: f32,
+}
+
+fn lufs(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// Gated integrated loudness in LUFS, or `None` for silence and files
+/// shorter than one block.
+fn integrated(blocks: &[f64]) -> Option<f64> {
+    let audible: Vec<f64> = blocks
+        .iter()
+        .copied()
+        .filter(|power| lufs(*power) > ABSOLUTE_GATE)
+        .collect();
+    if audible.is_empty() {
+        return None;
+    }
+    let threshold = lufs(mean(&audible)) + RELATIVE_GATE;
+    let gated: Vec<f64> = audible
+        .into_iter()
+        .filter(|power| lufs(*power) > threshold)
+        .collect();
+    (!gated.is_empty()).then(|| lufs(mean(&gated)))
+}
+
+fn measure(path: &Path) -> Result<Measurement, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(ext);
+    }
+    let mut format = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(|e| format!("Unreadable audio: {}", e))?
+        .format;
+    let track = format
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or("No audio track found")?;
+    let track_id = track.id;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|e| format!("Unsupported codec: {}", e))?;
+
+    let mut meter: Option<Meter> = None;
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(format!("Unreadable audio: {}", e)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // A damaged frame shouldn't throw away the whole measurement.
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(e.to_string()),
+        };
+        let spec = *decoded.spec();
+        if buffer
+            .as_ref()
+            .is_none_or(|buffer| buffer.capacity() < decoded.capacity())
+        {
+            buffer = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+        }
+        let buffer = buffer.as_mut().unwrap();
+        buffer.copy_interleaved_ref(decoded);
+        meter
+            .get_or_insert_with(|| Meter::new(spec.rate, spec.channels.count()))
+            .push(buffer.samples());
+    }
+    meter
+        .map(Meter::finish)
+        .ok_or_else(|| "No audio decoded".into())
+}
+
+// ---------------------------------------------------------------------------
+// Tags
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Gain {
+    /// Integrated loudness in LUFS.
+    pub loudness: f64,
+    /// Adjustment to reach the reference loudness, in dB.
+    pub gain: f64,
+    /// Sample peak, 1.0 being full scale.
+    pub peak: f32,
+}
+
+fn gain(blocks: &[f64], peak: f32, reference: f64) -> Option<Gain> {
+    integrated(blocks).map(|loudness| Gain {
+        loudness,
+        gain: reference - loudness,
+        peak,
+    })
+}
+
+fn album_key(path: &Path) -> Option<String> {
+    let file = lofty::read_from_path(path).ok()?;
+    let tag = file.primary_tag().or_else(|| file.first_tag())?;
+    let album = tag.get_string(&ItemKey::AlbumTitle)?;
+    let artist = tag.get_string(&ItemKey::AlbumArtist).unwrap_or_default();
+    Some(format!("{}\u{0}{}", artist, album))
+}
+
+fn write_tags(path: &Path, track: Gain, album: Option<Gain>) -> Result<(), String> {
+    let mut file =
+        lofty::read_from_path(path).map_err(|e| format!("Unsupported audio file: {}", e))?;
+    if file.primary_tag().is_none() {
+        let tag_type = file.primary_tag_type();
+        file.insert_tag(Tag::new(tag_type));
+    }
+    let tag = file.primary_tag_mut().ok_or("File does not support tags")?;
+
+    tag.insert_text(
+        ItemKey::ReplayGainTrackGain,
+        format!("{:.2} dB", track.gain),
+    );
+    tag.insert_text(ItemKey::ReplayGainTrackPeak, format!("{:.6}", track.peak));
+    if let Some(album) = album {
+        tag.insert_text(
+            ItemKey::ReplayGainAlbumGain,
+            format!("{:.2} dB", album.gain),
+        );
+        tag.insert_text(ItemKey::ReplayGainAlbumPeak, format!("{:.6}", album.peak));
+    }
+
+    file.save_to_path(path, WriteOptions::default())
+        .map_err(|e| e.to_string())
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoudnessResult {
+    pub path: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub track: Option<Gain>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub album: Option<Gain>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Measures and tags `files`, which must share a folder. With `album_gain`,
+/// tracks with the same album tag are also measured as one programme.
+fn scan_files(files: &[PathBuf], album_gain: bool, reference: f64) -> Vec<LoudnessResult> {
+    let measured: Vec<(&PathBuf, Result<Measurement, String>)> =
+        files.iter().map(|path| (path, measure(path))).collect();
+
+    let mut albums: HashMap<String, Vec<usize>> = HashMap::new();
+    if album_gain {
+        for (index, (path, result)) in measured.iter().enumerate() {
+            if let (Ok(_), Some(key)) = (result, album_key(path)) {
+                albums.entry(key).or_default().push(index);
+            }
+        }
+    }
+    let mut album_of: HashMap<usize, Gain> = HashMap::new();
+    for members in albums.values() {
+        let parts = members
+            .iter()
+            .filter_map(|&index| measured[index].1.as_ref().ok());
+        let blocks: Vec<f64> = parts
+            .clone()
+            .flat_map(|part| part.blocks.iter().copied())
+            .collect();
+        let peak = parts.map(|part| part.peak).fold(0.0, f32::max);
+        if let Some(album) = gain(&blocks, peak, reference) {
+            album_of.extend(members.iter().map(|&index| (index, album)));
+        }
+    }
+
+    measured
+        .into_iter()
+        .enumerate()
+        .map(|(index, (path, result))| {
+            let album = album_of.get(&index).copied();
+            let outcome = result.and_then(|measurement| {
+                let track = gain(&measurement.blocks, measurement.peak, reference)
+                    .ok_or("Too short or too quiet to measure")?;
+                write_tags(path, track, album)?;
+                Ok(track)
+            });
+            LoudnessResult {
+                path: path.clone(),
+                track: outcome.as_ref().ok().copied(),
+                album: album.filter(|_| outcome.is_ok()),
+                error: outcome.err(),
+            }
+        })
+        .collect()
+}
+
+fn is_audio(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    // Conversions in progress keep their real extension.
+    !name.contains(".transcoding.")
+        && path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| AUDIO_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+fn audio_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.is_file() && is_audio(path))
+                .collect()
+        })
+        .unwrap_or_default();
+    files.sort();
+    files
+}
+
+/// Audio files under `dir`, grouped by folder.
+fn collect_folders(dir: &Path, folders: &mut BTreeMap<PathBuf, Vec<PathBuf>>) {
+    let files = audio_files(dir);
+    if !files.is_empty() {
+        folders.insert(dir.to_path_buf(), files);
+    }
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+            collect_folders(&entry.path(), folders);
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Download hook
+// ---------------------------------------------------------------------------
+
+/// Post-download step: writes ReplayGain tags for a finished download when
+/// scanning is enabled. With album gain the whole folder is rescanned, once
+/// more if further downloads arrive in it meanwhile.
+pub async fn after_download(app: &AppHandle, path: &Path) {
+    let settings = settings(app);
+    if !settings.enabled || !is_audio(path) {
+        return;
+    }
+    let Some(dir) = path.parent().map(Path::to_path_buf) else {
+        return;
+    };
+    let state = app.state::<LoudnessState>();
+
+    if settings.album_gain {
+        let mut scanning = state.scanning.lock().unwrap();
+        if let Some(dirty) = scanning.get_mut(&dir) {
+            *dirty = true;
+            return;
+        }
+        scanning.insert(dir.clone(), false);
+    }
+
+    let result = loop {
+        let files = if settings.album_gain {
+            audio_files(&dir)
+        } else {
+            vec![path.to_path_buf()]
+        };
+        let results = tauri::async_runtime::spawn_blocking(move || {
+            scan_files(&files, settings.album_gain, settings.reference_loudness)
+        })
+        .await
+        .unwrap_or_default();
+        let result = results.into_iter().find(|result| result.path == path);
+
+        if !settings.album_gain {
+            break result;
+        }
+        let mut scanning = state.scanning.lock().unwrap();
+        if scanning.get(&dir) == Some(&true) {
+            scanning.insert(dir.clone(), false);
+            continue;
+        }
+        scanning.remove(&dir);
+        break result;
+    };
+
+    if let Some(error) = result.and_then(|result| result.error) {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        notifications::error(app, "Loudness Scan Failed", &format!("{}\n{}", name, error));
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tauri commands
+// ---------------------------------------------------------------------------
+
+#[tauri::command]
+pub fn get_loudness_settings(
+    state: tauri::State<LoudnessState>,
+) -> Result<LoudnessSettings, String> {
+    let settings = state.settings.lock().map_err(|_| "Failed to lock mutex")?;
+    Ok(settings.clone())
+}
+
+#[tauri::command]
+pub fn set_loudness_settings(
+    app: AppHandle,
+    state: tauri::State<LoudnessState>,
+    settings: LoudnessSettings,
+) -> Result<(), String> {
+    if !settings.reference_loudness.is_finite() || settings.reference_loudness >= 0.0 {
+        return Err("Reference loudness must be below 0 LUFS".into());
+    }
+    crate::store::save_config(&app, SETTINGS_FILE, &settings)?;
+    *state.settings.lock().map_err(|_| "Failed to lock mutex")? = settings;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LoudnessProgress<'a> {
+    done: usize,
+    total: usize,
+    folder: &'a Path,
+}
+
+/// Scans every folder under the download folder, or under a folder the user
+/// picks when `pick` is set, and writes ReplayGain tags, emitting
+/// `loudness-progress` after each folder. Runs regardless of the `enabled`
+/// setting.
+#[tauri::command]
+pub async fn scan_loudness(
+    app: AppHandle,
+    pick: Option<bool>,
+) -> Result<Vec<LoudnessResult>, String> {
+    let settings = settings(&app);
+
+    tauri::async_runtime::spawn_blocking(move || {
+        let dir = if pick.unwrap_or(false) {
+            app.dialog()
+                .file()
+                .set_title("Choose Folder to Scan")
+                .blocking_pick_folder()
+                .ok_or("No folder selected")?
+                .into_path()
+                .map_err(|e| e.to_string())?
+        } else {
+            crate::desktop::download_dir(&app).ok_or("No download folder available")?
+        };
+        if !dir.is_dir() {
+            return Err("Folder not found".to_string());
+        }
+        let mut folders = BTreeMap::new();
+        collect_folders(&dir, &mut folders);
+        let total = folders.values().map(Vec::len).sum();
+        let mut results = Vec::new();
+        for (folder, files) in folders {
+            results.extend(scan_files(
+                &files,
+                settings.album_gain,
+                settings.reference_loudness,
+            ));
+            let _ = app.emit(
+                "loudness-progress",
+                LoudnessProgress {
+                    done: results.len(),
+                    total,
+                    folder: &folder,
+                },
+            );
+        }
+        Ok(results)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
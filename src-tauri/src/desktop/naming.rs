@@ -0,0 +1,301 @@
+use crate::desktop::download_manager::TrackMetadata;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager};
+
+const SETTINGS_FILE: &str = "naming_settings.json";
+
+/// Keeps the full path usable on Windows without long path support.
+const MAX_PATH_LEN: usize = 250;
+
+/// Names that Windows refuses regardless of extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+// ---------------------------------------------------------------------------
+// Settings
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NamingSettings {
+    /// Path template relative to the download folder; `/` separates folders.
+    ///
+    /// Placeholders: `{title}`, `{artist}`, `{album_artist}`, `{album}`,
+    /// `{year}`, `{date}`, `{track}`, `{track_total}`, `{disc}`,
+    /// `{disc_total}`, `{isrc}`, `{ext}`, plus `{filename}` / `{stem}` for the
+    /// name the file would otherwise have been saved under.
+    pub template: String,
+    /// Longest allowed folder or file name, in bytes.
+    pub max_component_len: usize,
+}
+
+impl Default for NamingSettings {
+    fn default() -> Self {
+        Self {
+            template: "{filename}".into(),
+            max_component_len: 200,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct NamingState {
+    settings: Mutex<NamingSettings>,
+}
+
+pub fn load(app: &AppHandle) {
+    let state = app.state::<NamingState>();
+    *state.settings.lock().unwrap() = crate::store::load_config(app, SETTINGS_FILE);
+}
+
+// ---------------------------------------------------------------------------
+// Sanitization
+// ---------------------------------------------------------------------------
+
+/// Makes `name` valid as a single path component on Windows, macOS and
+/// Linux filesystems (including FAT/exFAT on removable drives).
+pub fn sanitize_component(name: &str) -> String {
+    let replaced: String = name
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let trimmed = replaced.trim().trim_end_matches(['.', ' ']);
+
+    let stem = trimmed.split('.').next().unwrap_or_default();
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    {
+        return format!("_{}", trimmed);
+    }
+    if trimmed.is_empty() || trimmed == ".." {
+        return "_".into();
+    }
+    trimmed.to_string()
+}
+
+/// Cuts `value` to at most `max_bytes` bytes on a character boundary.
+fn truncate_bytes(value: &str, max_bytes: usize) -> &str {
+    if value.len() <= max_bytes {
+        return value;
+    }
+    let mut end = max_bytes;
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    &value[..end]
+}
+
+/// Shortens a file name to `max_bytes`, keeping its extension intact.
+pub fn truncate_file_name(name: &str, max_bytes: usize) -> String {
+    let (stem, ext) = split_extension(name);
+    let ext_len = ext.map(|ext| ext.len() + 1).unwrap_or(0);
+    let stem = truncate_bytes(stem, max_bytes.saturating_sub(ext_len).max(1));
+    let stem = stem.trim_end_matches(['.', ' ']);
+    match ext {
+        Some(ext) => format!("{}.{}", stem, ext),
+        None => stem.to_string(),
+    }
+}
+
+fn split_extension(name: &str) -> (&str, Option<&str>) {
+    match name.rfind('.') {
+        Some(index) if index > 0 => (&name[..index], Some(&name[index + 1..])),
+        _ => (name, None),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Rendering
+// ---------------------------------------------------------------------------
+
+fn placeholder(key: &str, metadata: Option<&TrackMetadata>, filename: &str) -> Option<String> {
+    let (stem, ext) = split_extension(filename);
+    match key {
+        "filename" => return Some(filename.to_string()),
+        "stem" => return Some(stem.to_string()),
+        "ext" => return ext.map(str::to_string),
+        _ => {}
+    }
+
+    let metadata = metadata?;
+    let artist = (!metadata.artists.is_empty()).then(|| metadata.artists.join(", "));
+    let date = metadata.release_date.clone();
+    match key {
+        "title" => metadata.title.clone(),
+        "artist" => artist,
+        "album_artist" => metadata
+            .album_artist
+            .clone()
+            .or_else(|| metadata.artists.first().cloned()),
+        "album" => metadata.album.clone(),
+        "year" => date.map(|date| date.chars().take(4).collect()),
+        "date" => date,
+        "track" => metadata.track_number.map(|n| format!("{:02}", n)),
+        "track_total" => metadata.track_total.map(|n| n.to_string()),
+        "disc" => metadata.disc_number.map(|n| n.to_string()),
+        "disc_total" => metadata.disc_total.map(|n| n.to_string()),
+        "isrc" => metadata.isrc.clone(),
+        _ => None,
+    }
+    .filter(|value| !value.trim().is_empty())
+}
+
+/// Substitutes placeholders in one template segment. Unknown or missing
+/// values render empty, and the brackets/separators they leave behind are
+/// cleaned up (`Album ()` -> `Album`, `-05 Title` -> `05 Title`).
+fn render_segment(segment: &str, metadata: Option<&TrackMetadata>, filename: &str) -> String {
+    let mut out = String::new();
+    let mut rest = segment;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let Some(len) = rest[start..].find('}') else {
+            out.push_str(&rest[start..]);
+            rest = "";
+            break;
+        };
+        let key = &rest[start + 1..start + len];
+        // Values are sanitized here so a `/` inside a title can't add folders.
+        if let Some(value) = placeholder(key, metadata, filename) {
+            out.push_str(&value.replace(['/', '\\'], "_"));
+        }
+        rest = &rest[start + len + 1..];
+    }
+    out.push_str(rest);
+
+    for empty in ["()", "[]", "{}"] {
+        out = out.replace(empty, "");
+    }
+    let collapsed = out.split_whitespace().collect::<Vec<_>>().join(" ");
+    collapsed
+        .trim_matches(|c: char| c == '-' || c == '_' || c.is_whitespace())
+        .replace(" .", ".")
+}
+
+fn needs_metadata(template: &str) -> bool {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        if !matches!(&rest[start + 1..start + len], "filename" | "stem" | "ext") {
+            return true;
+        }
+        rest = &rest[start + len + 1..];
+    }
+    false
+}
+
+/// Renders `template` into a relative path. Without metadata, templates that
+/// depend on it fall back to the original file name.
+pub fn render(
+    settings: &NamingSettings,
+    template: &str,
+    metadata: Option<&TrackMetadata>,
+    filename: &str,
+) -> PathBuf {
+    let template = if metadata.is_none() && needs_metadata(template) {
+        "{filename}"
+    } else {
+        template
+    };
+
+    let mut components: Vec<String> = template
+        .split(['/', '\\'])
+        .map(|segment| render_segment(segment, metadata, filename))
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| sanitize_component(&segment))
+        .collect();
+    if components.is_empty() {
+        components.push(sanitize_component(filename));
+    }
+
+    let last = components.len() - 1;
+    for (index, component) in components.iter_mut().enumerate() {
+        *component = if index == last {
+            truncate_file_name(component, settings.max_component_len)
+        } else {
+            sanitize_component(truncate_bytes(component, settings.max_component_len))
+        };
+    }
+    components.iter().collect()
+}
+
+/// Full destination for a download below `base`, shortening the file name
+/// when the complete path would exceed [`MAX_PATH_LEN`].
+pub fn destination(
+    app: &AppHandle,
+    base: &Path,
+    metadata: Option<&TrackMetadata>,
+    filename: &str,
+) -> PathBuf {
+    let settings = app.state::<NamingState>().settings.lock().unwrap().clone();
+    let path = base.join(render(&settings, &settings.template, metadata, filename));
+    fit_path_len(path)
+}
+
+fn fit_path_len(path: PathBuf) -> PathBuf {
+    let len = path.as_os_str().len();
+    if len <= MAX_PATH_LEN {
+        return path;
+    }
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        return path;
+    };
+    let name = name.to_string_lossy();
+    let budget = name.len().saturating_sub(len - MAX_PATH_LEN).max(16);
+    parent.join(truncate_file_name(&name, budget))
+}
+
+// ---------------------------------------------------------------------------
+// Tauri commands
+// ---------------------------------------------------------------------------
+
+#[tauri::command]
+pub fn get_naming_settings(state: tauri::State<NamingState>) -> Result<NamingSettings, String> {
+    let settings = state.settings.lock().map_err(|_| "Failed to lock mutex")?;
+    Ok(settings.clone())
+}
+
+#[tauri::command]
+pub fn set_naming_settings(
+    app: AppHandle,
+    state: tauri::State<NamingState>,
+    settings: NamingSettings,
+) -> Result<(), String> {
+    if settings.template.trim().is_empty() {
+        return Err("Template must not be empty".into());
+    }
+    crate::store::save_config(&app, SETTINGS_FILE, &settings)?;
+    *state.settings.lock().map_err(|_| "Failed to lock mutex")? = settings;
+    Ok(())
+}
+
+/// Shows where a track would be saved. `template` previews an unsaved
+/// template; the configured one is used otherwise.
+#[tauri::command]
+pub fn preview_download_path(
+    app: AppHandle,
+    state: tauri::State<NamingState>,
+    metadata: Option<TrackMetadata>,
+    filename: String,
+    template: Option<String>,
+) -> Result<PathBuf, String> {
+    let base = crate::desktop::download_dir(&app).ok_or("No download folder available")?;
+    let settings = state
+        .settings
+        .lock()
+        .map_err(|_| "Failed to lock mutex")?
+        .clone();
+    let template = template.unwrap_or_else(|| settings.template.clone());
+    let relative = render(&settings, &template, metadata.as_ref(), &filename);
+    Ok(fit_path_len(base.join(relative)))
+}
@@ -0,0 +1,233 @@
+use crate::desktop::cover_cache;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+
+const SETTINGS_FILE: &str = "notification_settings.json";
+
+// ---------------------------------------------------------------------------
+// Settings
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NotificationSettings {
+    pub track_change: bool,
+    pub download_complete: bool,
+    pub errors: bool,
+    /// Skip track-change notifications while the player window has focus.
+    pub only_when_unfocused: bool,
+    pub show_cover: bool,
+    /// Offer Skip/Pause buttons where the notification server supports them.
+    pub show_actions: bool,
+    /// How long a track has to stay current before it is announced. Skipping
+    /// through several tracks within this window only announces the last one.
+    pub track_change_delay_ms: u64,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        Self {
+            track_change: true,
+            download_complete: true,
+            errors: true,
+            only_when_unfocused: true,
+            show_cover: true,
+            show_actions: true,
+            track_change_delay_ms: 1500,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// State
+// ---------------------------------------------------------------------------
+
+#[derive(Default)]
+pub struct NotificationState {
+    settings: Mutex<NotificationSettings>,
+    /// Bumped on every track change; a pending notification is dropped when a
+    /// newer track arrived while it was waiting.
+    generation: AtomicU64,
+}
+
+pub fn load(app: &AppHandle) {
+    let state = app.state::<NotificationState>();
+    *state.settings.lock().unwrap() = crate::store::load_config(app, SETTINGS_FILE);
+}
+
+fn settings(app: &AppHandle) -> NotificationSettings {
+    app.state::<NotificationState>()
+        .settings
+        .lock()
+        .unwrap()
+        .clone()
+}
+
+// ---------------------------------------------------------------------------
+// Notifications
+// ---------------------------------------------------------------------------
+
+pub fn track_changed(app: &AppHandle, title: &str, artist: &str, image: Option<String>) {
+    let settings = settings(app);
+    if !settings.track_change {
+        return;
+    }
+
+    let state = app.state::<NotificationState>();
+    let generation = state.generation.fetch_add(1, Ordering::SeqCst) + 1;
+    let body = format!("{}\n{}", title.trim(), artist.trim());
+    let app = app.clone();
+
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(settings.track_change_delay_ms));
+
+        let state = app.state::<NotificationState>();
+        if state.generation.load(Ordering::SeqCst) != generation {
+            return;
+        }
+        if settings.only_when_unfocused {
+            let focused = app
+                .get_webview_window("main")
+                .map(|win| win.is_focused().unwrap_or(false))
+                .unwrap_or(false);
+            if focused {
+                return;
+            }
+        }
+
+        let cover = match image {
+            Some(url) if settings.show_cover => {
+                tauri::async_runtime::block_on(cover_cache::fetch(&app, &url, Some(256)))
+            }
+            _ => None,
+        };
+        show(
+            &app,
+            "Now Playing",
+            &body,
+            cover.as_deref(),
+            settings.show_actions,
+        );
+    });
+}
+
+pub fn download_complete(app: &AppHandle, path: Option<&Path>) {
+    if !settings(app).download_complete {
+        return;
+    }
+    let name = path
+        .and_then(|p| p.file_name())
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "File saved".into());
+    show(app, "Download Complete", &name, None, false);
+}
+
+pub fn error(app: &AppHandle, title: &str, message: &str) {
+    if !settings(app).errors {
+        return;
+    }
+    show(app, title, message, None, false);
+}
+
+// ---------------------------------------------------------------------------
+// Platform backends
+// ---------------------------------------------------------------------------
+
+/// Linux talks to the notification server directly so it can attach the cover
+/// as an image hint and listen for action invocations, which the notification
+/// plugin does not expose on desktop.
+#[cfg(target_os = "linux")]
+fn show(app: &AppHandle, title: &str, body: &str, image: Option<&Path>, media_actions: bool) {
+    use std::sync::OnceLock;
+    use tauri::Emitter;
+
+    static SUPPORTS_ACTIONS: OnceLock<bool> = OnceLock::new();
+    let supports_actions = *SUPPORTS_ACTIONS.get_or_init(|| {
+        notify_rust::get_capabilities()
+            .map(|caps| caps.iter().any(|cap| cap == "actions"))
+            .unwrap_or(false)
+    });
+
+    let mut notification = notify_rust::Notification::new();
+    notification.appname("Monochrome").summary(title).body(body);
+    match image {
+        Some(path) => {
+            notification.image_path(&path.to_string_lossy());
+        }
+        None => {
+            notification.auto_icon();
+        }
+    }
+
+    let with_actions = media_actions && supports_actions;
+    if with_actions {
+        notification
+            .action("default", "Show Player")
+            .action("pause", "Pause")
+            .action("next", "Skip");
+    }
+
+    let Ok(handle) = notification.show() else {
+        return;
+    };
+
+    if with_actions {
+        let app = app.clone();
+        thread::spawn(move || {
+            handle.wait_for_action(|action| match action {
+                "default" => {
+                    if let Some(window) = app.get_webview_window("main") {
+                        let _ = window.show();
+                        let _ = window.set_focus();
+                    }
+                }
+                "pause" => {
+                    let _ = app.emit("media-toggle", ());
+                }
+                "next" => {
+                    let _ = app.emit("media-next", ());
+                }
+                _ => {}
+            });
+        });
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn show(app: &AppHandle, title: &str, body: &str, image: Option<&Path>, _media_actions: bool) {
+    use tauri_plugin_notification::NotificationExt;
+
+    let mut builder = app.notification().builder().title(title).body(body);
+    if let Some(path) = image {
+        builder = builder.icon(path.to_string_lossy());
+    }
+    let _ = builder.show();
+}
+
+// ---------------------------------------------------------------------------
+// Tauri commands
+// ---------------------------------------------------------------------------
+
+#[tauri::command]
+pub fn get_notification_settings(
+    state: tauri::State<NotificationState>,
+) -> Result<NotificationSettings, String> {
+    let settings = state.settings.lock().map_err(|_| "Failed to lock mutex")?;
+    Ok(settings.clone())
+}
+
+#[tauri::command]
+pub fn set_notification_settings(
+    app: AppHandle,
+    state: tauri::State<NotificationState>,
+    settings: NotificationSettings,
+) -> Result<(), String> {
+    crate::store::save_config(&app, SETTINGS_FILE, &settings)?;
+    *state.settings.lock().map_err(|_| "Failed to lock mutex")? = settings;
+    Ok(())
+}
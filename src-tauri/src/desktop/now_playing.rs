@@ -0,0 +1,281 @@
+use crate::desktop::cover_cache;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Manager};
+use tauri_plugin_dialog::DialogExt;
+
+const SETTINGS_FILE: &str = "now_playing_settings.json";
+
+// ---------------------------------------------------------------------------
+// Settings
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NowPlayingSettings {
+    pub enabled: bool,
+    /// Output folder; defaults to `now_playing` inside the app data dir.
+    /// Only changed through `pick_now_playing_folder`.
+    pub folder: Option<PathBuf>,
+    /// Text written to `now_playing.txt`. Supports `{title}`, `{artist}` and
+    /// `{state}` (`Playing` / `Paused`).
+    pub template: String,
+    /// Text written while nothing is playing or playback is paused. Falls back
+    /// to `template` when unset.
+    pub paused_template: Option<String>,
+    pub write_json: bool,
+    pub write_cover: bool,
+}
+
+impl Default for NowPlayingSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            folder: None,
+            template: "{artist} - {title}".into(),
+            paused_template: None,
+            write_json: true,
+            write_cover: true,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// State
+// ---------------------------------------------------------------------------
+
+/// What the web player reported through `update_discord_presence`.
+#[derive(Debug, Clone)]
+pub struct Track {
+    pub title: String,
+    pub artist: String,
+    /// Remote cover URL; `None` when the player only has the app logo.
+    pub image: Option<String>,
+    pub is_paused: bool,
+    pub current_sec: f64,
+}
+
+/// Everything that ends up in the output files, apart from the position.
+#[derive(Debug, Clone, PartialEq)]
+struct Written {
+    text: String,
+    title: String,
+    artist: String,
+    image: Option<String>,
+    is_paused: bool,
+}
+
+#[derive(Default)]
+pub struct NowPlayingState {
+    settings: Mutex<NowPlayingSettings>,
+    last_written: Mutex<Option<Written>>,
+    last_image: Mutex<Option<String>>,
+    /// Bumped for every cover change; a fetch that finishes after a newer
+    /// change leaves the file alone.
+    cover_generation: Mutex<u64>,
+}
+
+pub fn load(app: &AppHandle) {
+    let state = app.state::<NowPlayingState>();
+    *state.settings.lock().unwrap() = crate::store::load_config(app, SETTINGS_FILE);
+}
+
+fn output_dir(app: &AppHandle, settings: &NowPlayingSettings) -> Option<PathBuf> {
+    match &settings.folder {
+        Some(folder) => Some(folder.clone()),
+        None => app
+            .path()
+            .app_data_dir()
+            .ok()
+            .map(|dir| dir.join("now_playing")),
+    }
+}
+
+fn render(template: &str, track: &Track) -> String {
+    template
+        .replace("{title}", &track.title)
+        .replace("{artist}", &track.artist)
+        .replace(
+            "{state}",
+            if track.is_paused { "Paused" } else { "Playing" },
+        )
+}
+
+// ---------------------------------------------------------------------------
+// Writer
+// ---------------------------------------------------------------------------
+
+/// Mirrors the current track into the configured folder. Files are only
+/// rewritten when their content changes so scene software isn't reloading
+/// them on every progress update.
+pub fn publish(app: &AppHandle, track: &Track) {
+    let state = app.state::<NowPlayingState>();
+    let settings = state.settings.lock().unwrap().clone();
+    if !settings.enabled {
+        return;
+    }
+    let Some(dir) = output_dir(app, &settings) else {
+        return;
+    };
+
+    let template = match (&settings.paused_template, track.is_paused) {
+        (Some(paused), true) => paused,
+        _ => &settings.template,
+    };
+    let text = render(template, track);
+
+    {
+        let current = Written {
+            text: text.clone(),
+            title: track.title.clone(),
+            artist: track.artist.clone(),
+            image: track.image.clone(),
+            is_paused: track.is_paused,
+        };
+        let mut last_written = state.last_written.lock().unwrap();
+        if last_written.as_ref() == Some(&current) {
+            return;
+        }
+        *last_written = Some(current);
+    }
+
+    let _ = crate::store::write_atomic(&dir.join("now_playing.txt"), text.as_bytes());
+
+    if settings.write_json {
+        let updated_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let _ = crate::store::write_json(
+            &dir.join("now_playing.json"),
+            &json!({
+                "title": track.title,
+                "artist": track.artist,
+                "image": track.image,
+                "isPaused": track.is_paused,
+                "currentSec": track.current_sec,
+                "text": text,
+                "updatedAt": updated_at,
+            }),
+        );
+    }
+
+    if settings.write_cover {
+        let mut last_image = state.last_image.lock().unwrap();
+        if *last_image != track.image {
+            *last_image = track.image.clone();
+            write_cover(app, dir.join("cover.jpg"), track.image.clone());
+        }
+    }
+}
+
+fn write_cover(app: &AppHandle, path: PathBuf, image: Option<String>) {
+    let state = app.state::<NowPlayingState>();
+    let generation = {
+        let mut current = state.cover_generation.lock().unwrap();
+        *current += 1;
+        *current
+    };
+    let Some(url) = image else {
+        let _ = fs::remove_file(path);
+        return;
+    };
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let cached = cover_cache::fetch(&app, &url, Some(512)).await;
+        let state = app.state::<NowPlayingState>();
+        // Held while writing, so a newer change waits for this one.
+        let current = state.cover_generation.lock().unwrap();
+        if *current != generation {
+            return;
+        }
+        match cached.and_then(|cached| fs::read(cached).ok()) {
+            Some(bytes) => {
+                let _ = crate::store::write_atomic(&path, &bytes);
+            }
+            None => {
+                let _ = fs::remove_file(&path);
+            }
+        }
+    });
+}
+
+/// Makes the next update rewrite every file.
+fn invalidate(state: &NowPlayingState) -> Result<(), String> {
+    *state
+        .last_written
+        .lock()
+        .map_err(|_| "Failed to lock mutex")? = None;
+    *state
+        .last_image
+        .lock()
+        .map_err(|_| "Failed to lock mutex")? = None;
+    Ok(())
+}
+
+/// Stores the output folder; `None` goes back to the default.
+fn set_folder(app: &AppHandle, folder: Option<PathBuf>) -> Result<NowPlayingSettings, String> {
+    let state = app.state::<NowPlayingState>();
+    let mut settings = state.settings.lock().map_err(|_| "Failed to lock mutex")?;
+    settings.folder = folder;
+    crate::store::save_config(app, SETTINGS_FILE, &*settings)?;
+    let settings = settings.clone();
+    invalidate(&state)?;
+    Ok(settings)
+}
+
+// ---------------------------------------------------------------------------
+// Tauri commands
+// ---------------------------------------------------------------------------
+
+#[tauri::command]
+pub fn get_now_playing_settings(
+    state: tauri::State<NowPlayingState>,
+) -> Result<NowPlayingSettings, String> {
+    let settings = state.settings.lock().map_err(|_| "Failed to lock mutex")?;
+    Ok(settings.clone())
+}
+
+#[tauri::command]
+pub fn set_now_playing_settings(
+    app: AppHandle,
+    state: tauri::State<NowPlayingState>,
+    mut settings: NowPlayingSettings,
+) -> Result<(), String> {
+    {
+        let mut current = state.settings.lock().map_err(|_| "Failed to lock mutex")?;
+        // The folder is only set through the native picker.
+        settings.folder = current.folder.clone();
+        crate::store::save_config(&app, SETTINGS_FILE, &settings)?;
+        *current = settings;
+    }
+    invalidate(&state)
+}
+
+/// Asks the user for the output folder and stores it.
+#[tauri::command]
+pub async fn pick_now_playing_folder(app: AppHandle) -> Result<NowPlayingSettings, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let path = app
+            .dialog()
+            .file()
+            .set_title("Choose Now Playing Folder")
+            .blocking_pick_folder()
+            .ok_or("No folder selected")?
+            .into_path()
+            .map_err(|e| e.to_string())?;
+        set_folder(&app, Some(path))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Goes back to the default output folder.
+#[tauri::command]
+pub fn reset_now_playing_folder(app: AppHandle) -> Result<NowPlayingSettings, String> {
+    set_folder(&app, None)
+}
@@ -0,0 +1,167 @@
+use crate::desktop::library;
+use percent_encoding::percent_decode_str;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use tauri::http::{header, Method, Request, Response, StatusCode};
+use tauri::{AppHandle, Manager};
+
+/// Scheme library files are streamed from. The offline UI builds its URLs
+/// with `convertFileSrc(path, "library")`.
+pub const SCHEME: &str = "library";
+
+/// Open-ended range requests are answered in pieces of at most this size,
+/// so seeking through a long FLAC doesn't read it all into memory.
+const MAX_CHUNK: u64 = 2 * 1024 * 1024;
+
+/// Page of the bundled offline UI inside `dist/`.
+const OFFLINE_PAGE: &str = "offline.html";
+
+fn mime_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "flac" => "audio/flac",
+        "mp3" => "audio/mpeg",
+        "m4a" | "mp4" => "audio/mp4",
+        "ogg" | "opus" => "audio/ogg",
+        "wav" => "audio/wav",
+        "aiff" | "aif" => "audio/aiff",
+        "wv" => "audio/x-wavpack",
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        _ => "application/octet-stream",
+    }
+}
+
+/// `/%2Fmusic%2FSong.flac` -> `/music/Song.flac`.
+fn requested_path(request: &Request<Vec<u8>>) -> Option<PathBuf> {
+    let encoded = request.uri().path().trim_start_matches('/');
+    let decoded = percent_decode_str(encoded).decode_utf8().ok()?;
+    Some(PathBuf::from(decoded.as_ref()))
+}
+
+/// Parses the first range of a `bytes=` header against a file of `size`
+/// bytes. Returns the inclusive start and end, or `None` if unsatisfiable.
+fn parse_range(value: &str, size: u64) -> Option<(u64, u64)> {
+    if size == 0 {
+        return None;
+    }
+    let spec = value.strip_prefix("bytes=")?.split(',').next()?.trim();
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = if start.is_empty() {
+        // `bytes=-500`: the last 500 bytes.
+        let length: u64 = end.parse().ok()?;
+        if length == 0 {
+            return None;
+        }
+        (size.saturating_sub(length), size - 1)
+    } else {
+        let start: u64 = start.parse().ok()?;
+        let end = match end {
+            "" => size - 1,
+            end => end.parse::<u64>().ok()?.min(size - 1),
+        };
+        (start, end)
+    };
+    (start <= end && start < size).then_some((start, end))
+}
+
+fn serve(app: &AppHandle, request: &Request<Vec<u8>>) -> Result<Response<Vec<u8>>, StatusCode> {
+    let path = requested_path(request).ok_or(StatusCode::BAD_REQUEST)?;
+    // Only indexed tracks and their covers are served, never arbitrary files.
+    if !library::contains(app, &path) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let mut file = File::open(&path).map_err(|_| StatusCode::NOT_FOUND)?;
+    let size = file
+        .metadata()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .len();
+    let head = request.method() == Method::HEAD;
+
+    let builder = Response::builder()
+        .header(header::CONTENT_TYPE, mime_type(&path))
+        .header(header::ACCEPT_RANGES, "bytes");
+    let range = request
+        .headers()
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        // Files too large for one chunk are sent as an open-ended range
+        // even when none was asked for; the player requests the rest.
+        .or((size > MAX_CHUNK && !head).then_some("bytes=0-"));
+
+    let Some(range) = range else {
+        let mut body = Vec::new();
+        if !head {
+            file.read_to_end(&mut body)
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        }
+        return builder
+            .status(StatusCode::OK)
+            .header(header::CONTENT_LENGTH, size)
+            .body(body)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR);
+    };
+
+    let Some((start, end)) = parse_range(range, size) else {
+        return builder
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{}", size))
+            .body(Vec::new())
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR);
+    };
+    let end = end.min(start + MAX_CHUNK - 1);
+    let length = end - start + 1;
+    let mut body = Vec::new();
+    if !head {
+        file.seek(SeekFrom::Start(start))
+            .and_then(|_| file.take(length).read_to_end(&mut body))
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    }
+    builder
+        .status(StatusCode::PARTIAL_CONTENT)
+        .header(
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", start, end, size),
+        )
+        .header(header::CONTENT_LENGTH, length)
+        .body(body)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Answers a request on the [`SCHEME`] protocol.
+pub fn handle(app: &AppHandle, request: &Request<Vec<u8>>) -> Response<Vec<u8>> {
+    serve(app, request).unwrap_or_else(|status| {
+        Response::builder()
+            .status(status)
+            .body(Vec::new())
+            .unwrap_or_default()
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Tauri commands
+// ---------------------------------------------------------------------------
+
+/// Switches the main window to the bundled offline UI, e.g. from the
+/// "Connection Failed" page when the source instance is unreachable.
+#[tauri::command]
+pub fn open_offline_library(app: AppHandle) -> Result<(), String> {
+    // Windows serves bundled pages over http; the other platforms use the
+    // `tauri` scheme.
+    let base = if cfg!(windows) {
+        "http://tauri.localhost/"
+    } else {
+        "tauri://localhost/"
+    };
+    let url = tauri::Url::parse(base)
+        .and_then(|base| base.join(OFFLINE_PAGE))
+        .map_err(|e| e.to_string())?;
+    let window = app
+        .get_webview_window("main")
+        .ok_or("Main window not found")?;
+    window.navigate(url).map_err(|e| e.to_string())
+}
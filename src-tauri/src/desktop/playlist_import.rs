@@ -0,0 +1,635 @@
+use crate::desktop::{library, naming};
+use lofty::file::{AudioFile, TaggedFileExt};
+use lofty::tag::{ItemKey, Tag};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{AppHandle, Emitter, Manager};
+use tauri_plugin_dialog::DialogExt;
+
+const REPORTS_DIR: &str = "Playlist Imports";
+
+// ---------------------------------------------------------------------------
+// State
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaylistSource {
+    M3u,
+    Xspf,
+    Csv,
+}
+
+/// One playlist entry, reduced to what the catalogue can be searched by.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportedEntry {
+    /// Position in the source playlist.
+    pub index: usize,
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    pub album: Option<String>,
+    pub duration_ms: Option<u64>,
+    pub isrc: Option<String>,
+    /// File path or URL the entry pointed at in the source playlist.
+    pub location: Option<String>,
+}
+
+/// Sent to the web app, which searches the catalogue for every entry and
+/// answers with `report_playlist_matches`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlaylistMatchRequest {
+    pub id: String,
+    pub name: String,
+    pub source: PlaylistSource,
+    pub file: PathBuf,
+    pub entries: Vec<ImportedEntry>,
+    /// Lines that held neither a usable title nor a location.
+    pub skipped: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlaylistImportReport {
+    pub id: String,
+    pub name: String,
+    pub total: usize,
+    pub matched: usize,
+    pub unmatched: Vec<ImportedEntry>,
+    /// CSV of the unmatched entries, written to the download folder.
+    pub report: Option<PathBuf>,
+}
+
+/// Imports waiting for the web app's answer. Only kept for the session.
+#[derive(Default)]
+pub struct PlaylistImportState {
+    pending: Mutex<HashMap<String, PlaylistMatchRequest>>,
+}
+
+// ---------------------------------------------------------------------------
+// Normalization
+// ---------------------------------------------------------------------------
+
+fn clean(value: &str) -> Option<String> {
+    let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!value.is_empty()).then_some(value)
+}
+
+/// ISRCs are 12 alphanumerics; exports write them with dashes, lowercase or
+/// as a `isrc:` URN.
+fn clean_isrc(value: &str) -> Option<String> {
+    let value = value.trim();
+    let value = value
+        .strip_prefix("urn:isrc:")
+        .or_else(|| value.strip_prefix("isrc:"))
+        .unwrap_or(value);
+    let isrc: String = value
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_ascii_uppercase();
+    (isrc.len() == 12).then_some(isrc)
+}
+
+/// `3:25`, `1:02:03`, `205` (seconds) or `205000` with `millis`.
+fn parse_duration(value: &str, millis: bool) -> Option<u64> {
+    let value = value.trim();
+    if value.contains(':') {
+        let mut secs = 0f64;
+        for part in value.split(':') {
+            secs = secs * 60.0 + part.trim().parse::<f64>().ok()?;
+        }
+        return Some((secs * 1000.0) as u64);
+    }
+    let number: f64 = value.parse().ok()?;
+    if number <= 0.0 {
+        return None;
+    }
+    Some(if millis { number } else { number * 1000.0 } as u64)
+}
+
+/// Splits the `Artist - Title` most players write when there are no
+/// separate fields.
+fn split_display(value: &str) -> (Option<String>, Option<String>) {
+    match value.split_once(" - ") {
+        Some((artist, title)) => (clean(artist), clean(title)),
+        None => (None, clean(value)),
+    }
+}
+
+/// Playlists from older players are often Latin-1 rather than UTF-8.
+fn decode(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => bytes.iter().map(|&b| b as char).collect(),
+    }
+}
+
+fn text(tag: &Tag, key: ItemKey) -> Option<String> {
+    tag.get_string(&key).and_then(clean)
+}
+
+/// Fills gaps in `entry` from the tags of the file it points at, when that
+/// file is still on disk. Only files next to the playlist or in the library
+/// are read.
+fn fill_from_file(app: &AppHandle, entry: &mut ImportedEntry, base: &Path) {
+    let Some(location) = entry.location.as_deref() else {
+        return;
+    };
+    let path = location
+        .strip_prefix("file://")
+        .map(|rest| {
+            percent_encoding::percent_decode_str(rest)
+                .decode_utf8_lossy()
+                .into_owned()
+        })
+        .unwrap_or_else(|| location.to_string());
+    if path.contains("://") {
+        return;
+    }
+    let path = base.join(path);
+    // Resolved, so `..` can't climb out of the playlist's folder.
+    let beside = fs::canonicalize(&path)
+        .and_then(|path| Ok(path.starts_with(fs::canonicalize(base)?)))
+        .unwrap_or(false);
+    if !path.is_file() || !(beside || library::contains(app, &path)) {
+        return;
+    }
+    let Ok(file) = lofty::read_from_path(&path) else {
+        return;
+    };
+    if let Some(tag) = file.primary_tag().or_else(|| file.first_tag()) {
+        entry.title = entry
+            .title
+            .take()
+            .or_else(|| text(tag, ItemKey::TrackTitle));
+        entry.artist = entry
+            .artist
+            .take()
+            .or_else(|| text(tag, ItemKey::TrackArtist));
+        entry.album = entry
+            .album
+            .take()
+            .or_else(|| text(tag, ItemKey::AlbumTitle));
+        entry.isrc = entry
+            .isrc
+            .take()
+            .or_else(|| text(tag, ItemKey::Isrc).and_then(|isrc| clean_isrc(&isrc)));
+    }
+    if entry.duration_ms.is_none() {
+        let duration = file.properties().duration().as_millis() as u64;
+        entry.duration_ms = (duration > 0).then_some(duration);
+    }
+}
+
+/// Falls back to the file name (`Artist - Title.mp3`) for entries without
+/// any metadata.
+fn fill_from_name(entry: &mut ImportedEntry) {
+    if entry.title.is_some() {
+        return;
+    }
+    let Some(location) = entry.location.as_deref() else {
+        return;
+    };
+    let name = location.rsplit(['/', '\\']).next().unwrap_or(location);
+    let stem = name.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(name);
+    let stem = percent_encoding::percent_decode_str(stem).decode_utf8_lossy();
+    let (artist, title) = split_display(&stem);
+    entry.artist = entry.artist.take().or(artist);
+    entry.title = title;
+}
+
+// ---------------------------------------------------------------------------
+// Parsing
+// ---------------------------------------------------------------------------
+
+struct Parsed {
+    name: Option<String>,
+    entries: Vec<ImportedEntry>,
+    skipped: usize,
+}
+
+fn detect_source(path: &Path, content: &str) -> Result<PlaylistSource, String> {
+    let ext = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "m3u" | "m3u8" => Ok(PlaylistSource::M3u),
+        "xspf" => Ok(PlaylistSource::Xspf),
+        "csv" => Ok(PlaylistSource::Csv),
+        _ => {
+            let start = content.trim_start();
+            if start.starts_with("#EXTM3U") {
+                Ok(PlaylistSource::M3u)
+            } else if start.starts_with("<?xml") || start.starts_with("<playlist") {
+                Ok(PlaylistSource::Xspf)
+            } else {
+                Err("Unrecognized playlist format".into())
+            }
+        }
+    }
+}
+
+/// Reads `#EXTINF:<secs> <attrs>,<Artist> - <Title>` plus the `#EXTART`,
+/// `#EXTALB` and `#PLAYLIST` extensions; every other line is a location.
+fn parse_m3u(content: &str) -> Parsed {
+    let mut name = None;
+    let mut entries = Vec::new();
+    let mut skipped = 0;
+    let mut current = ImportedEntry::default();
+
+    for line in content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            let (head, display) = info.split_once(',').unwrap_or((info, ""));
+            let secs = head.split_whitespace().next().unwrap_or("");
+            current.duration_ms = parse_duration(secs, false);
+            let (artist, title) = split_display(display);
+            current.artist = artist;
+            current.title = title;
+        } else if let Some(artist) = line.strip_prefix("#EXTART:") {
+            current.artist = clean(artist);
+        } else if let Some(album) = line.strip_prefix("#EXTALB:") {
+            current.album = clean(album);
+        } else if let Some(title) = line.strip_prefix("#PLAYLIST:") {
+            name = clean(title);
+        } else if line.starts_with('#') {
+            continue;
+        } else {
+            current.location = Some(line.to_string());
+            current.index = entries.len() + skipped;
+            entries.push(std::mem::take(&mut current));
+        }
+    }
+    if current.title.is_some() {
+        current.index = entries.len() + skipped;
+        entries.push(current);
+    } else if current.artist.is_some() {
+        skipped += 1;
+    }
+    Parsed {
+        name,
+        entries,
+        skipped,
+    }
+}
+
+fn unescape_xml(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..end];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Every `<tag>` element directly inside `xml`, ignoring attributes and
+/// CDATA wrappers.
+fn elements<'a>(xml: &'a str, tag: &'a str) -> impl Iterator<Item = String> + 'a {
+    let open = format!("<{}", tag);
+    let close = format!("</{}>", tag);
+    let mut rest = xml;
+    std::iter::from_fn(move || loop {
+        let start = rest.find(&open)?;
+        let after = &rest[start + open.len()..];
+        // `<title` must not match `<titles>`.
+        if !after.starts_with(['>', ' ', '\t', '\r', '\n', '/']) {
+            rest = after;
+            continue;
+        }
+        let body_start = after.find('>')? + 1;
+        if after[..body_start].ends_with("/>") {
+            rest = &after[body_start..];
+            return Some(String::new());
+        }
+        let body = &after[body_start..];
+        let end = body.find(&close)?;
+        rest = &body[end + close.len()..];
+        let inner = body[..end].trim();
+        let inner = inner
+            .strip_prefix("<![CDATA[")
+            .and_then(|inner| inner.strip_suffix("]]>"))
+            .map(str::to_string)
+            .unwrap_or_else(|| unescape_xml(inner));
+        return Some(inner);
+    })
+}
+
+fn element(xml: &str, tag: &str) -> Option<String> {
+    elements(xml, tag).next().and_then(|value| clean(&value))
+}
+
+fn parse_xspf(content: &str) -> Result<Parsed, String> {
+    let list_start = content
+        .find("<trackList")
+        .ok_or("The file has no <trackList>")?;
+    let name = element(&content[..list_start], "title");
+
+    let mut entries = Vec::new();
+    let mut skipped = 0;
+    for (index, track) in elements(&content[list_start..], "track").enumerate() {
+        let entry = ImportedEntry {
+            index,
+            artist: element(&track, "creator"),
+            title: element(&track, "title"),
+            album: element(&track, "album"),
+            duration_ms: element(&track, "duration").and_then(|ms| parse_duration(&ms, true)),
+            isrc: elements(&track, "identifier").find_map(|id| clean_isrc(&id)),
+            location: element(&track, "location"),
+        };
+        if entry.title.is_none() && entry.location.is_none() {
+            skipped += 1;
+        } else {
+            entries.push(entry);
+        }
+    }
+    Ok(Parsed {
+        name,
+        entries,
+        skipped,
+    })
+}
+
+/// Index of the first header matching one of `names`.
+fn column(headers: &[String], names: &[&str]) -> Option<usize> {
+    names
+        .iter()
+        .find_map(|name| headers.iter().position(|header| header == name))
+}
+
+/// Handles headed exports from Exportify, Soundiiz, TuneMyMusic and
+/// spreadsheets; column names are matched case-insensitively.
+fn parse_csv(content: &str) -> Result<Parsed, String> {
+    let delimiter = match content.lines().next() {
+        Some(header) if header.matches(';').count() > header.matches(',').count() => b';',
+        Some(header) if header.contains('\t') && !header.contains(',') => b'\t',
+        _ => b',',
+    };
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(content.as_bytes());
+    let headers: Vec<String> = reader
+        .headers()
+        .map_err(|e| format!("Invalid CSV: {}", e))?
+        .iter()
+        .map(|header| header.trim().to_ascii_lowercase())
+        .collect();
+
+    let title = column(
+        &headers,
+        &[
+            "title",
+            "track name",
+            "track",
+            "name",
+            "song",
+            "track title",
+        ],
+    )
+    .ok_or("The CSV has no title column")?;
+    let artist = column(
+        &headers,
+        &[
+            "artist",
+            "artist name(s)",
+            "artist name",
+            "artists",
+            "creator",
+        ],
+    );
+    let album = column(&headers, &["album", "album name", "album title"]);
+    let isrc = column(&headers, &["isrc"]);
+    let duration_ms = column(&headers, &["duration (ms)", "duration_ms", "length (ms)"]);
+    let duration = column(&headers, &["duration", "length", "time"]);
+    let location = column(&headers, &["path", "location", "file", "url"]);
+
+    let mut entries = Vec::new();
+    let mut skipped = 0;
+    for (index, record) in reader.records().enumerate() {
+        let Ok(record) = record else {
+            skipped += 1;
+            continue;
+        };
+        let field = |i: Option<usize>| i.and_then(|i| record.get(i)).and_then(clean);
+        let entry = ImportedEntry {
+            index,
+            artist: field(artist),
+            title: field(Some(title)),
+            album: field(album),
+            duration_ms: field(duration_ms)
+                .and_then(|ms| parse_duration(&ms, true))
+                .or_else(|| field(duration).and_then(|d| parse_duration(&d, false))),
+            isrc: field(isrc).and_then(|isrc| clean_isrc(&isrc)),
+            location: field(location),
+        };
+        if entry.title.is_none() {
+            skipped += 1;
+        } else {
+            entries.push(entry);
+        }
+    }
+    Ok(Parsed {
+        name: None,
+        entries,
+        skipped,
+    })
+}
+
+fn parse_file(app: &AppHandle, path: &Path) -> Result<PlaylistMatchRequest, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Failed to read file: {}", e))?;
+    let content = decode(&bytes);
+    let source = detect_source(path, &content)?;
+    let mut parsed = match source {
+        PlaylistSource::M3u => parse_m3u(&content),
+        PlaylistSource::Xspf => parse_xspf(&content)?,
+        PlaylistSource::Csv => parse_csv(&content)?,
+    };
+    if parsed.entries.is_empty() {
+        return Err("The playlist has no entries".into());
+    }
+
+    let base = path.parent().unwrap_or(Path::new(""));
+    for entry in &mut parsed.entries {
+        fill_from_file(app, entry, base);
+        fill_from_name(entry);
+    }
+
+    let name = parsed.name.unwrap_or_else(|| {
+        path.file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "Imported Playlist".into())
+    });
+    Ok(PlaylistMatchRequest {
+        id: uuid::Uuid::new_v4().to_string(),
+        name,
+        source,
+        file: path.to_path_buf(),
+        entries: parsed.entries,
+        skipped: parsed.skipped,
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Report
+// ---------------------------------------------------------------------------
+
+fn write_report(app: &AppHandle, name: &str, entries: &[ImportedEntry]) -> Result<PathBuf, String> {
+    let dir = crate::desktop::download_dir(app)
+        .ok_or("No download folder")?
+        .join(REPORTS_DIR);
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let path = dir.join(format!(
+        "{} - unmatched.csv",
+        naming::sanitize_component(name)
+    ));
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer
+        .write_record([
+            "Position", "Artist", "Title", "Album", "Duration", "ISRC", "Location",
+        ])
+        .map_err(|e| e.to_string())?;
+    for entry in entries {
+        let duration = entry
+            .duration_ms
+            .map(|ms| format!("{}:{:02}", ms / 60_000, ms / 1000 % 60))
+            .unwrap_or_default();
+        writer
+            .write_record([
+                (entry.index + 1).to_string().as_str(),
+                entry.artist.as_deref().unwrap_or(""),
+                entry.title.as_deref().unwrap_or(""),
+                entry.album.as_deref().unwrap_or(""),
+                &duration,
+                entry.isrc.as_deref().unwrap_or(""),
+                entry.location.as_deref().unwrap_or(""),
+            ])
+            .map_err(|e| e.to_string())?;
+    }
+    let data = writer.into_inner().map_err(|e| e.to_string())?;
+    crate::store::write_atomic(&path, &data)?;
+    Ok(path)
+}
+
+// ---------------------------------------------------------------------------
+// Tauri commands
+// ---------------------------------------------------------------------------
+
+/// Parses M3U/M3U8, XSPF or CSV playlists and emits a
+/// `playlist-match-request` for each, for the web app to match against the
+/// catalogue. The user picks the files, so the page can't read any others.
+#[tauri::command]
+pub async fn import_playlist_files(app: AppHandle) -> Result<Vec<PlaylistMatchRequest>, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let paths: Vec<PathBuf> = app
+            .dialog()
+            .file()
+            .set_title("Import Playlists")
+            .add_filter("Playlist", &["m3u", "m3u8", "xspf", "csv"])
+            .blocking_pick_files()
+            .ok_or("No file selected")?
+            .into_iter()
+            .filter_map(|file| file.into_path().ok())
+            .collect();
+
+        let mut requests = Vec::new();
+        let mut errors = Vec::new();
+        for path in &paths {
+            match parse_file(&app, path) {
+                Ok(request) => requests.push(request),
+                Err(e) => errors.push(format!("{}: {}", path.display(), e)),
+            }
+        }
+        if requests.is_empty() && !errors.is_empty() {
+            return Err(errors.join("\n"));
+        }
+
+        let state = app.state::<PlaylistImportState>();
+        let mut pending = state.pending.lock().map_err(|_| "Failed to lock mutex")?;
+        for request in &requests {
+            pending.insert(request.id.clone(), request.clone());
+            let _ = app.emit("playlist-match-request", request);
+        }
+        Ok(requests)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Completes an import with the positions of the entries the web app found
+/// in the catalogue. Unmatched entries are written to a CSV report in the
+/// download folder.
+#[tauri::command]
+pub fn report_playlist_matches(
+    app: AppHandle,
+    id: String,
+    matched: Vec<usize>,
+) -> Result<PlaylistImportReport, String> {
+    let request = app
+        .state::<PlaylistImportState>()
+        .pending
+        .lock()
+        .map_err(|_| "Failed to lock mutex")?
+        .remove(&id)
+        .ok_or("Unknown playlist import")?;
+
+    let matched: HashSet<usize> = matched.into_iter().collect();
+    let unmatched: Vec<ImportedEntry> = request
+        .entries
+        .iter()
+        .filter(|entry| !matched.contains(&entry.index))
+        .cloned()
+        .collect();
+    let report = if unmatched.is_empty() {
+        None
+    } else {
+        Some(write_report(&app, &request.name, &unmatched)?)
+    };
+
+    Ok(PlaylistImportReport {
+        id,
+        name: request.name,
+        total: request.entries.len(),
+        matched: request.entries.len() - unmatched.len(),
+        unmatched,
+        report,
+    })
+}
@@ -0,0 +1,583 @@
+use crate::desktop::download_manager::{self, TrackMetadata};
+use crate::desktop::{download_history, library};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Emitter, Manager};
+
+const WATCHED_FILE: &str = "watched_playlists.json";
+const LOG_FILE: &str = "playlist_sync_log.json";
+
+/// Oldest runs are dropped once the log grows past this.
+const MAX_RUNS: usize = 200;
+
+/// How often due playlists are looked for.
+const TICK: Duration = Duration::from_secs(60);
+
+/// A run the web app hasn't answered by then is logged as failed.
+const RUN_TIMEOUT: u64 = 5 * 60;
+
+const DEFAULT_INTERVAL_MINUTES: u64 = 60;
+
+// ---------------------------------------------------------------------------
+// State
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum WatchKind {
+    Playlist,
+    /// The user's liked tracks.
+    Liked,
+}
+
+/// A track as the web app lists it, with a URL to download it from.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RemoteTrack {
+    /// The web app's track ID.
+    pub id: String,
+    pub url: String,
+    pub filename: Option<String>,
+    pub metadata: TrackMetadata,
+}
+
+/// A track seen in the playlist on the last sync.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncedTrack {
+    pub id: String,
+    pub url: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    /// Where the file was found; unknown while its download is queued, and
+    /// when it was only found in the library by its tags.
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WatchedPlaylist {
+    /// The web app's playlist ID.
+    pub id: String,
+    pub name: String,
+    pub kind: WatchKind,
+    /// Delete the files of tracks that left the playlist.
+    #[serde(default)]
+    pub remove_missing: bool,
+    pub interval_minutes: u64,
+    #[serde(default)]
+    pub last_synced: Option<u64>,
+    #[serde(default)]
+    pub tracks: Vec<SyncedTrack>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SyncStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncRun {
+    pub id: String,
+    pub playlist_id: String,
+    pub name: String,
+    pub status: SyncStatus,
+    pub started_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<u64>,
+    /// Tracks in the playlist.
+    #[serde(default)]
+    pub total: usize,
+    /// Tracks that were already downloaded or queued.
+    #[serde(default)]
+    pub present: usize,
+    /// `Artist - Title` of every track queued by this run.
+    #[serde(default)]
+    pub queued: Vec<String>,
+    #[serde(default)]
+    pub removed: Vec<PathBuf>,
+    #[serde(default)]
+    pub errors: Vec<String>,
+}
+
+/// Sent to the web app, which answers with `report_playlist_contents` or
+/// `fail_playlist_sync`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SyncRequest<'a> {
+    run_id: &'a str,
+    playlist_id: &'a str,
+    kind: WatchKind,
+}
+
+#[derive(Default)]
+pub struct PlaylistSyncState {
+    playlists: Mutex<Vec<WatchedPlaylist>>,
+    /// Newest last.
+    log: Mutex<Vec<SyncRun>>,
+}
+
+pub fn load(app: &AppHandle) {
+    let state = app.state::<PlaylistSyncState>();
+    *state.playlists.lock().unwrap() = crate::store::data_file(app, WATCHED_FILE)
+        .and_then(|path| crate::store::read_json(&path))
+        .unwrap_or_default();
+
+    // Runs cut short by the last exit will never be answered.
+    let mut log: Vec<SyncRun> = crate::store::data_file(app, LOG_FILE)
+        .and_then(|path| crate::store::read_json(&path))
+        .unwrap_or_default();
+    for run in log
+        .iter_mut()
+        .filter(|run| run.status == SyncStatus::Running)
+    {
+        run.status = SyncStatus::Failed;
+        run.errors.push("Interrupted".into());
+    }
+    *state.log.lock().unwrap() = log;
+
+    let app = app.clone();
+    std::thread::spawn(move || loop {
+        std::thread::sleep(TICK);
+        tick(&app);
+    });
+}
+
+fn save_playlists(app: &AppHandle, playlists: &[WatchedPlaylist]) {
+    if let Some(path) = crate::store::data_file(app, WATCHED_FILE) {
+        let _ = crate::store::write_json(&path, &playlists);
+    }
+}
+
+fn save_log(app: &AppHandle, log: &[SyncRun]) {
+    if let Some(path) = crate::store::data_file(app, LOG_FILE) {
+        let _ = crate::store::write_json(&path, &log);
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0)
+}
+
+// ---------------------------------------------------------------------------
+// Runs
+// ---------------------------------------------------------------------------
+
+/// Logs a new run for `playlist` and asks the web app for its contents.
+fn start(app: &AppHandle, playlist: &WatchedPlaylist) -> SyncRun {
+    let run = SyncRun {
+        id: uuid::Uuid::new_v4().to_string(),
+        playlist_id: playlist.id.clone(),
+        name: playlist.name.clone(),
+        status: SyncStatus::Running,
+        started_at: now(),
+        finished_at: None,
+        total: 0,
+        present: 0,
+        queued: Vec::new(),
+        removed: Vec::new(),
+        errors: Vec::new(),
+    };
+    {
+        let state = app.state::<PlaylistSyncState>();
+        let mut log = state.log.lock().unwrap();
+        log.push(run.clone());
+        if log.len() > MAX_RUNS {
+            let excess = log.len() - MAX_RUNS;
+            log.drain(..excess);
+        }
+        save_log(app, &log);
+    }
+    let _ = app.emit(
+        "playlist-sync-request",
+        SyncRequest {
+            run_id: &run.id,
+            playlist_id: &playlist.id,
+            kind: playlist.kind,
+        },
+    );
+    run
+}
+
+/// Whether a run for `playlist_id` is waiting for the web app.
+fn running(app: &AppHandle, playlist_id: &str) -> bool {
+    let state = app.state::<PlaylistSyncState>();
+    let log = state.log.lock().unwrap();
+    log.iter()
+        .any(|run| run.playlist_id == playlist_id && run.status == SyncStatus::Running)
+}
+
+/// Closes the running run `run_id` with `update` and emits
+/// `playlist-sync-finished`.
+fn finish<F>(app: &AppHandle, run_id: &str, update: F) -> Option<SyncRun>
+where
+    F: FnOnce(&mut SyncRun),
+{
+    let state = app.state::<PlaylistSyncState>();
+    let mut log = state.log.lock().unwrap();
+    let run = log
+        .iter_mut()
+        .find(|run| run.id == run_id && run.status == SyncStatus::Running)?;
+    update(run);
+    run.finished_at = Some(now());
+    let run = run.clone();
+    save_log(app, &log);
+    drop(log);
+    let _ = app.emit("playlist-sync-finished", &run);
+    Some(run)
+}
+
+/// Fails runs the web app never answered and starts the ones that are due.
+fn tick(app: &AppHandle) {
+    let now = now();
+    let stale: Vec<String> = {
+        let state = app.state::<PlaylistSyncState>();
+        let log = state.log.lock().unwrap();
+        log.iter()
+            .filter(|run| run.status == SyncStatus::Running)
+            .filter(|run| now.saturating_sub(run.started_at) >= RUN_TIMEOUT)
+            .map(|run| run.id.clone())
+            .collect()
+    };
+    for id in stale {
+        finish(app, &id, |run| {
+            run.status = SyncStatus::Failed;
+            run.errors.push("The web app did not answer".into());
+        });
+    }
+
+    let due: Vec<WatchedPlaylist> = {
+        let state = app.state::<PlaylistSyncState>();
+        let log = state.log.lock().unwrap();
+        let playlists = state.playlists.lock().unwrap();
+        // Failed runs count too, so an unreachable web app isn't asked again
+        // every tick.
+        let last_attempt = |id: &str| {
+            log.iter()
+                .rev()
+                .find(|run| run.playlist_id == id)
+                .map(|run| run.started_at)
+        };
+        playlists
+            .iter()
+            .filter(|playlist| {
+                let last = playlist.last_synced.max(last_attempt(&playlist.id));
+                last.is_none_or(|last| {
+                    now.saturating_sub(last) >= playlist.interval_minutes.max(1) * 60
+                })
+            })
+            .cloned()
+            .collect()
+    };
+    for playlist in due {
+        if !running(app, &playlist.id) {
+            start(app, &playlist);
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Diffing
+// ---------------------------------------------------------------------------
+
+fn display_name(metadata: &TrackMetadata, url: &str) -> String {
+    match (&metadata.title, metadata.artists.is_empty()) {
+        (Some(title), false) => format!("{} - {}", metadata.artists.join(", "), title),
+        (Some(title), true) => title.clone(),
+        (None, _) => url.to_string(),
+    }
+}
+
+/// Finds the file of `track` where it was last time or in the download
+/// history.
+fn locate(app: &AppHandle, track: &SyncedTrack) -> Option<PathBuf> {
+    let existing = |path: Option<&Path>| path.filter(|path| path.is_file()).map(Path::to_path_buf);
+    existing(track.path.as_deref())
+        .or_else(|| existing(download_history::path_for_url(app, &track.url).as_deref()))
+}
+
+/// Whether the library has `track` under another name, by its tags.
+fn in_library(app: &AppHandle, track: &SyncedTrack) -> bool {
+    track.title.as_deref().is_some_and(|title| {
+        library::find_track(app, title, track.artist.as_deref(), track.album.as_deref()).is_some()
+    })
+}
+
+fn synced(remote: &RemoteTrack, previous: Option<&SyncedTrack>) -> SyncedTrack {
+    SyncedTrack {
+        id: remote.id.clone(),
+        url: remote.url.clone(),
+        title: remote.metadata.title.clone(),
+        artist: remote.metadata.artists.first().cloned(),
+        album: remote.metadata.album.clone(),
+        path: previous.and_then(|track| track.path.clone()),
+    }
+}
+
+/// Queues the tracks of `tracks` that are neither downloaded nor queued, and
+/// deletes the files of tracks that left the playlist when asked to. Counts
+/// go into `run`.
+fn apply(app: &AppHandle, run: &mut SyncRun, tracks: Vec<RemoteTrack>) -> Result<(), String> {
+    let playlist_id = run.playlist_id.clone();
+    let state = app.state::<PlaylistSyncState>();
+    let (playlist, others) = {
+        let playlists = state.playlists.lock().map_err(|_| "Failed to lock mutex")?;
+        let playlist = playlists
+            .iter()
+            .find(|playlist| playlist.id == playlist_id)
+            .cloned()
+            .ok_or("Playlist is not watched")?;
+        // Tracks other watched playlists still hold on to.
+        let others: HashSet<String> = playlists
+            .iter()
+            .filter(|other| other.id != playlist_id)
+            .flat_map(|other| other.tracks.iter().map(|track| track.id.clone()))
+            .collect();
+        (playlist, others)
+    };
+
+    let previous: HashMap<&str, &SyncedTrack> = playlist
+        .tracks
+        .iter()
+        .map(|track| (track.id.as_str(), track))
+        .collect();
+    run.total = tracks.len();
+
+    let mut current = Vec::new();
+    for remote in &tracks {
+        let mut track = synced(remote, previous.get(remote.id.as_str()).copied());
+        track.path = locate(app, &track);
+        if track.path.is_some()
+            || in_library(app, &track)
+            || download_manager::queued(app, &remote.url)
+        {
+            run.present += 1;
+        } else {
+            match download_manager::enqueue(
+                app,
+                remote.url.clone(),
+                remote.filename.clone(),
+                Some(remote.metadata.clone()),
+                0,
+            ) {
+                Ok(_) => run.queued.push(display_name(&remote.metadata, &remote.url)),
+                Err(e) => run.errors.push(format!(
+                    "{}: {}",
+                    display_name(&remote.metadata, &remote.url),
+                    e
+                )),
+            }
+        }
+        current.push(track);
+    }
+
+    // An empty playlist is more likely a failed fetch than a cleared one.
+    if playlist.remove_missing && !tracks.is_empty() {
+        let kept: HashSet<&str> = tracks.iter().map(|track| track.id.as_str()).collect();
+        for track in &playlist.tracks {
+            if kept.contains(track.id.as_str()) || others.contains(&track.id) {
+                continue;
+            }
+            // Only files the app downloaded for the track, never ones found
+            // by their tags or skipped for.
+            let Some(path) =
+                download_history::downloaded_path(app, &track.url).filter(|path| path.is_file())
+            else {
+                continue;
+            };
+            match fs::remove_file(&path) {
+                Ok(()) => run.removed.push(path),
+                Err(e) => run.errors.push(format!("{}: {}", path.display(), e)),
+            }
+        }
+        if !run.removed.is_empty() {
+            library::refresh(app, run.removed.clone());
+        }
+    }
+
+    let mut playlists = state.playlists.lock().map_err(|_| "Failed to lock mutex")?;
+    if let Some(entry) = playlists.iter_mut().find(|entry| entry.id == playlist_id) {
+        entry.tracks = current;
+        entry.last_synced = Some(now());
+    }
+    save_playlists(app, &playlists);
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Tauri commands
+// ---------------------------------------------------------------------------
+
+/// Starts watching a playlist, or updates one already watched, and syncs it
+/// straight away.
+#[tauri::command]
+pub fn watch_playlist(
+    app: AppHandle,
+    state: tauri::State<PlaylistSyncState>,
+    id: String,
+    name: String,
+    kind: WatchKind,
+    remove_missing: Option<bool>,
+    interval_minutes: Option<u64>,
+) -> Result<WatchedPlaylist, String> {
+    let playlist = {
+        let mut playlists = state.playlists.lock().map_err(|_| "Failed to lock mutex")?;
+        let index = match playlists.iter().position(|playlist| playlist.id == id) {
+            Some(index) => index,
+            None => {
+                playlists.push(WatchedPlaylist {
+                    id: id.clone(),
+                    name: name.clone(),
+                    kind,
+                    remove_missing: false,
+                    interval_minutes: DEFAULT_INTERVAL_MINUTES,
+                    last_synced: None,
+                    tracks: Vec::new(),
+                });
+                playlists.len() - 1
+            }
+        };
+        let playlist = &mut playlists[index];
+        playlist.name = name;
+        playlist.kind = kind;
+        if let Some(remove_missing) = remove_missing {
+            playlist.remove_missing = remove_missing;
+        }
+        if let Some(interval_minutes) = interval_minutes {
+            playlist.interval_minutes = interval_minutes.max(1);
+        }
+        let playlist = playlist.clone();
+        save_playlists(&app, &playlists);
+        playlist
+    };
+    if !running(&app, &playlist.id) {
+        start(&app, &playlist);
+    }
+    Ok(playlist)
+}
+
+/// Stops watching a playlist. Its files are kept.
+#[tauri::command]
+pub fn unwatch_playlist(
+    app: AppHandle,
+    state: tauri::State<PlaylistSyncState>,
+    id: String,
+) -> Result<(), String> {
+    let mut playlists = state.playlists.lock().map_err(|_| "Failed to lock mutex")?;
+    playlists.retain(|playlist| playlist.id != id);
+    save_playlists(&app, &playlists);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn list_watched_playlists(
+    state: tauri::State<PlaylistSyncState>,
+) -> Result<Vec<WatchedPlaylist>, String> {
+    let playlists = state.playlists.lock().map_err(|_| "Failed to lock mutex")?;
+    Ok(playlists.clone())
+}
+
+/// Syncs a watched playlist now and returns the run.
+#[tauri::command]
+pub fn sync_watched_playlist(
+    app: AppHandle,
+    state: tauri::State<PlaylistSyncState>,
+    id: String,
+) -> Result<SyncRun, String> {
+    let playlist = state
+        .playlists
+        .lock()
+        .map_err(|_| "Failed to lock mutex")?
+        .iter()
+        .find(|playlist| playlist.id == id)
+        .cloned()
+        .ok_or("Playlist is not watched")?;
+    if running(&app, &id) {
+        return Err("A sync of this playlist is already running".into());
+    }
+    Ok(start(&app, &playlist))
+}
+
+/// Answers a `playlist-sync-request` with the playlist's current tracks.
+#[tauri::command]
+pub fn report_playlist_contents(
+    app: AppHandle,
+    state: tauri::State<PlaylistSyncState>,
+    run_id: String,
+    tracks: Vec<RemoteTrack>,
+) -> Result<SyncRun, String> {
+    let mut outcome = state
+        .log
+        .lock()
+        .map_err(|_| "Failed to lock mutex")?
+        .iter()
+        .find(|run| run.id == run_id && run.status == SyncStatus::Running)
+        .cloned()
+        .ok_or("Unknown playlist sync")?;
+
+    let result = apply(&app, &mut outcome, tracks);
+    finish(&app, &run_id, |run| {
+        *run = outcome;
+        run.status = match result {
+            Ok(()) => SyncStatus::Completed,
+            Err(e) => {
+                run.errors.push(e);
+                SyncStatus::Failed
+            }
+        };
+    })
+    .ok_or_else(|| "Unknown playlist sync".into())
+}
+
+/// Answers a `playlist-sync-request` the web app could not serve, e.g.
+/// because the playlist was deleted or the user is signed out.
+#[tauri::command]
+pub fn fail_playlist_sync(
+    app: AppHandle,
+    run_id: String,
+    error: String,
+) -> Result<SyncRun, String> {
+    finish(&app, &run_id, |run| {
+        run.status = SyncStatus::Failed;
+        run.errors.push(error);
+    })
+    .ok_or_else(|| "Unknown playlist sync".into())
+}
+
+#[tauri::command]
+pub fn get_playlist_sync_log(
+    state: tauri::State<PlaylistSyncState>,
+    playlist_id: Option<String>,
+) -> Result<Vec<SyncRun>, String> {
+    let log = state.log.lock().map_err(|_| "Failed to lock mutex")?;
+    Ok(log
+        .iter()
+        .rev()
+        .filter(|run| playlist_id.as_ref().is_none_or(|id| run.playlist_id == *id))
+        .cloned()
+        .collect())
+}
+
+#[tauri::command]
+pub fn clear_playlist_sync_log(
+    app: AppHandle,
+    state: tauri::State<PlaylistSyncState>,
+) -> Result<(), String> {
+    let mut log = state.log.lock().map_err(|_| "Failed to lock mutex")?;
+    log.retain(|run| run.status == SyncStatus::Running);
+    save_log(&app, &log);
+    Ok(())
+}
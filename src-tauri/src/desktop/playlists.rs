@@ -0,0 +1,520 @@
+use crate::desktop::{download_folder, download_history, library, naming};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Manager};
+use tauri_plugin_dialog::DialogExt;
+
+const PLAYLISTS_FILE: &str = "exported_playlists.json";
+
+/// Characters left alone in the path segments of an XSPF `<location>`.
+const URI_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+// ---------------------------------------------------------------------------
+// State
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PlaylistFormat {
+    M3u8,
+    Xspf,
+}
+
+impl PlaylistFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            PlaylistFormat::M3u8 => "m3u8",
+            PlaylistFormat::Xspf => "xspf",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PlaylistTrack {
+    pub title: Option<String>,
+    pub artists: Vec<String>,
+    pub album: Option<String>,
+    pub duration_ms: Option<u64>,
+    /// URL the track was downloaded from, looked up in the download history.
+    pub url: Option<String>,
+    /// The track's file, when the web app already knows it.
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlaylistDefinition {
+    /// The web app's playlist ID; exporting it again replaces the earlier
+    /// export.
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub creator: Option<String>,
+    pub tracks: Vec<PlaylistTrack>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedPlaylist {
+    pub playlist: PlaylistDefinition,
+    pub formats: Vec<PlaylistFormat>,
+    /// Folder the playlist files are written to.
+    pub dir: PathBuf,
+    /// File each track was found at, by position.
+    pub resolved: Vec<Option<PathBuf>>,
+    pub files: Vec<PathBuf>,
+    pub exported_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlaylistExportResult {
+    pub id: String,
+    pub files: Vec<PathBuf>,
+    pub found: usize,
+    /// Positions of tracks that have no file and were left out.
+    pub missing: Vec<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Default)]
+pub struct PlaylistState {
+    playlists: Mutex<Vec<ExportedPlaylist>>,
+}
+
+pub fn load(app: &AppHandle) {
+    let playlists = crate::store::data_file(app, PLAYLISTS_FILE)
+        .and_then(|path| crate::store::read_json(&path))
+        .unwrap_or_default();
+    *app.state::<PlaylistState>().playlists.lock().unwrap() = playlists;
+}
+
+fn save(app: &AppHandle, playlists: &[ExportedPlaylist]) {
+    if let Some(path) = crate::store::data_file(app, PLAYLISTS_FILE) {
+        let _ = crate::store::write_json(&path, &playlists);
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0)
+}
+
+// ---------------------------------------------------------------------------
+// Resolving files
+// ---------------------------------------------------------------------------
+
+/// Finds the file of `track`: where it was last time, the path the web app
+/// sent, the download history, and finally the library index by tags (for
+/// files the naming template moved since).
+fn resolve(app: &AppHandle, track: &PlaylistTrack, previous: Option<&Path>) -> Option<PathBuf> {
+    let existing = |path: Option<&Path>| path.filter(|path| path.is_file()).map(Path::to_path_buf);
+    existing(previous)
+        .or_else(|| existing(track.path.as_deref()))
+        .or_else(|| {
+            let url = track.url.as_deref()?;
+            existing(download_history::path_for_url(app, url).as_deref())
+        })
+        .or_else(|| {
+            let title = track.title.as_deref()?;
+            library::find_track(
+                app,
+                title,
+                track.artists.first().map(String::as_str),
+                track.album.as_deref(),
+            )
+        })
+}
+
+/// Exported playlist `id` with the current file of every track, by
+/// position.
+pub fn resolved(app: &AppHandle, id: &str) -> Option<(PlaylistDefinition, Vec<Option<PathBuf>>)> {
+    let exported = app
+        .state::<PlaylistState>()
+        .playlists
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|exported| exported.playlist.id == id)
+        .cloned()?;
+    let files = exported
+        .playlist
+        .tracks
+        .iter()
+        .enumerate()
+        .map(|(index, track)| {
+            resolve(
+                app,
+                track,
+                exported.resolved.get(index).cloned().flatten().as_deref(),
+            )
+        })
+        .collect();
+    Some((exported.playlist, files))
+}
+
+/// The folder most of `files` are in.
+fn common_dir(files: &[Option<PathBuf>]) -> Option<PathBuf> {
+    let mut counts: HashMap<&Path, usize> = HashMap::new();
+    for parent in files.iter().flatten().filter_map(|file| file.parent()) {
+        *counts.entry(parent).or_default() += 1;
+    }
+    counts
+        .into_iter()
+        .max_by_key(|(_, count)| *count)
+        .map(|(dir, _)| dir.to_path_buf())
+}
+
+/// `target` relative to the folder `base`; absolute when they share no
+/// root (e.g. another drive on Windows).
+fn relative_path(base: &Path, target: &Path) -> PathBuf {
+    let base: Vec<Component> = base.components().collect();
+    let target: Vec<Component> = target.components().collect();
+    let common = base.iter().zip(&target).take_while(|(a, b)| a == b).count();
+    if common == 0 {
+        return target.iter().collect();
+    }
+    let mut relative = PathBuf::new();
+    for _ in common..base.len() {
+        relative.push("..");
+    }
+    for component in &target[common..] {
+        relative.push(component);
+    }
+    relative
+}
+
+/// Relative paths with `/` separators, which players accept everywhere.
+fn segments(path: &Path) -> Vec<String> {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .collect()
+}
+
+// ---------------------------------------------------------------------------
+// Writing
+// ---------------------------------------------------------------------------
+
+fn display_name(track: &PlaylistTrack) -> String {
+    let title = track.title.clone().unwrap_or_default();
+    if track.artists.is_empty() {
+        title
+    } else {
+        format!("{} - {}", track.artists.join(", "), title)
+    }
+}
+
+/// M3U is line based; a line break inside a value would start a new entry.
+fn single_line(value: &str) -> String {
+    value.replace(['\r', '\n'], " ")
+}
+
+pub fn m3u8(playlist: &PlaylistDefinition, entries: &[(&PlaylistTrack, PathBuf)]) -> String {
+    let mut out = format!("#EXTM3U\n#PLAYLIST:{}\n", single_line(&playlist.name));
+    for (track, path) in entries {
+        let seconds = track.duration_ms.map(|ms| (ms / 1000) as i64).unwrap_or(-1);
+        let location = if path.is_absolute() {
+            path.to_string_lossy().into_owned()
+        } else {
+            segments(path).join("/")
+        };
+        out.push_str(&format!(
+            "#EXTINF:{},{}\n{}\n",
+            seconds,
+            single_line(&display_name(track)),
+            single_line(&location)
+        ));
+    }
+    out
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn xspf(playlist: &PlaylistDefinition, entries: &[(&PlaylistTrack, PathBuf)]) -> String {
+    let mut out = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n",
+    );
+    out.push_str(&format!(
+        "  <title>{}</title>\n",
+        escape_xml(&playlist.name)
+    ));
+    if let Some(creator) = &playlist.creator {
+        out.push_str(&format!("  <creator>{}</creator>\n", escape_xml(creator)));
+    }
+    out.push_str("  <trackList>\n");
+    for (track, path) in entries {
+        let location = if path.is_absolute() {
+            url::Url::from_file_path(path)
+                .map(|url| url.to_string())
+                .unwrap_or_default()
+        } else {
+            segments(path)
+                .iter()
+                .map(|segment| utf8_percent_encode(segment, URI_SEGMENT).to_string())
+                .collect::<Vec<_>>()
+                .join("/")
+        };
+        out.push_str("    <track>\n");
+        out.push_str(&format!(
+            "      <location>{}</location>\n",
+            escape_xml(&location)
+        ));
+        if let Some(title) = &track.title {
+            out.push_str(&format!("      <title>{}</title>\n", escape_xml(title)));
+        }
+        if !track.artists.is_empty() {
+            out.push_str(&format!(
+                "      <creator>{}</creator>\n",
+                escape_xml(&track.artists.join(", "))
+            ));
+        }
+        if let Some(album) = &track.album {
+            out.push_str(&format!("      <album>{}</album>\n", escape_xml(album)));
+        }
+        if let Some(duration) = track.duration_ms {
+            out.push_str(&format!("      <duration>{}</duration>\n", duration));
+        }
+        out.push_str("    </track>\n");
+    }
+    out.push_str("  </trackList>\n</playlist>\n");
+    out
+}
+
+/// Resolves every track and (re)writes the playlist files. Files of an
+/// earlier export that are no longer produced, e.g. after a rename, are
+/// removed.
+fn write(app: &AppHandle, exported: &mut ExportedPlaylist) -> Result<PlaylistExportResult, String> {
+    let previous = std::mem::take(&mut exported.resolved);
+    exported.resolved = exported
+        .playlist
+        .tracks
+        .iter()
+        .enumerate()
+        .map(|(index, track)| {
+            resolve(
+                app,
+                track,
+                previous.get(index).cloned().flatten().as_deref(),
+            )
+        })
+        .collect();
+
+    let entries: Vec<(&PlaylistTrack, PathBuf)> = exported
+        .playlist
+        .tracks
+        .iter()
+        .zip(&exported.resolved)
+        .filter_map(|(track, file)| Some((track, relative_path(&exported.dir, file.as_ref()?))))
+        .collect();
+    let missing: Vec<usize> = exported
+        .resolved
+        .iter()
+        .enumerate()
+        .filter(|(_, file)| file.is_none())
+        .map(|(index, _)| index)
+        .collect();
+
+    let stem = naming::sanitize_component(&exported.playlist.name);
+    let files: Vec<PathBuf> = exported
+        .formats
+        .iter()
+        .map(|format| {
+            exported
+                .dir
+                .join(format!("{}.{}", stem, format.extension()))
+        })
+        .collect();
+    // Only files this export wrote earlier are replaced.
+    if let Some(taken) = files
+        .iter()
+        .find(|file| file.exists() && !exported.files.contains(file))
+    {
+        return Err(format!("{} already exists", taken.display()));
+    }
+    for (format, file) in exported.formats.iter().zip(&files) {
+        let content = match format {
+            PlaylistFormat::M3u8 => m3u8(&exported.playlist, &entries),
+            PlaylistFormat::Xspf => xspf(&exported.playlist, &entries),
+        };
+        crate::store::write_atomic(file, content.as_bytes())?;
+    }
+    for old in exported.files.iter().filter(|old| !files.contains(old)) {
+        let _ = fs::remove_file(old);
+    }
+    exported.files = files.clone();
+    exported.exported_at = now();
+
+    Ok(PlaylistExportResult {
+        id: exported.playlist.id.clone(),
+        files,
+        found: entries.len(),
+        missing,
+        error: None,
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Tauri commands
+// ---------------------------------------------------------------------------
+
+/// Writes `playlist` as M3U8 and/or XSPF (both by default) with paths
+/// relative to the folder most of its tracks are in, when that is inside
+/// the download folder, or else the download folder itself. With `pick`,
+/// the user chooses the folder instead. The export is remembered so it can
+/// be regenerated later.
+#[tauri::command]
+pub async fn export_playlist(
+    app: AppHandle,
+    playlist: PlaylistDefinition,
+    formats: Option<Vec<PlaylistFormat>>,
+    pick: Option<bool>,
+) -> Result<PlaylistExportResult, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let dir = match pick {
+            Some(true) => Some(
+                app.dialog()
+                    .file()
+                    .set_title("Choose Playlist Folder")
+                    .blocking_pick_folder()
+                    .ok_or("No folder selected")?
+                    .into_path()
+                    .map_err(|e| e.to_string())?,
+            ),
+            _ => None,
+        };
+        export(app, playlist, formats, dir)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+fn export(
+    app: AppHandle,
+    playlist: PlaylistDefinition,
+    formats: Option<Vec<PlaylistFormat>>,
+    dir: Option<PathBuf>,
+) -> Result<PlaylistExportResult, String> {
+    let state = app.state::<PlaylistState>();
+    let formats = formats
+        .filter(|formats| !formats.is_empty())
+        .unwrap_or_else(|| vec![PlaylistFormat::M3u8, PlaylistFormat::Xspf]);
+    let mut playlists = state.playlists.lock().map_err(|_| "Failed to lock mutex")?;
+    let earlier = playlists
+        .iter()
+        .position(|exported| exported.playlist.id == playlist.id)
+        .map(|index| (index, playlists.remove(index)));
+
+    let resolved: Vec<Option<PathBuf>> = playlist
+        .tracks
+        .iter()
+        .map(|track| resolve(&app, track, None))
+        .collect();
+    let dir = match dir
+        .or_else(|| common_dir(&resolved).filter(|dir| download_folder::contains(&app, dir)))
+    {
+        Some(dir) => dir,
+        None => crate::desktop::download_dir(&app).ok_or("No download folder available")?,
+    };
+    let mut exported = ExportedPlaylist {
+        playlist,
+        formats,
+        dir,
+        resolved,
+        files: earlier
+            .as_ref()
+            .map(|(_, earlier)| earlier.files.clone())
+            .unwrap_or_default(),
+        exported_at: 0,
+    };
+    let result = match write(&app, &mut exported) {
+        Ok(result) => result,
+        Err(error) => {
+            // Still remembered, so its files get cleaned up next time.
+            if let Some((index, earlier)) = earlier {
+                playlists.insert(index, earlier);
+            }
+            return Err(error);
+        }
+    };
+    playlists.push(exported);
+    save(&app, &playlists);
+    Ok(result)
+}
+
+/// Rewrites exported playlists (all, or the one with `id`) after their
+/// files were moved or renamed. A playlist that can't be written is
+/// reported in its result and the rest are still rewritten.
+#[tauri::command]
+pub fn regenerate_playlists(
+    app: AppHandle,
+    state: tauri::State<PlaylistState>,
+    id: Option<String>,
+) -> Result<Vec<PlaylistExportResult>, String> {
+    let mut playlists = state.playlists.lock().map_err(|_| "Failed to lock mutex")?;
+    let results = playlists
+        .iter_mut()
+        .filter(|exported| id.as_ref().is_none_or(|id| &exported.playlist.id == id))
+        .map(|exported| {
+            write(&app, exported).unwrap_or_else(|error| PlaylistExportResult {
+                id: exported.playlist.id.clone(),
+                files: Vec::new(),
+                found: 0,
+                missing: Vec::new(),
+                error: Some(error),
+            })
+        })
+        .collect();
+    save(&app, &playlists);
+    Ok(results)
+}
+
+#[tauri::command]
+pub fn list_exported_playlists(
+    state: tauri::State<PlaylistState>,
+) -> Result<Vec<ExportedPlaylist>, String> {
+    let playlists = state.playlists.lock().map_err(|_| "Failed to lock mutex")?;
+    Ok(playlists.clone())
+}
+
+/// Forgets an export and, with `delete_files`, removes its playlist files.
+#[tauri::command]
+pub fn remove_exported_playlist(
+    app: AppHandle,
+    state: tauri::State<PlaylistState>,
+    id: String,
+    delete_files: bool,
+) -> Result<(), String> {
+    let mut playlists = state.playlists.lock().map_err(|_| "Failed to lock mutex")?;
+    let index = playlists
+        .iter()
+        .position(|exported| exported.playlist.id == id)
+        .ok_or("Unknown playlist")?;
+    let exported = playlists.remove(index);
+    if delete_files {
+        for file in &exported.files {
+            let _ = fs::remove_file(file);
+        }
+    }
+    save(&app, &playlists);
+    Ok(())
+}
@@ -0,0 +1,495 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::http::{header, Method, Request, Response, StatusCode};
+use tauri::{AppHandle, Manager, Url};
+
+const SETTINGS_FILE: &str = "shell_cache_settings.json";
+const INDEX_FILE: &str = "index.json";
+const MANIFEST_PATH: &str = "/manifest.json";
+
+/// Scheme the main window loads the instance through while the cache is
+/// enabled.
+pub const SCHEME: &str = "instance";
+
+/// Static files that make up the app shell. JSON is left out since it is
+/// usually API data; only the manifest is cached.
+const SHELL_EXTENSIONS: [&str; 15] = [
+    "html",
+    "js",
+    "mjs",
+    "css",
+    "webmanifest",
+    "woff",
+    "woff2",
+    "ttf",
+    "otf",
+    "svg",
+    "png",
+    "ico",
+    "webp",
+    "jpg",
+    "gif",
+];
+
+/// Request headers passed on to the instance.
+const FORWARDED_HEADERS: [header::HeaderName; 5] = [
+    header::ACCEPT,
+    header::ACCEPT_LANGUAGE,
+    header::AUTHORIZATION,
+    header::CONTENT_TYPE,
+    header::COOKIE,
+];
+
+/// Response headers that describe the connection to the instance rather
+/// than the response, and are not passed on to the page.
+const HOP_BY_HOP_HEADERS: [header::HeaderName; 6] = [
+    header::CONNECTION,
+    header::CONTENT_LENGTH,
+    header::PROXY_AUTHENTICATE,
+    header::TRAILER,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+];
+
+// ---------------------------------------------------------------------------
+// Settings
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ShellCacheSettings {
+    /// Load the instance through the caching protocol. The page then runs
+    /// on its own origin, so logins and site data start out empty.
+    pub enabled: bool,
+    /// How long to wait for the instance before serving from the cache.
+    pub timeout_secs: u64,
+}
+
+impl Default for ShellCacheSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            timeout_secs: 5,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// State
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ShellEntry {
+    /// File name inside the cache folder.
+    file: String,
+    content_type: Option<String>,
+    size: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ShellIndex {
+    /// Instance the cached files came from.
+    source: String,
+    /// Hash of the instance manifest the files belong to.
+    version: Option<String>,
+    /// Keyed by path and query.
+    entries: HashMap<String, ShellEntry>,
+}
+
+pub struct ShellCacheState {
+    settings: Mutex<ShellCacheSettings>,
+    index: Mutex<ShellIndex>,
+    client: reqwest::Client,
+}
+
+impl Default for ShellCacheState {
+    fn default() -> Self {
+        Self {
+            settings: Mutex::default(),
+            index: Mutex::default(),
+            // Redirects go back to the page so its address follows them.
+            client: reqwest::Client::builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShellCacheStats {
+    version: Option<String>,
+    entries: usize,
+    bytes: u64,
+}
+
+pub fn load(app: &AppHandle) {
+    let state = app.state::<ShellCacheState>();
+    *state.settings.lock().unwrap() = crate::store::load_config(app, SETTINGS_FILE);
+    if let Some(index) =
+        cache_dir(app).and_then(|dir| crate::store::read_json(&dir.join(INDEX_FILE)))
+    {
+        *state.index.lock().unwrap() = index;
+    }
+}
+
+fn settings(app: &AppHandle) -> ShellCacheSettings {
+    app.state::<ShellCacheState>()
+        .settings
+        .lock()
+        .unwrap()
+        .clone()
+}
+
+fn cache_dir(app: &AppHandle) -> Option<PathBuf> {
+    app.path().app_cache_dir().ok().map(|dir| dir.join("shell"))
+}
+
+fn save_index(app: &AppHandle, index: &ShellIndex) {
+    if let Some(dir) = cache_dir(app) {
+        let _ = crate::store::write_json(&dir.join(INDEX_FILE), index);
+    }
+}
+
+/// Drops every cached file and starts a new index for `source` at `version`.
+fn reset(app: &AppHandle, index: &mut ShellIndex, source: &str, version: Option<String>) {
+    if let Some(dir) = cache_dir(app) {
+        let _ = fs::remove_dir_all(&dir);
+    }
+    *index = ShellIndex {
+        source: source.to_string(),
+        version,
+        entries: HashMap::new(),
+    };
+    save_index(app, index);
+}
+
+/// URL the main window should open for `source`: the caching protocol when
+/// enabled, otherwise the instance itself.
+pub fn entry_url(app: &AppHandle, source: Url) -> Url {
+    if !settings(app).enabled {
+        return source;
+    }
+    // Windows serves custom schemes over http.
+    let base = if cfg!(windows) {
+        format!("http://{}.localhost/", SCHEME)
+    } else {
+        format!("{}://localhost/", SCHEME)
+    };
+    Url::parse(&base)
+        .and_then(|base| base.join(source.path()))
+        .unwrap_or(source)
+}
+
+// ---------------------------------------------------------------------------
+// Cache
+// ---------------------------------------------------------------------------
+
+fn hash(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn is_navigation(request: &Request<Vec<u8>>) -> bool {
+    let path = request.uri().path();
+    path.ends_with('/')
+        || path.ends_with(".html")
+        || request
+            .headers()
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .is_some_and(|accept| accept.contains("text/html"))
+}
+
+fn is_shell_file(request: &Request<Vec<u8>>) -> bool {
+    let path = request.uri().path();
+    let ext = path
+        .rsplit('/')
+        .next()
+        .and_then(|name| name.rsplit_once('.'))
+        .map(|(_, ext)| ext.to_ascii_lowercase());
+    is_navigation(request)
+        || path == MANIFEST_PATH
+        || ext.is_some_and(|ext| SHELL_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// Whether a response may be written to the cache: not one fetched with the
+/// user's credentials, and not one the instance marked as private.
+fn is_storable(request: &Request<Vec<u8>>, response: &Response<Vec<u8>>) -> bool {
+    let credentialed = [header::AUTHORIZATION, header::COOKIE]
+        .iter()
+        .any(|name| request.headers().contains_key(name));
+    let private = response
+        .headers()
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|directive| {
+            let directive = directive.trim();
+            directive.eq_ignore_ascii_case("no-store")
+                || directive.eq_ignore_ascii_case("private")
+                || directive.to_ascii_lowercase().starts_with("private=")
+        });
+    response.status() == StatusCode::OK && !credentialed && !private
+}
+
+fn cached(app: &AppHandle, key: &str) -> Option<Response<Vec<u8>>> {
+    let entry = app
+        .state::<ShellCacheState>()
+        .index
+        .lock()
+        .unwrap()
+        .entries
+        .get(key)
+        .cloned()?;
+    let body = fs::read(cache_dir(app)?.join(&entry.file)).ok()?;
+    let mut builder = Response::builder().status(StatusCode::OK);
+    if let Some(content_type) = &entry.content_type {
+        builder = builder.header(header::CONTENT_TYPE, content_type);
+    }
+    builder.body(body).ok()
+}
+
+fn store(app: &AppHandle, key: &str, content_type: Option<String>, body: &[u8]) {
+    let Some(dir) = cache_dir(app) else {
+        return;
+    };
+    let file = hash(key.as_bytes());
+    if crate::store::write_atomic(&dir.join(&file), body).is_err() {
+        return;
+    }
+    let state = app.state::<ShellCacheState>();
+    let mut index = state.index.lock().unwrap();
+    index.entries.insert(
+        key.to_string(),
+        ShellEntry {
+            file,
+            content_type,
+            size: body.len() as u64,
+        },
+    );
+    save_index(app, &index);
+}
+
+/// Compares the instance manifest with the one the cache was filled from
+/// and starts over when it changed, or when the instance itself did.
+/// Without a network the cache is kept as it is.
+async fn check_version(app: &AppHandle, source: &Url, timeout: Duration) {
+    let state = app.state::<ShellCacheState>();
+    {
+        let mut index = state.index.lock().unwrap();
+        if index.source != source.as_str() {
+            reset(app, &mut index, source.as_str(), None);
+        }
+    }
+    let Ok(url) = source.join(MANIFEST_PATH) else {
+        return;
+    };
+    let response = state.client.get(url).timeout(timeout).send().await;
+    let Ok(manifest) = response.and_then(|response| response.error_for_status()) else {
+        return;
+    };
+    if !manifest.status().is_success() {
+        return;
+    }
+    let Ok(body) = manifest.bytes().await else {
+        return;
+    };
+    let version = hash(&body);
+    let mut index = state.index.lock().unwrap();
+    if index.version.as_deref() != Some(version.as_str()) {
+        reset(app, &mut index, source.as_str(), Some(version));
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Protocol
+// ---------------------------------------------------------------------------
+
+async fn fetch(
+    app: &AppHandle,
+    request: &Request<Vec<u8>>,
+    source: &Url,
+    url: Url,
+    timeout: Duration,
+) -> Result<Response<Vec<u8>>, reqwest::Error> {
+    let client = app.state::<ShellCacheState>().client.clone();
+    let mut outgoing = client
+        .request(request.method().clone(), url)
+        .timeout(timeout)
+        .body(request.body().clone());
+    for name in FORWARDED_HEADERS {
+        if let Some(value) = request.headers().get(&name) {
+            outgoing = outgoing.header(name, value);
+        }
+    }
+    let response = outgoing.send().await?;
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response.bytes().await?.to_vec();
+
+    let mut builder = Response::builder().status(status);
+    for (name, value) in &headers {
+        if HOP_BY_HOP_HEADERS.contains(name) {
+            continue;
+        }
+        // Redirects within the instance stay on the protocol.
+        if name == header::LOCATION {
+            if let Some(location) = local_location(source, value) {
+                builder = builder.header(name, location);
+                continue;
+            }
+        }
+        builder = builder.header(name, value);
+    }
+    Ok(builder.body(body).unwrap_or_default())
+}
+
+/// Path and query of a `Location` pointing at the instance itself.
+fn local_location(source: &Url, value: &header::HeaderValue) -> Option<String> {
+    let location = Url::parse(value.to_str().ok()?).ok()?;
+    if location.origin() != source.origin() {
+        return None;
+    }
+    Some(match location.query() {
+        Some(query) => format!("{}?{}", location.path(), query),
+        None => location.path().to_string(),
+    })
+}
+
+fn offline_page() -> Response<Vec<u8>> {
+    Response::builder()
+        .status(StatusCode::GATEWAY_TIMEOUT)
+        .header(header::CONTENT_TYPE, "text/html")
+        .body(b"<!DOCTYPE html><html><body></body></html>".to_vec())
+        .unwrap_or_default()
+}
+
+/// Answers a request on the [`SCHEME`] protocol by forwarding it to the
+/// instance. Shell files are cached: pages are fetched fresh and fall back
+/// to the cache, other shell files come from the cache of the current
+/// manifest version first.
+pub async fn handle(app: AppHandle, request: Request<Vec<u8>>) -> Response<Vec<u8>> {
+    let Ok(source) = Url::parse(&crate::load_source_url(&app)) else {
+        return offline_page();
+    };
+    let key = request
+        .uri()
+        .path_and_query()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "/".into());
+    let Ok(url) = source.join(&key) else {
+        return offline_page();
+    };
+    let timeout = Duration::from_secs(settings(&app).timeout_secs.max(1));
+    let cacheable = request.method() == Method::GET && is_shell_file(&request);
+    let navigation = cacheable && is_navigation(&request);
+
+    if navigation {
+        // Not awaited: a slow manifest would hold up every page load.
+        let app = app.clone();
+        let source = source.clone();
+        tauri::async_runtime::spawn(async move {
+            check_version(&app, &source, timeout).await;
+        });
+    } else if cacheable {
+        if let Some(response) = cached(&app, &key) {
+            return response;
+        }
+    }
+
+    match fetch(&app, &request, &source, url, timeout).await {
+        Ok(response) => {
+            if cacheable && is_storable(&request, &response) {
+                let content_type = response
+                    .headers()
+                    .get(header::CONTENT_TYPE)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_string);
+                store(&app, &key, content_type, response.body());
+            }
+            response
+        }
+        // Offline: routes of the single-page app that were never opened
+        // fall back to the cached start page.
+        Err(_) if navigation => cached(&app, &key)
+            .or_else(|| cached(&app, source.path()))
+            .unwrap_or_else(offline_page),
+        Err(_) => cached(&app, &key).unwrap_or_else(|| {
+            Response::builder()
+                .status(StatusCode::BAD_GATEWAY)
+                .body(Vec::new())
+                .unwrap_or_default()
+        }),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tauri commands
+// ---------------------------------------------------------------------------
+
+#[tauri::command]
+pub fn get_shell_cache_settings(
+    state: tauri::State<ShellCacheState>,
+) -> Result<ShellCacheSettings, String> {
+    let settings = state.settings.lock().map_err(|_| "Failed to lock mutex")?;
+    Ok(settings.clone())
+}
+
+/// Saves the settings and reloads the main window through the protocol (or
+/// straight from the instance) to match.
+#[tauri::command]
+pub fn set_shell_cache_settings(
+    app: AppHandle,
+    state: tauri::State<ShellCacheState>,
+    settings: ShellCacheSettings,
+) -> Result<(), String> {
+    crate::store::save_config(&app, SETTINGS_FILE, &settings)?;
+    *state.settings.lock().map_err(|_| "Failed to lock mutex")? = settings;
+    let source = Url::parse(&crate::load_source_url(&app)).map_err(|e| e.to_string())?;
+    if let Some(window) = app.get_webview_window("main") {
+        let _ = window.navigate(entry_url(&app, source));
+    }
+    Ok(())
+}
+
+/// Size of the cached shell files.
+pub fn bytes(app: &AppHandle) -> u64 {
+    let state = app.state::<ShellCacheState>();
+    let index = state.index.lock().unwrap();
+    index.entries.values().map(|entry| entry.size).sum()
+}
+
+#[tauri::command]
+pub fn get_shell_cache_stats(
+    state: tauri::State<ShellCacheState>,
+) -> Result<ShellCacheStats, String> {
+    let index = state.index.lock().map_err(|_| "Failed to lock mutex")?;
+    Ok(ShellCacheStats {
+        version: index.version.clone(),
+        entries: index.entries.len(),
+        bytes: index.entries.values().map(|entry| entry.size).sum(),
+    })
+}
+
+#[tauri::command]
+pub fn clear_shell_cache(
+    app: AppHandle,
+    state: tauri::State<ShellCacheState>,
+) -> Result<(), String> {
+    let mut index = state.index.lock().map_err(|_| "Failed to lock mutex")?;
+    let source = index.source.clone();
+    reset(&app, &mut index, &source, None);
+    Ok(())
+}
@@ -0,0 +1,333 @@
+use crate::desktop::{cover_cache, download_history, history, library, notifications, shell_cache};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tauri::{AppHandle, Emitter, Manager};
+use tauri_plugin_dialog::{DialogExt, MessageDialogButtons, MessageDialogKind};
+
+const SETTINGS_FILE: &str = "storage_settings.json";
+
+// ---------------------------------------------------------------------------
+// Settings
+// ---------------------------------------------------------------------------
+
+/// What happens when a download would take the download folder over its
+/// quota.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum QuotaPolicy {
+    /// Don't start the download.
+    Refuse,
+    /// Download anyway and notify.
+    #[default]
+    Warn,
+    /// Delete the tracks played longest ago (never played first) until the
+    /// download fits.
+    EvictLeastPlayed,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct StorageSettings {
+    /// Cap for the download folder; `None` for no limit.
+    pub download_quota_bytes: Option<u64>,
+    /// Cap for the app cache (covers and the instance shell); `None` for no
+    /// limit beyond each cache's own.
+    pub cache_quota_bytes: Option<u64>,
+    pub policy: QuotaPolicy,
+}
+
+// ---------------------------------------------------------------------------
+// State
+// ---------------------------------------------------------------------------
+
+#[derive(Default)]
+pub struct StorageState {
+    settings: Mutex<StorageSettings>,
+    /// Set once the user has been warned about the quota, until usage drops
+    /// below it again.
+    warned: Mutex<bool>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageGroup {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    pub bytes: u64,
+    pub files: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageUsage {
+    pub download_dir: Option<PathBuf>,
+    pub download_bytes: u64,
+    pub download_files: usize,
+    pub download_quota_bytes: Option<u64>,
+    pub by_artist: Vec<UsageGroup>,
+    pub by_album: Vec<UsageGroup>,
+    pub by_format: Vec<UsageGroup>,
+    pub cache_bytes: u64,
+    pub cover_cache_bytes: u64,
+    pub shell_cache_bytes: u64,
+    pub cache_quota_bytes: Option<u64>,
+    pub policy: QuotaPolicy,
+}
+
+pub fn load(app: &AppHandle) {
+    let state = app.state::<StorageState>();
+    *state.settings.lock().unwrap() = crate::store::load_config(app, SETTINGS_FILE);
+}
+
+fn settings(app: &AppHandle) -> StorageSettings {
+    app.state::<StorageState>().settings.lock().unwrap().clone()
+}
+
+// ---------------------------------------------------------------------------
+// Usage
+// ---------------------------------------------------------------------------
+
+const UNKNOWN: &str = "Unknown";
+
+/// Sums `tracks` per key, largest first.
+fn group<F>(tracks: &[library::LibraryTrack], key: F) -> Vec<UsageGroup>
+where
+    F: Fn(&library::LibraryTrack) -> (String, Option<String>),
+{
+    let mut groups: HashMap<(String, Option<String>), (u64, usize)> = HashMap::new();
+    for track in tracks {
+        let entry = groups.entry(key(track)).or_default();
+        entry.0 += track.size;
+        entry.1 += 1;
+    }
+    let mut groups: Vec<UsageGroup> = groups
+        .into_iter()
+        .map(|((name, artist), (bytes, files))| UsageGroup {
+            name,
+            artist,
+            bytes,
+            files,
+        })
+        .collect();
+    groups.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.name.cmp(&b.name)));
+    groups
+}
+
+fn download_bytes(app: &AppHandle) -> u64 {
+    library::tracks(app).iter().map(|track| track.size).sum()
+}
+
+/// How large the cover cache may grow before the app cache quota is hit.
+pub fn cover_budget(app: &AppHandle) -> u64 {
+    match settings(app).cache_quota_bytes {
+        Some(quota) => quota.saturating_sub(shell_cache::bytes(app)),
+        None => u64::MAX,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Quota
+// ---------------------------------------------------------------------------
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Deletes the least recently played downloads until at least `needed`
+/// bytes are freed. Only files the download history has for a download are
+/// considered, never other library files. Nothing is deleted when they
+/// wouldn't free enough. Returns the bytes freed.
+fn evict(app: &AppHandle, needed: u64) -> u64 {
+    let downloaded = download_history::downloaded_paths(app);
+    let last_played = history::last_played(app);
+    let played = |track: &library::LibraryTrack| {
+        let artist = track.artist.as_deref().unwrap_or_default();
+        let title = track.title.as_deref().unwrap_or_default();
+        let key = (history::normalize(artist), history::normalize(title));
+        last_played.get(&key).copied().unwrap_or(0)
+    };
+    let mut tracks = library::tracks(app);
+    tracks.retain(|track| downloaded.contains(&track.path));
+    tracks.sort_by_key(|track| (played(track), track.modified));
+
+    let mut planned = 0;
+    let mut victims = Vec::new();
+    for track in tracks {
+        if planned >= needed {
+            break;
+        }
+        planned += track.size;
+        victims.push(track);
+    }
+    if planned < needed {
+        return 0;
+    }
+
+    let mut freed = 0;
+    let mut removed = Vec::new();
+    for track in victims {
+        if fs::remove_file(&track.path).is_ok() {
+            freed += track.size;
+            removed.push(track.path);
+        }
+    }
+    if !removed.is_empty() {
+        let _ = app.emit("storage-evicted", &removed);
+        library::refresh(app, removed);
+    }
+    freed
+}
+
+/// Makes room for a download of `incoming` bytes (0 when unknown) under the
+/// quota policy. Fails when the policy is to refuse, or when eviction could
+/// not free enough.
+pub fn reserve(app: &AppHandle, incoming: u64) -> Result<(), String> {
+    let settings = settings(app);
+    let Some(quota) = settings.download_quota_bytes else {
+        return Ok(());
+    };
+    let used = download_bytes(app);
+    let state = app.state::<StorageState>();
+    if used.saturating_add(incoming) <= quota {
+        *state.warned.lock().unwrap() = false;
+        return Ok(());
+    }
+
+    let message = format!(
+        "The download folder uses {} of its {} quota.",
+        format_bytes(used),
+        format_bytes(quota)
+    );
+    match settings.policy {
+        QuotaPolicy::Refuse => Err(message),
+        QuotaPolicy::Warn => {
+            let mut warned = state.warned.lock().unwrap();
+            if !*warned {
+                *warned = true;
+                let _ = app.emit("storage-quota-exceeded", used);
+                notifications::error(app, "Download Quota Exceeded", &message);
+            }
+            Ok(())
+        }
+        QuotaPolicy::EvictLeastPlayed => {
+            let needed = used.saturating_add(incoming) - quota;
+            if evict(app, needed) >= needed {
+                Ok(())
+            } else {
+                Err(message)
+            }
+        }
+    }
+}
+
+/// [`reserve`] for a download of unknown size, for callers that must not
+/// block. Only a refusal is decided right away; warnings and eviction run
+/// in the background, and the download goes ahead meanwhile.
+pub fn admit(app: &AppHandle) -> Result<(), String> {
+    if settings(app).policy == QuotaPolicy::Refuse {
+        return reserve(app, 0);
+    }
+    let app = app.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        if let Err(error) = reserve(&app, 0) {
+            notifications::error(&app, "Download Quota Exceeded", &error);
+        }
+    });
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Tauri commands
+// ---------------------------------------------------------------------------
+
+#[tauri::command]
+pub fn get_storage_settings(state: tauri::State<StorageState>) -> Result<StorageSettings, String> {
+    let settings = state.settings.lock().map_err(|_| "Failed to lock mutex")?;
+    Ok(settings.clone())
+}
+
+/// Saves the settings. A new download quota below what the download folder
+/// already uses is only accepted once the user confirms it natively, since
+/// the next download may then refuse or delete tracks.
+#[tauri::command]
+pub async fn set_storage_settings(app: AppHandle, settings: StorageSettings) -> Result<(), String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let current = self::settings(&app);
+        let changed = settings.download_quota_bytes != current.download_quota_bytes
+            || settings.policy != current.policy;
+        if let Some(quota) = settings.download_quota_bytes.filter(|_| changed) {
+            let used = download_bytes(&app);
+            let confirmed = used <= quota
+                || app
+                    .dialog()
+                    .message(format!(
+                        "The download folder already uses {}, more than the new {} quota.",
+                        format_bytes(used),
+                        format_bytes(quota)
+                    ))
+                    .title("Lower Download Quota?")
+                    .kind(MessageDialogKind::Warning)
+                    .buttons(MessageDialogButtons::OkCancelCustom(
+                        "Lower Quota".into(),
+                        "Cancel".into(),
+                    ))
+                    .blocking_show();
+            if !confirmed {
+                return Err("The quota is below the current usage".to_string());
+            }
+        }
+
+        let state = app.state::<StorageState>();
+        crate::store::save_config(&app, SETTINGS_FILE, &settings)?;
+        *state.settings.lock().map_err(|_| "Failed to lock mutex")? = settings;
+        *state.warned.lock().map_err(|_| "Failed to lock mutex")? = false;
+        cover_cache::trim(&app);
+        Ok(())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Reports how much space downloads take, broken down by artist, album and
+/// format, next to the app cache and the configured quotas.
+#[tauri::command]
+pub fn get_storage_usage(app: AppHandle) -> StorageUsage {
+    let settings = settings(&app);
+    let tracks = library::tracks(&app);
+    let cover_cache_bytes = cover_cache::bytes(&app);
+    let shell_cache_bytes = shell_cache::bytes(&app);
+    StorageUsage {
+        download_dir: crate::desktop::download_dir(&app),
+        download_bytes: tracks.iter().map(|track| track.size).sum(),
+        download_files: tracks.len(),
+        download_quota_bytes: settings.download_quota_bytes,
+        by_artist: group(&tracks, |track| {
+            let artist = track.album_artist.as_ref().or(track.artist.as_ref());
+            (artist.cloned().unwrap_or_else(|| UNKNOWN.into()), None)
+        }),
+        by_album: group(&tracks, |track| {
+            let artist = track.album_artist.as_ref().or(track.artist.as_ref());
+            (
+                track.album.clone().unwrap_or_else(|| UNKNOWN.into()),
+                artist.cloned(),
+            )
+        }),
+        by_format: group(&tracks, |track| (track.format.clone(), None)),
+        cache_bytes: cover_cache_bytes + shell_cache_bytes,
+        cover_cache_bytes,
+        shell_cache_bytes,
+        cache_quota_bytes: settings.cache_quota_bytes,
+        policy: settings.policy,
+    }
+}
@@ -0,0 +1,241 @@
+use crate::desktop::download_manager::TrackMetadata;
+use crate::desktop::{cover_cache, library, notifications};
+use lofty::config::WriteOptions;
+use lofty::file::{AudioFile, TaggedFileExt};
+use lofty::picture::{Picture, PictureType};
+use lofty::tag::{ItemKey, Tag};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager};
+
+const SETTINGS_FILE: &str = "tagging_settings.json";
+
+// ---------------------------------------------------------------------------
+// Settings
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TaggingSettings {
+    /// Tag files automatically once a download finishes.
+    pub enabled: bool,
+    /// Replace tags the server already wrote; otherwise only missing fields
+    /// (and a missing cover) are filled in.
+    pub overwrite_existing: bool,
+    pub embed_cover: bool,
+    /// Edge length of the embedded cover, rounded up to a size the cover
+    /// cache renders. `0` embeds the original image.
+    pub cover_size: u32,
+}
+
+impl Default for TaggingSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            overwrite_existing: true,
+            embed_cover: true,
+            cover_size: 512,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct TaggingState {
+    settings: Mutex<TaggingSettings>,
+}
+
+pub fn load(app: &AppHandle) {
+    let state = app.state::<TaggingState>();
+    *state.settings.lock().unwrap() = crate::store::load_config(app, SETTINGS_FILE);
+}
+
+fn settings(app: &AppHandle) -> TaggingSettings {
+    app.state::<TaggingState>().settings.lock().unwrap().clone()
+}
+
+// ---------------------------------------------------------------------------
+// Writing
+// ---------------------------------------------------------------------------
+
+fn set_text(tag: &mut Tag, key: ItemKey, value: Option<String>, overwrite: bool) {
+    let Some(value) = value.filter(|value| !value.trim().is_empty()) else {
+        return;
+    };
+    if overwrite || tag.get_string(&key).is_none() {
+        tag.insert_text(key, value);
+    }
+}
+
+/// Writes `metadata` into the file's primary tag: ID3v2 for MP3, Vorbis
+/// comments for FLAC/Ogg/Opus, `ilst` atoms for MP4/M4A.
+fn write_tags(
+    path: &Path,
+    metadata: &TrackMetadata,
+    cover: Option<&[u8]>,
+    overwrite: bool,
+) -> Result<(), String> {
+    let mut file =
+        lofty::read_from_path(path).map_err(|e| format!("Unsupported audio file: {}", e))?;
+
+    if file.primary_tag().is_none() {
+        let tag_type = file.primary_tag_type();
+        file.insert_tag(Tag::new(tag_type));
+    }
+    let tag = file.primary_tag_mut().ok_or("File does not support tags")?;
+
+    let artist = (!metadata.artists.is_empty()).then(|| metadata.artists.join(", "));
+    set_text(tag, ItemKey::TrackTitle, metadata.title.clone(), overwrite);
+    set_text(tag, ItemKey::TrackArtist, artist, overwrite);
+    set_text(
+        tag,
+        ItemKey::AlbumArtist,
+        metadata.album_artist.clone(),
+        overwrite,
+    );
+    set_text(tag, ItemKey::AlbumTitle, metadata.album.clone(), overwrite);
+    set_text(
+        tag,
+        ItemKey::TrackNumber,
+        metadata.track_number.map(|n| n.to_string()),
+        overwrite,
+    );
+    set_text(
+        tag,
+        ItemKey::TrackTotal,
+        metadata.track_total.map(|n| n.to_string()),
+        overwrite,
+    );
+    set_text(
+        tag,
+        ItemKey::DiscNumber,
+        metadata.disc_number.map(|n| n.to_string()),
+        overwrite,
+    );
+    set_text(
+        tag,
+        ItemKey::DiscTotal,
+        metadata.disc_total.map(|n| n.to_string()),
+        overwrite,
+    );
+    set_text(tag, ItemKey::Isrc, metadata.isrc.clone(), overwrite);
+    set_text(
+        tag,
+        ItemKey::RecordingDate,
+        metadata.release_date.clone(),
+        overwrite,
+    );
+
+    if let Some(data) = cover {
+        let has_cover = tag
+            .pictures()
+            .iter()
+            .any(|picture| picture.pic_type() == PictureType::CoverFront);
+        if overwrite || !has_cover {
+            let mut picture = Picture::from_reader(&mut &data[..]).map_err(|e| e.to_string())?;
+            picture.set_pic_type(PictureType::CoverFront);
+            tag.remove_picture_type(PictureType::CoverFront);
+            tag.push_picture(picture);
+        }
+    }
+
+    file.save_to_path(path, WriteOptions::default())
+        .map_err(|e| e.to_string())
+}
+
+/// Copies the tags and pictures of `from` into `to`, converting between tag
+/// formats (e.g. Vorbis comments to ID3v2) along the way.
+pub fn copy_tags(from: &Path, to: &Path) -> Result<(), String> {
+    let source = lofty::read_from_path(from).map_err(|e| e.to_string())?;
+    let Some(tag) = source.primary_tag().or_else(|| source.first_tag()) else {
+        return Ok(());
+    };
+    let mut target = lofty::read_from_path(to).map_err(|e| e.to_string())?;
+    let mut tag = tag.clone();
+    tag.re_map(target.primary_tag_type());
+    target.insert_tag(tag);
+    target
+        .save_to_path(to, WriteOptions::default())
+        .map_err(|e| e.to_string())
+}
+
+/// Tags `path` with `metadata`, embedding the cover when enabled.
+async fn tag_file(
+    app: &AppHandle,
+    settings: &TaggingSettings,
+    path: &Path,
+    metadata: &TrackMetadata,
+) -> Result<(), String> {
+    let cover = match &metadata.cover_url {
+        Some(url) if settings.embed_cover => {
+            let size = (settings.cover_size > 0).then_some(settings.cover_size);
+            cover_cache::fetch(app, url, size)
+                .await
+                .and_then(|cover| fs::read(cover).ok())
+        }
+        _ => None,
+    };
+
+    let path = path.to_path_buf();
+    let metadata = metadata.clone();
+    let overwrite = settings.overwrite_existing;
+    tauri::async_runtime::spawn_blocking(move || {
+        write_tags(&path, &metadata, cover.as_deref(), overwrite)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Post-download step: tags a finished download when tagging is enabled.
+/// Failures are reported but never fail the download itself.
+pub async fn tag_download(app: &AppHandle, path: &Path, metadata: Option<&TrackMetadata>) {
+    let settings = settings(app);
+    let Some(metadata) = metadata.filter(|_| settings.enabled) else {
+        return;
+    };
+    if let Err(error) = tag_file(app, &settings, path, metadata).await {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        notifications::error(app, "Tagging Failed", &format!("{}\n{}", name, error));
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tauri commands
+// ---------------------------------------------------------------------------
+
+#[tauri::command]
+pub fn get_tagging_settings(state: tauri::State<TaggingState>) -> Result<TaggingSettings, String> {
+    let settings = state.settings.lock().map_err(|_| "Failed to lock mutex")?;
+    Ok(settings.clone())
+}
+
+#[tauri::command]
+pub fn set_tagging_settings(
+    app: AppHandle,
+    state: tauri::State<TaggingState>,
+    settings: TaggingSettings,
+) -> Result<(), String> {
+    crate::store::save_config(&app, SETTINGS_FILE, &settings)?;
+    *state.settings.lock().map_err(|_| "Failed to lock mutex")? = settings;
+    Ok(())
+}
+
+/// Re-tags a file in the library, e.g. one downloaded before tagging was
+/// enabled. Runs regardless of the `enabled` setting.
+#[tauri::command]
+pub async fn retag_file(
+    app: AppHandle,
+    path: PathBuf,
+    metadata: TrackMetadata,
+) -> Result<(), String> {
+    // Only indexed downloads, never arbitrary files the webview names.
+    if !library::contains(&app, &path) || !path.is_file() {
+        return Err("File not found in the library".into());
+    }
+    let settings = settings(&app);
+    tag_file(&app, &settings, &path, &metadata).await
+}
@@ -0,0 +1,643 @@
+use crate::collision;
+use crate::desktop::{library, notifications, tagging};
+use lofty::file::{AudioFile, FileType, TaggedFileExt};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager};
+use tauri_plugin_dialog::DialogExt;
+
+const SETTINGS_FILE: &str = "transcoding_settings.json";
+/// Kept apart from the settings so only the native file dialog can set it.
+const FFMPEG_FILE: &str = "ffmpeg_path.json";
+
+/// Minimum delay between two `transcode-progress` events for the same task.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+// ---------------------------------------------------------------------------
+// Settings
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Codec {
+    Mp3,
+    Opus,
+    Aac,
+}
+
+impl Codec {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Codec::Mp3 => "mp3",
+            Codec::Opus => "opus",
+            Codec::Aac => "m4a",
+        }
+    }
+
+    fn encoder(self) -> &'static str {
+        match self {
+            Codec::Mp3 => "libmp3lame",
+            Codec::Opus => "libopus",
+            Codec::Aac => "aac",
+        }
+    }
+
+    fn muxer(self) -> &'static str {
+        match self {
+            Codec::Mp3 => "mp3",
+            Codec::Opus => "opus",
+            Codec::Aac => "ipod",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TranscodeProfile {
+    pub id: String,
+    pub name: String,
+    pub codec: Codec,
+    pub bitrate_kbps: u32,
+    /// Keep the source file next to the transcoded copy.
+    pub keep_original: bool,
+    /// Folder for the copies; next to the source when unset. Only set
+    /// through `pick_transcode_output_dir`.
+    #[serde(default)]
+    pub output_dir: Option<PathBuf>,
+    /// Also convert sources that are already lossy when run automatically.
+    #[serde(default)]
+    pub lossy_sources: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TranscodingSettings {
+    /// Number of files converted at the same time.
+    pub workers: usize,
+    pub profiles: Vec<TranscodeProfile>,
+    /// Profile applied to every finished download, if any.
+    pub auto_profile: Option<String>,
+}
+
+impl Default for TranscodingSettings {
+    fn default() -> Self {
+        let profile = |id: &str, name: &str, codec, bitrate_kbps| TranscodeProfile {
+            id: id.into(),
+            name: name.into(),
+            codec,
+            bitrate_kbps,
+            keep_original: true,
+            output_dir: None,
+            lossy_sources: false,
+        };
+        Self {
+            workers: 2,
+            profiles: vec![
+                profile("mp3-320", "MP3 320 kbps", Codec::Mp3, 320),
+                profile("opus-160", "Opus 160 kbps", Codec::Opus, 160),
+                profile("aac-256", "AAC 256 kbps", Codec::Aac, 256),
+            ],
+            auto_profile: None,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// State
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TranscodeTask {
+    pub id: String,
+    pub source: PathBuf,
+    pub profile: TranscodeProfile,
+    /// Leave lossy sources alone (automatic runs without `lossy_sources`).
+    pub only_lossless: bool,
+}
+
+#[derive(Default)]
+pub struct TranscodingState {
+    settings: Mutex<TranscodingSettings>,
+    /// `ffmpeg` executable picked by the user; looked up on `PATH` when unset.
+    ffmpeg: Mutex<Option<PathBuf>>,
+    queue: Mutex<VecDeque<TranscodeTask>>,
+    /// Worker threads currently running.
+    workers: AtomicUsize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TranscodeEventPayload<'a> {
+    id: &'a str,
+    source: &'a Path,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<&'a Path>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    progress: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+}
+
+pub fn load(app: &AppHandle) {
+    let state = app.state::<TranscodingState>();
+    *state.settings.lock().unwrap() = crate::store::load_config(app, SETTINGS_FILE);
+    *state.ffmpeg.lock().unwrap() = crate::store::load_config(app, FFMPEG_FILE);
+}
+
+fn settings(app: &AppHandle) -> TranscodingSettings {
+    app.state::<TranscodingState>()
+        .settings
+        .lock()
+        .unwrap()
+        .clone()
+}
+
+fn emit(app: &AppHandle, event: &str, payload: TranscodeEventPayload) {
+    let _ = app.emit(event, payload);
+}
+
+// ---------------------------------------------------------------------------
+// Worker pool
+// ---------------------------------------------------------------------------
+
+fn enqueue(
+    app: &AppHandle,
+    source: PathBuf,
+    profile: TranscodeProfile,
+    only_lossless: bool,
+) -> String {
+    let task = TranscodeTask {
+        id: uuid::Uuid::new_v4().to_string(),
+        source,
+        profile,
+        only_lossless,
+    };
+    let id = task.id.clone();
+    app.state::<TranscodingState>()
+        .queue
+        .lock()
+        .unwrap()
+        .push_back(task);
+    pump(app);
+    id
+}
+
+/// Starts worker threads until the pool is full or every task is taken.
+fn pump(app: &AppHandle) {
+    let state = app.state::<TranscodingState>();
+    let limit = state.settings.lock().unwrap().workers.max(1);
+    loop {
+        let running = state.workers.load(Ordering::SeqCst);
+        if running >= limit || state.queue.lock().unwrap().is_empty() {
+            return;
+        }
+        if state
+            .workers
+            .compare_exchange(running, running + 1, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            continue;
+        }
+        let app = app.clone();
+        thread::spawn(move || work(&app));
+    }
+}
+
+fn work(app: &AppHandle) {
+    let state = app.state::<TranscodingState>();
+    loop {
+        let task = state.queue.lock().unwrap().pop_front();
+        let Some(task) = task else {
+            break;
+        };
+        match transcode(app, &task) {
+            Ok(Some(output)) => emit(
+                app,
+                "transcode-finished",
+                TranscodeEventPayload {
+                    id: &task.id,
+                    source: &task.source,
+                    output: Some(&output),
+                    progress: Some(1.0),
+                    error: None,
+                },
+            ),
+            Ok(None) => emit(
+                app,
+                "transcode-skipped",
+                TranscodeEventPayload {
+                    id: &task.id,
+                    source: &task.source,
+                    output: None,
+                    progress: None,
+                    error: None,
+                },
+            ),
+            Err(error) => {
+                emit(
+                    app,
+                    "transcode-failed",
+                    TranscodeEventPayload {
+                        id: &task.id,
+                        source: &task.source,
+                        output: None,
+                        progress: None,
+                        error: Some(&error),
+                    },
+                );
+                let name = task
+                    .source
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                notifications::error(app, "Transcoding Failed", &format!("{}\n{}", name, error));
+            }
+        }
+    }
+    state.workers.fetch_sub(1, Ordering::SeqCst);
+    // A task queued while this worker was winding down would otherwise wait
+    // for the next one to be added.
+    pump(app);
+}
+
+// ---------------------------------------------------------------------------
+// Transcoding
+// ---------------------------------------------------------------------------
+
+/// Whether the file is stored losslessly, and how long it plays.
+fn probe(path: &Path) -> Result<(bool, Duration), String> {
+    let file = lofty::read_from_path(path).map_err(|e| format!("Unsupported audio file: {}", e))?;
+    let properties = file.properties();
+    let lossless = match file.file_type() {
+        FileType::Flac | FileType::Wav | FileType::Aiff | FileType::WavPack | FileType::Ape => true,
+        // ALAC reports a bit depth, AAC does not.
+        FileType::Mp4 => properties.bit_depth().is_some(),
+        _ => false,
+    };
+    Ok((lossless, properties.duration()))
+}
+
+/// Converts one file. Returns the new file, or `None` when the source was
+/// left alone because it is already lossy.
+fn transcode(app: &AppHandle, task: &TranscodeTask) -> Result<Option<PathBuf>, String> {
+    let (lossless, duration) = probe(&task.source)?;
+    if task.only_lossless && !lossless {
+        return Ok(None);
+    }
+
+    let profile = &task.profile;
+    let dir = match &profile.output_dir {
+        Some(dir) => dir.clone(),
+        None => task
+            .source
+            .parent()
+            .map(Path::to_path_buf)
+            .ok_or("Invalid source path")?,
+    };
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let stem = task
+        .source
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .ok_or("Invalid source path")?;
+    let ext = profile.codec.extension();
+    let mut output = dir.join(format!("{}.{}", stem, ext));
+    let replaces_source = output == task.source;
+    if !replaces_source {
+        output = collision::free_path(&output);
+    }
+    // Keeps the real extension so the tag library recognises the file.
+    let temp = dir.join(format!("{}.{}.transcoding.{}", stem, task.id, ext));
+
+    let result = run_ffmpeg(app, task, &temp, duration)
+        .and_then(|_| tagging::copy_tags(&task.source, &temp));
+    if let Err(error) = result {
+        let _ = fs::remove_file(&temp);
+        return Err(error);
+    }
+
+    if let Err(error) = fs::rename(&temp, &output) {
+        let _ = fs::remove_file(&temp);
+        return Err(error.to_string());
+    }
+    if !profile.keep_original && !replaces_source {
+        let _ = fs::remove_file(&task.source);
+    }
+    Ok(Some(output))
+}
+
+fn run_ffmpeg(
+    app: &AppHandle,
+    task: &TranscodeTask,
+    output: &Path,
+    duration: Duration,
+) -> Result<(), String> {
+    let ffmpeg = app
+        .state::<TranscodingState>()
+        .ffmpeg
+        .lock()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("ffmpeg"));
+    let codec = task.profile.codec;
+
+    let mut command = Command::new(ffmpeg);
+    command
+        .args(["-hide_banner", "-nostdin", "-y", "-i"])
+        .arg(&task.source)
+        // Audio only; tags and artwork are copied afterwards so they survive
+        // containers ffmpeg can't put pictures in.
+        .args(["-map", "0:a:0", "-vn", "-map_metadata", "-1"])
+        .args(["-c:a", codec.encoder()])
+        .args(["-b:a", &format!("{}k", task.profile.bitrate_kbps)])
+        .args(["-progress", "pipe:1", "-nostats", "-f", codec.muxer()])
+        .arg(output)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+        command.creation_flags(CREATE_NO_WINDOW);
+    }
+
+    let mut child = command
+        .spawn()
+        .map_err(|e| format!("Failed to start ffmpeg: {}", e))?;
+    let mut stderr = child.stderr.take().ok_or("Failed to read ffmpeg output")?;
+    let errors = thread::spawn(move || {
+        let mut text = String::new();
+        let _ = stderr.read_to_string(&mut text);
+        text
+    });
+
+    let stdout = child.stdout.take().ok_or("Failed to read ffmpeg output")?;
+    let total_us = duration.as_micros().max(1) as f64;
+    let mut last_progress = Instant::now();
+    for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+        // `out_time_ms` is in microseconds as well, despite its name.
+        let Some(value) = line
+            .strip_prefix("out_time_us=")
+            .or_else(|| line.strip_prefix("out_time_ms="))
+        else {
+            continue;
+        };
+        let Ok(done_us) = value.trim().parse::<f64>() else {
+            continue;
+        };
+        if last_progress.elapsed() >= PROGRESS_INTERVAL {
+            last_progress = Instant::now();
+            emit(
+                app,
+                "transcode-progress",
+                TranscodeEventPayload {
+                    id: &task.id,
+                    source: &task.source,
+                    output: None,
+                    progress: Some((done_us / total_us).clamp(0.0, 1.0)),
+                    error: None,
+                },
+            );
+        }
+    }
+
+    let status = child.wait().map_err(|e| e.to_string())?;
+    let errors = errors.join().unwrap_or_default();
+    if !status.success() {
+        let detail = errors.lines().last().unwrap_or("unknown error").trim();
+        return Err(format!("ffmpeg failed: {}", detail));
+    }
+    Ok(())
+}
+
+/// Profile `id` from the settings.
+pub fn profile(app: &AppHandle, id: &str) -> Option<TranscodeProfile> {
+    settings(app)
+        .profiles
+        .into_iter()
+        .find(|profile| profile.id == id)
+}
+
+/// Whether `profile` converts `source` rather than leaving it as it is.
+pub fn converts(profile: &TranscodeProfile, source: &Path) -> bool {
+    profile.lossy_sources || probe(source).is_ok_and(|(lossless, _)| lossless)
+}
+
+/// Converts `source` into `output` right away, outside the worker pool.
+/// `output` must carry the codec's extension.
+pub fn convert(
+    app: &AppHandle,
+    source: &Path,
+    output: &Path,
+    profile: &TranscodeProfile,
+) -> Result<(), String> {
+    let (_, duration) = probe(source)?;
+    let task = TranscodeTask {
+        id: uuid::Uuid::new_v4().to_string(),
+        source: source.to_path_buf(),
+        profile: profile.clone(),
+        only_lossless: false,
+    };
+    run_ffmpeg(app, &task, output, duration).and_then(|_| tagging::copy_tags(source, output))
+}
+
+/// Post-download step: queues the automatic profile for a finished download.
+pub fn after_download(app: &AppHandle, path: &Path) {
+    let settings = settings(app);
+    let Some(profile) = settings
+        .auto_profile
+        .as_ref()
+        .and_then(|id| settings.profiles.iter().find(|profile| &profile.id == id))
+    else {
+        return;
+    };
+    let only_lossless = !profile.lossy_sources;
+    enqueue(app, path.to_path_buf(), profile.clone(), only_lossless);
+}
+
+// ---------------------------------------------------------------------------
+// Tauri commands
+// ---------------------------------------------------------------------------
+
+#[tauri::command]
+pub fn get_transcoding_settings(
+    state: tauri::State<TranscodingState>,
+) -> Result<TranscodingSettings, String> {
+    let settings = state.settings.lock().map_err(|_| "Failed to lock mutex")?;
+    Ok(settings.clone())
+}
+
+#[tauri::command]
+pub fn set_transcoding_settings(
+    app: AppHandle,
+    state: tauri::State<TranscodingState>,
+    settings: TranscodingSettings,
+) -> Result<(), String> {
+    for (index, profile) in settings.profiles.iter().enumerate() {
+        if profile.bitrate_kbps == 0 {
+            return Err(format!("Profile \"{}\" needs a bitrate", profile.name));
+        }
+        if settings.profiles[..index]
+            .iter()
+            .any(|other| other.id == profile.id)
+        {
+            return Err(format!("Duplicate profile id \"{}\"", profile.id));
+        }
+    }
+    if let Some(id) = &settings.auto_profile {
+        if !settings.profiles.iter().any(|profile| &profile.id == id) {
+            return Err(format!("Unknown profile \"{}\"", id));
+        }
+    }
+    let mut settings = settings;
+    let mut current = state.settings.lock().map_err(|_| "Failed to lock mutex")?;
+    // Output folders are only chosen through the native picker.
+    for profile in &mut settings.profiles {
+        profile.output_dir = current
+            .profiles
+            .iter()
+            .find(|old| old.id == profile.id)
+            .and_then(|old| old.output_dir.clone());
+    }
+    crate::store::save_config(&app, SETTINGS_FILE, &settings)?;
+    *current = settings;
+    drop(current);
+    pump(&app);
+    Ok(())
+}
+
+/// Sets the output folder of profile `profile_id`; `None` puts copies next
+/// to their sources.
+fn set_output_dir(
+    app: &AppHandle,
+    profile_id: &str,
+    dir: Option<PathBuf>,
+) -> Result<TranscodeProfile, String> {
+    let state = app.state::<TranscodingState>();
+    let mut settings = state.settings.lock().map_err(|_| "Failed to lock mutex")?;
+    let profile = settings
+        .profiles
+        .iter_mut()
+        .find(|profile| profile.id == profile_id)
+        .ok_or("Unknown profile")?;
+    profile.output_dir = dir;
+    let profile = profile.clone();
+    crate::store::save_config(app, SETTINGS_FILE, &*settings)?;
+    Ok(profile)
+}
+
+/// Asks the user for the output folder of profile `profile_id`.
+#[tauri::command]
+pub async fn pick_transcode_output_dir(
+    app: AppHandle,
+    profile_id: String,
+) -> Result<TranscodeProfile, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let path = app
+            .dialog()
+            .file()
+            .set_title("Choose Output Folder")
+            .blocking_pick_folder()
+            .ok_or("No folder selected")?
+            .into_path()
+            .map_err(|e| e.to_string())?;
+        set_output_dir(&app, &profile_id, Some(path))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Puts the copies of profile `profile_id` next to their sources again.
+#[tauri::command]
+pub fn reset_transcode_output_dir(
+    app: AppHandle,
+    profile_id: String,
+) -> Result<TranscodeProfile, String> {
+    set_output_dir(&app, &profile_id, None)
+}
+
+/// The `ffmpeg` executable in use, if one was picked.
+#[tauri::command]
+pub fn get_ffmpeg_path(state: tauri::State<TranscodingState>) -> Result<Option<PathBuf>, String> {
+    let ffmpeg = state.ffmpeg.lock().map_err(|_| "Failed to lock mutex")?;
+    Ok(ffmpeg.clone())
+}
+
+/// Asks the user for the `ffmpeg` executable and stores it.
+#[tauri::command]
+pub async fn pick_ffmpeg_path(app: AppHandle) -> Result<PathBuf, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let path = app
+            .dialog()
+            .file()
+            .set_title("Locate ffmpeg")
+            .blocking_pick_file()
+            .ok_or("No file selected")?
+            .into_path()
+            .map_err(|e| e.to_string())?;
+        let state = app.state::<TranscodingState>();
+        let mut ffmpeg = state.ffmpeg.lock().map_err(|_| "Failed to lock mutex")?;
+        crate::store::save_config(&app, FFMPEG_FILE, &Some(&path))?;
+        *ffmpeg = Some(path.clone());
+        Ok(path)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Goes back to the `ffmpeg` found on `PATH`.
+#[tauri::command]
+pub fn clear_ffmpeg_path(
+    app: AppHandle,
+    state: tauri::State<TranscodingState>,
+) -> Result<(), String> {
+    let mut ffmpeg = state.ffmpeg.lock().map_err(|_| "Failed to lock mutex")?;
+    crate::store::save_config(&app, FFMPEG_FILE, &None::<PathBuf>)?;
+    *ffmpeg = None;
+    Ok(())
+}
+
+/// Queues `paths` for conversion with the profile `profile_id` and returns
+/// the task IDs. Unlike automatic runs, lossy sources are converted too.
+/// Only library files are accepted.
+#[tauri::command]
+pub fn transcode_files(
+    app: AppHandle,
+    paths: Vec<PathBuf>,
+    profile_id: String,
+) -> Result<Vec<String>, String> {
+    let profile = settings(&app)
+        .profiles
+        .into_iter()
+        .find(|profile| profile.id == profile_id)
+        .ok_or("Unknown profile")?;
+    if let Some(outside) = paths
+        .iter()
+        .find(|path| !library::contains(&app, path) || !path.is_file())
+    {
+        return Err(format!("Not in the library: {}", outside.display()));
+    }
+    Ok(paths
+        .into_iter()
+        .map(|path| enqueue(&app, path, profile.clone(), false))
+        .collect())
+}
+
+/// Tasks waiting for a free worker, in order.
+#[tauri::command]
+pub fn list_transcode_queue(
+    state: tauri::State<TranscodingState>,
+) -> Result<Vec<TranscodeTask>, String> {
+    let queue = state.queue.lock().map_err(|_| "Failed to lock mutex")?;
+    Ok(queue.iter().cloned().collect())
+}
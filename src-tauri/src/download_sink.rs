@@ -0,0 +1,712 @@
+use serde::{Deserialize, Serialize};
+#[cfg(desktop)]
+use std::collections::HashMap;
+#[cfg(desktop)]
+use std::fs::{self, File};
+#[cfg(desktop)]
+use std::io::Write;
+#[cfg(desktop)]
+use std::path::{Component, Path, PathBuf};
+#[cfg(desktop)]
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+#[cfg(desktop)]
+use std::time::Instant;
+use tauri::ipc::{InvokeBody, Request};
+use tauri::{AppHandle, Manager};
+
+#[cfg(desktop)]
+use crate::collision::{self, CollisionPolicy, Resolution};
+
+const SESSIONS_FILE: &str = "download_sessions.json";
+
+/// Sessions without a write for this long are cancelled.
+const SESSION_TIMEOUT: Duration = Duration::from_secs(120);
+const REAP_INTERVAL: Duration = Duration::from_secs(30);
+
+// ---------------------------------------------------------------------------
+// Protocol
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadBeginArgs {
+    pub filename: String,
+    pub mime_type: Option<String>,
+    /// Folder below the sink's root, e.g. `Download` on Android.
+    pub relative_path: Option<String>,
+    /// Expected size in bytes, checked against the download quota.
+    pub size: Option<u64>,
+    /// URL the page saved from, e.g. a `blob:` URL; looked up for metadata
+    /// the web app registered and kept in the download history.
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadBegin {
+    /// Session to write to; `None` when the download was skipped.
+    pub session: Option<String>,
+    /// Name the file is saved under, or the existing file's name when skipped.
+    pub saved_name: String,
+    pub skipped: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadFinish {
+    /// `false` when the new file lost a quality comparison and was discarded.
+    pub kept: bool,
+    pub bytes: u64,
+}
+
+/// Somewhere a download can be streamed to in chunks: `begin` applies the
+/// collision policy and opens a session, `write` appends to it and `finish`
+/// publishes it. Sessions keep their output open between chunks.
+///
+/// Sinks [`record`] where each session writes until it is finished or
+/// cancelled, so whatever a crash leaves behind can be passed to `discard`
+/// on the next start.
+pub trait DownloadSink: Send + Sync {
+    fn begin(&self, app: &AppHandle, args: DownloadBeginArgs) -> Result<DownloadBegin, String>;
+    /// Returns the bytes written to the session so far.
+    fn write(&self, app: &AppHandle, session: &str, data: &[u8]) -> Result<u64, String>;
+    fn finish(&self, app: &AppHandle, session: &str) -> Result<DownloadFinish, String>;
+    /// Closes the session and removes what it wrote.
+    fn cancel(&self, app: &AppHandle, session: &str) -> Result<(), String>;
+    /// Sessions that have not been written to for longer than `idle`.
+    fn idle_sessions(&self, idle: Duration) -> Vec<String>;
+    /// Removes a partial download left at a recorded `location`.
+    fn discard(&self, app: &AppHandle, location: &str) -> Result<(), String>;
+}
+
+pub struct DownloadSinkState {
+    sink: Box<dyn DownloadSink>,
+    /// Locations of unfinished sessions, mirrored to [`SESSIONS_FILE`].
+    pending: Mutex<Vec<String>>,
+}
+
+impl Default for DownloadSinkState {
+    #[cfg(target_os = "android")]
+    fn default() -> Self {
+        DownloadSinkState {
+            sink: Box::new(crate::android_download::MediaStoreSink::default()),
+            pending: Mutex::default(),
+        }
+    }
+
+    #[cfg(desktop)]
+    fn default() -> Self {
+        DownloadSinkState {
+            sink: Box::new(FilesystemSink::default()),
+            pending: Mutex::default(),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Orphans and timeouts
+// ---------------------------------------------------------------------------
+
+fn save_pending(app: &AppHandle, pending: &[String]) {
+    if let Some(path) = crate::store::data_file(app, SESSIONS_FILE) {
+        let _ = crate::store::write_json(&path, &pending);
+    }
+}
+
+/// Notes that a session is writing to `location`.
+pub fn record(app: &AppHandle, location: &str) {
+    let state = app.state::<DownloadSinkState>();
+    let mut pending = state.pending.lock().unwrap();
+    pending.push(location.to_string());
+    save_pending(app, &pending);
+}
+
+/// Drops `location` once its session has been finished or cancelled.
+pub fn forget(app: &AppHandle, location: &str) {
+    let state = app.state::<DownloadSinkState>();
+    let mut pending = state.pending.lock().unwrap();
+    pending.retain(|entry| entry != location);
+    save_pending(app, &pending);
+}
+
+/// Discards downloads a previous run left unfinished and starts cancelling
+/// sessions that stop receiving data.
+pub fn load(app: &AppHandle) {
+    let orphans: Vec<String> = crate::store::data_file(app, SESSIONS_FILE)
+        .and_then(|path| crate::store::read_json(&path))
+        .unwrap_or_default();
+    if !orphans.is_empty() {
+        // Keep them recorded until they are gone, in case this run dies too.
+        *app.state::<DownloadSinkState>().pending.lock().unwrap() = orphans.clone();
+        let app = app.clone();
+        std::thread::spawn(move || {
+            let state = app.state::<DownloadSinkState>();
+            // Ones that could not be removed are tried again next run.
+            let discarded: Vec<&String> = orphans
+                .iter()
+                .filter(|location| state.sink.discard(&app, location).is_ok())
+                .collect();
+            let mut pending = state.pending.lock().unwrap();
+            pending.retain(|entry| !discarded.contains(&entry));
+            save_pending(&app, &pending);
+        });
+    }
+
+    let app = app.clone();
+    std::thread::spawn(move || loop {
+        std::thread::sleep(REAP_INTERVAL);
+        let state = app.state::<DownloadSinkState>();
+        for session in state.sink.idle_sessions(SESSION_TIMEOUT) {
+            let _ = state.sink.cancel(&app, &session);
+        }
+    });
+}
+
+// ---------------------------------------------------------------------------
+// Filesystem backend
+// ---------------------------------------------------------------------------
+
+/// A download being written by [`FilesystemSink`].
+#[cfg(desktop)]
+struct FileSession {
+    /// Open on `staging`; the target is only touched once the session
+    /// finishes.
+    file: File,
+    staging: PathBuf,
+    written: u64,
+    last_write: Instant,
+    policy: CollisionPolicy,
+    /// `Write` renames the staged file over its path, `Compare` settles it
+    /// against the existing copies.
+    resolution: Resolution,
+    /// Finishes the download like a webview download; without it the file
+    /// is only published.
+    download: Option<crate::desktop::SinkDownload>,
+}
+
+/// Writes into the download folder. Sessions are opaque IDs rather than
+/// paths, so the webview can only write files it began.
+#[cfg(desktop)]
+#[derive(Default)]
+pub struct FilesystemSink {
+    sessions: Mutex<HashMap<String, FileSession>>,
+    next_id: AtomicU64,
+}
+
+#[cfg(desktop)]
+fn root(app: &AppHandle) -> Result<PathBuf, String> {
+    crate::desktop::download_dir(app).ok_or_else(|| "No download folder".to_string())
+}
+
+/// Where `args` asks to be saved below `root`, before naming.
+#[cfg(desktop)]
+fn target(root: &Path, args: &DownloadBeginArgs) -> PathBuf {
+    let name = confined(&args.filename)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "download".to_string());
+    root.join(confined(args.relative_path.as_deref().unwrap_or("")))
+        .join(name)
+}
+
+/// `relative` with everything that could leave the root dropped.
+#[cfg(desktop)]
+fn confined(relative: &str) -> PathBuf {
+    Path::new(relative)
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(desktop)]
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Hidden file next to `target` that a session writes to. Unique, so two
+/// downloads of the same name don't share one.
+#[cfg(desktop)]
+fn staging_path(target: &Path) -> PathBuf {
+    target.with_file_name(format!(
+        ".{}.{}.incoming",
+        file_name(target),
+        uuid::Uuid::new_v4().simple()
+    ))
+}
+
+#[cfg(desktop)]
+fn remove_partial(location: &str) -> Result<(), String> {
+    match fs::remove_file(location) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.to_string()),
+        _ => Ok(()),
+    }
+}
+
+/// Renames a `Write` session's `staging` file to `target`. Returns where it
+/// ended up.
+#[cfg(desktop)]
+fn place(staging: &Path, target: &Path, policy: CollisionPolicy) -> Result<PathBuf, String> {
+    // Another download may have taken the name meanwhile.
+    let target = if policy == CollisionPolicy::Overwrite {
+        target.to_path_buf()
+    } else {
+        collision::free_path(target)
+    };
+    fs::rename(staging, &target).map_err(|e| e.to_string())?;
+    Ok(target)
+}
+
+/// Moves a finished session's file into place. Returns whether it was kept.
+#[cfg(desktop)]
+fn publish(session: &FileSession) -> Result<bool, String> {
+    match &session.resolution {
+        Resolution::Write(target) => place(&session.staging, target, session.policy).map(|_| true),
+        Resolution::Compare {
+            target, existing, ..
+        } => Ok(collision::settle(&session.staging, target, existing)?.1),
+        Resolution::Skip(_) => Ok(false),
+    }
+}
+
+#[cfg(desktop)]
+impl FilesystemSink {
+    /// `begin` for a file at `target` with `policy`. Returns the staging
+    /// file of the new session, if one was opened.
+    fn open(
+        &self,
+        target: &Path,
+        policy: CollisionPolicy,
+    ) -> Result<(DownloadBegin, Option<PathBuf>), String> {
+        if let Some(dir) = target.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+
+        let (staging, saved_name, resolution) = match collision::resolve(policy, target) {
+            Resolution::Skip(existing) => {
+                let begin = DownloadBegin {
+                    session: None,
+                    saved_name: file_name(&existing),
+                    skipped: true,
+                };
+                return Ok((begin, None));
+            }
+            Resolution::Write(target) => (
+                staging_path(&target),
+                file_name(&target),
+                Resolution::Write(target),
+            ),
+            Resolution::Compare {
+                target, existing, ..
+            } => {
+                let staging = staging_path(&target);
+                let saved_name = file_name(&target);
+                let resolution = Resolution::Compare {
+                    staging: staging.clone(),
+                    target,
+                    existing,
+                };
+                (staging, saved_name, resolution)
+            }
+        };
+        let file = File::create(&staging).map_err(|e| format!("Failed to create file: {}", e))?;
+
+        let id = format!("file-{}", self.next_id.fetch_add(1, Ordering::Relaxed));
+        self.sessions
+            .lock()
+            .map_err(|_| "Failed to lock mutex")?
+            .insert(
+                id.clone(),
+                FileSession {
+                    file,
+                    staging: staging.clone(),
+                    written: 0,
+                    last_write: Instant::now(),
+                    policy,
+                    resolution,
+                    download: None,
+                },
+            );
+        let begin = DownloadBegin {
+            session: Some(id),
+            saved_name,
+            skipped: false,
+        };
+        Ok((begin, Some(staging)))
+    }
+
+    fn append(&self, session: &str, data: &[u8]) -> Result<u64, String> {
+        let mut sessions = self.sessions.lock().map_err(|_| "Failed to lock mutex")?;
+        let session = sessions.get_mut(session).ok_or("Unknown download")?;
+        session
+            .file
+            .write_all(data)
+            .map_err(|e| format!("Failed to write bytes: {}", e))?;
+        session.written += data.len() as u64;
+        session.last_write = Instant::now();
+        Ok(session.written)
+    }
+
+    /// Completes a taken session through the download pipeline. The staging
+    /// file is gone afterwards, whether or not that worked.
+    fn complete(
+        app: &AppHandle,
+        session: FileSession,
+        download: crate::desktop::SinkDownload,
+    ) -> Result<DownloadFinish, String> {
+        let FileSession {
+            file,
+            staging,
+            written,
+            policy,
+            resolution,
+            ..
+        } = session;
+        let flushed = file
+            .sync_all()
+            .map_err(|e| format!("Failed to flush file: {}", e));
+        drop(file);
+        let placed = flushed.and_then(|_| match resolution {
+            Resolution::Write(target) => Ok((Some(place(&staging, &target, policy)?), None)),
+            Resolution::Compare { .. } => Ok((None, Some(resolution))),
+            Resolution::Skip(_) => Ok((None, None)),
+        });
+        let kept = match placed {
+            Ok((path, comparison)) => {
+                tauri::async_runtime::block_on(download.complete(app, path, comparison))
+            }
+            Err(error) => {
+                download.fail(app, &error);
+                Err(error)
+            }
+        };
+        let _ = remove_partial(&staging.to_string_lossy());
+        Ok(DownloadFinish {
+            kept: kept?,
+            bytes: written,
+        })
+    }
+
+    fn take(&self, session: &str) -> Result<FileSession, String> {
+        self.sessions
+            .lock()
+            .map_err(|_| "Failed to lock mutex")?
+            .remove(session)
+            .ok_or_else(|| "Unknown download".to_string())
+    }
+
+    /// Flushes a taken session and publishes it. The staging file is gone
+    /// afterwards, whether or not that worked.
+    fn close(session: FileSession) -> Result<DownloadFinish, String> {
+        let result = session
+            .file
+            .sync_all()
+            .map_err(|e| format!("Failed to flush file: {}", e))
+            .and_then(|_| publish(&session));
+        let _ = remove_partial(&session.staging.to_string_lossy());
+        Ok(DownloadFinish {
+            kept: result?,
+            bytes: session.written,
+        })
+    }
+}
+
+#[cfg(desktop)]
+impl DownloadSink for FilesystemSink {
+    fn begin(&self, app: &AppHandle, args: DownloadBeginArgs) -> Result<DownloadBegin, String> {
+        crate::desktop::reserve_download_space(app, args.size.unwrap_or(0))?;
+        let url = args.url.clone().unwrap_or_else(|| args.filename.clone());
+        let (path, download) =
+            crate::desktop::SinkDownload::begin(app, &url, &target(&root(app)?, &args));
+        let (begin, staging) = match self.open(&path, collision::policy(app)) {
+            Ok(opened) => opened,
+            Err(error) => {
+                download.fail(app, &error);
+                return Err(error);
+            }
+        };
+        let Some(staging) = staging else {
+            download.skip(app, path.with_file_name(&begin.saved_name));
+            return Ok(begin);
+        };
+        record(app, &staging.to_string_lossy());
+        if let Some(session) = begin.session.as_ref() {
+            if let Some(session) = self
+                .sessions
+                .lock()
+                .map_err(|_| "Failed to lock mutex")?
+                .get_mut(session)
+            {
+                session.download = Some(download);
+            }
+        }
+        Ok(begin)
+    }
+
+    fn write(&self, _app: &AppHandle, session: &str, data: &[u8]) -> Result<u64, String> {
+        self.append(session, data)
+    }
+
+    fn finish(&self, app: &AppHandle, session: &str) -> Result<DownloadFinish, String> {
+        let mut session = self.take(session)?;
+        let location = session.staging.to_string_lossy().into_owned();
+        let result = match session.download.take() {
+            Some(download) => Self::complete(app, session, download),
+            None => Self::close(session),
+        };
+        if !Path::new(&location).exists() {
+            forget(app, &location);
+        }
+        result
+    }
+
+    fn cancel(&self, app: &AppHandle, session: &str) -> Result<(), String> {
+        let session = self.take(session)?;
+        drop(session.file);
+        if let Some(download) = session.download {
+            download.cancel(app);
+        }
+        let location = session.staging.to_string_lossy();
+        remove_partial(&location)?;
+        forget(app, &location);
+        Ok(())
+    }
+
+    fn idle_sessions(&self, idle: Duration) -> Vec<String> {
+        let Ok(sessions) = self.sessions.lock() else {
+            return Vec::new();
+        };
+        sessions
+            .iter()
+            .filter(|(_, session)| session.last_write.elapsed() > idle)
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    fn discard(&self, _app: &AppHandle, location: &str) -> Result<(), String> {
+        remove_partial(location)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tauri commands
+// ---------------------------------------------------------------------------
+
+#[tauri::command]
+pub fn download_begin(
+    app: AppHandle,
+    state: tauri::State<DownloadSinkState>,
+    args: DownloadBeginArgs,
+) -> Result<DownloadBegin, String> {
+    state.sink.begin(&app, args)
+}
+
+/// Takes the chunk as a raw binary payload, with the session ID in the
+/// `session` header, and returns the bytes written so far.
+#[tauri::command]
+pub fn download_write(
+    app: AppHandle,
+    state: tauri::State<DownloadSinkState>,
+    request: Request<'_>,
+) -> Result<u64, String> {
+    let InvokeBody::Raw(data) = request.body() else {
+        return Err("Expected a binary payload".into());
+    };
+    let session = request
+        .headers()
+        .get("session")
+        .and_then(|value| value.to_str().ok())
+        .ok_or("Missing session header")?;
+    state.sink.write(&app, session, data)
+}
+
+/// Publishes a download. On desktop this waits for it to be verified and
+/// tagged, so it runs off the main thread.
+#[tauri::command]
+pub async fn download_finish(app: AppHandle, session: String) -> Result<DownloadFinish, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        app.state::<DownloadSinkState>().sink.finish(&app, &session)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Abandons a download, removing the partial file or pending entry.
+#[tauri::command]
+pub fn download_cancel(
+    app: AppHandle,
+    state: tauri::State<DownloadSinkState>,
+    session: String,
+) -> Result<(), String> {
+    state.sink.cancel(&app, &session)
+}
+
+#[cfg(all(test, desktop))]
+mod tests {
+    use super::*;
+
+    fn temp_root(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("download-sink-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn args(filename: &str, relative_path: Option<&str>) -> DownloadBeginArgs {
+        DownloadBeginArgs {
+            filename: filename.into(),
+            mime_type: None,
+            relative_path: relative_path.map(str::to_string),
+            size: None,
+            url: None,
+        }
+    }
+
+    fn entries(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn finish_publishes_the_written_file() {
+        let root = temp_root("finish");
+        let sink = FilesystemSink::default();
+        let (begin, staging) = sink
+            .open(
+                &target(&root, &args("Song.flac", None)),
+                CollisionPolicy::KeepBoth,
+            )
+            .unwrap();
+        let session = begin.session.unwrap();
+        assert_eq!(begin.saved_name, "Song.flac");
+        assert_eq!(sink.append(&session, b"abc").unwrap(), 3);
+        assert_eq!(sink.append(&session, b"def").unwrap(), 6);
+        assert!(staging.unwrap().is_file());
+        assert!(!root.join("Song.flac").exists());
+
+        let finish = FilesystemSink::close(sink.take(&session).unwrap()).unwrap();
+        assert!(finish.kept);
+        assert_eq!(finish.bytes, 6);
+        assert_eq!(fs::read(root.join("Song.flac")).unwrap(), b"abcdef");
+        assert_eq!(entries(&root), ["Song.flac"]);
+        assert!(sink.append(&session, b"more").is_err());
+    }
+
+    #[test]
+    fn overwrite_keeps_the_existing_file_until_finish() {
+        let root = temp_root("overwrite");
+        fs::write(root.join("Song.flac"), b"old").unwrap();
+        let sink = FilesystemSink::default();
+
+        let (begin, staging) = sink
+            .open(
+                &target(&root, &args("Song.flac", None)),
+                CollisionPolicy::Overwrite,
+            )
+            .unwrap();
+        let session = begin.session.unwrap();
+        sink.append(&session, b"new").unwrap();
+        assert_eq!(fs::read(root.join("Song.flac")).unwrap(), b"old");
+
+        // Cancelling leaves the existing file as it was.
+        drop(sink.take(&session).unwrap());
+        remove_partial(&staging.unwrap().to_string_lossy()).unwrap();
+        assert_eq!(fs::read(root.join("Song.flac")).unwrap(), b"old");
+        assert_eq!(entries(&root), ["Song.flac"]);
+
+        let (begin, _) = sink
+            .open(
+                &target(&root, &args("Song.flac", None)),
+                CollisionPolicy::Overwrite,
+            )
+            .unwrap();
+        let session = begin.session.unwrap();
+        sink.append(&session, b"new").unwrap();
+        FilesystemSink::close(sink.take(&session).unwrap()).unwrap();
+        assert_eq!(fs::read(root.join("Song.flac")).unwrap(), b"new");
+        assert_eq!(entries(&root), ["Song.flac"]);
+    }
+
+    #[test]
+    fn keep_both_and_skip_leave_the_existing_file() {
+        let root = temp_root("keep-both");
+        fs::write(root.join("Song.flac"), b"old").unwrap();
+        let sink = FilesystemSink::default();
+
+        let (begin, _) = sink
+            .open(
+                &target(&root, &args("Song.flac", None)),
+                CollisionPolicy::Skip,
+            )
+            .unwrap();
+        assert!(begin.skipped);
+        assert!(begin.session.is_none());
+
+        let (begin, _) = sink
+            .open(
+                &target(&root, &args("Song.flac", None)),
+                CollisionPolicy::KeepBoth,
+            )
+            .unwrap();
+        assert_eq!(begin.saved_name, "Song (2).flac");
+        let session = begin.session.unwrap();
+        sink.append(&session, b"new").unwrap();
+        FilesystemSink::close(sink.take(&session).unwrap()).unwrap();
+        assert_eq!(fs::read(root.join("Song.flac")).unwrap(), b"old");
+        assert_eq!(fs::read(root.join("Song (2).flac")).unwrap(), b"new");
+    }
+
+    #[test]
+    fn begin_stays_inside_the_root() {
+        let root = temp_root("confined");
+        let sink = FilesystemSink::default();
+        let (begin, staging) = sink
+            .open(
+                &target(
+                    &root.join("inner"),
+                    &args("../../Song.flac", Some("../Album")),
+                ),
+                CollisionPolicy::KeepBoth,
+            )
+            .unwrap();
+        assert!(staging
+            .unwrap()
+            .starts_with(root.join("inner").join("Album")));
+        let session = begin.session.unwrap();
+        FilesystemSink::close(sink.take(&session).unwrap()).unwrap();
+        assert!(root.join("inner").join("Album").join("Song.flac").is_file());
+    }
+
+    #[test]
+    fn discard_removes_a_left_over_file() {
+        let root = temp_root("discard");
+        let sink = FilesystemSink::default();
+        let (_, staging) = sink
+            .open(
+                &target(&root, &args("Song.flac", None)),
+                CollisionPolicy::KeepBoth,
+            )
+            .unwrap();
+        let staging = staging.unwrap();
+        drop(sink.sessions.lock().unwrap().drain());
+        assert!(staging.is_file());
+
+        remove_partial(&staging.to_string_lossy()).unwrap();
+        assert!(!staging.exists());
+        // Already gone is fine too.
+        remove_partial(&staging.to_string_lossy()).unwrap();
+        assert!(entries(&root).is_empty());
+    }
+}
@@ -43,6 +43,8 @@ fn set_source_url(app: AppHandle, url: String) -> Result<String, String> {
     let parsed = url::Url::parse(&url).map_err(|e| format!("Invalid URL: {}", e))?;
     let url = parsed.to_string();
     save_source_url(&app, &url);
+    #[cfg(desktop)]
+    let parsed = desktop::entry_url(&app, parsed);
     if let Some(window) = app.get_webview_window("main") {
         let _ = window.navigate(parsed);
     }
@@ -68,6 +70,16 @@ fn open_external(app: AppHandle, url: String) -> Result<(), String> {
 #[cfg(desktop)]
 mod desktop;
 
+#[cfg(any(desktop, target_os = "android"))]
+#[cfg_attr(not(desktop), allow(dead_code))]
+mod store;
+
+#[cfg(any(desktop, target_os = "android"))]
+mod collision;
+
+#[cfg(any(desktop, target_os = "android"))]
+mod download_sink;
+
 #[cfg(mobile)]
 mod mobile;
 
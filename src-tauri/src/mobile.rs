@@ -11,14 +11,20 @@ mod android;
 
 #[cfg(target_os = "android")]
 pub fn configure(builder: tauri::Builder<tauri::Wry>) -> tauri::Builder<tauri::Wry> {
-    builder.invoke_handler(tauri::generate_handler![
-        open_external,
-        get_source_url,
-        set_source_url,
-        crate::android_download::android_download_begin,
-        crate::android_download::android_download_write,
-        crate::android_download::android_download_finish
-    ])
+    builder
+        .manage(crate::collision::CollisionState::default())
+        .manage(crate::download_sink::DownloadSinkState::default())
+        .invoke_handler(tauri::generate_handler![
+            open_external,
+            get_source_url,
+            set_source_url,
+            crate::collision::get_collision_settings,
+            crate::collision::set_collision_settings,
+            crate::download_sink::download_begin,
+            crate::download_sink::download_write,
+            crate::download_sink::download_finish,
+            crate::download_sink::download_cancel
+        ])
 }
 
 #[cfg(not(target_os = "android"))]
@@ -76,7 +82,8 @@ pub fn setup(app: &mut tauri::App) -> Result<(), Box<dyn std::error::Error>> {
     init_script.push('\n');
     let fallback_script = include_str!("../scripts/mobile/source_url_fallback.js")
         .replace("__EXPECTED_URL__", &source_url)
-        .replace("__DEFAULT_URL__", crate::DEFAULT_SOURCE_URL);
+        .replace("__DEFAULT_URL__", crate::DEFAULT_SOURCE_URL)
+        .replace("__OFFLINE_LIBRARY__", "false");
     init_script.push_str(&fallback_script);
 
     #[cfg(target_os = "android")]
@@ -116,7 +123,11 @@ pub fn setup(app: &mut tauri::App) -> Result<(), Box<dyn std::error::Error>> {
     ios::setup(app)?;
 
     #[cfg(target_os = "android")]
-    android::setup(app)?;
+    {
+        crate::collision::load(app.handle());
+        crate::download_sink::load(app.handle());
+        android::setup(app)?;
+    }
 
     Ok(())
 }
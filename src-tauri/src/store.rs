@@ -0,0 +1,95 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Manager};
+
+// ---------------------------------------------------------------------------
+// Locations
+// ---------------------------------------------------------------------------
+
+/// Path of a settings file inside the app config dir (next to `source_url.txt`).
+pub fn config_file(app: &AppHandle, name: &str) -> Option<PathBuf> {
+    app.path().app_config_dir().ok().map(|dir| dir.join(name))
+}
+
+/// Path of a state file inside the app data dir (history, queues, indexes).
+pub fn data_file(app: &AppHandle, name: &str) -> Option<PathBuf> {
+    app.path().app_data_dir().ok().map(|dir| dir.join(name))
+}
+
+// ---------------------------------------------------------------------------
+// Files
+// ---------------------------------------------------------------------------
+
+/// Writes to a temporary sibling first and renames it into place, so readers
+/// never observe a half-written file.
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, data).map_err(|e| e.to_string())?;
+    fs::rename(&tmp, path).map_err(|e| e.to_string())
+}
+
+pub fn read_json<T: DeserializeOwned>(path: &Path) -> Option<T> {
+    let content = fs::read(path).ok()?;
+    serde_json::from_slice(&content).ok()
+}
+
+pub fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<(), String> {
+    let data = serde_json::to_vec_pretty(value).map_err(|e| e.to_string())?;
+    write_atomic(path, &data)
+}
+
+pub fn load_config<T: DeserializeOwned + Default>(app: &AppHandle, name: &str) -> T {
+    config_file(app, name)
+        .and_then(|path| read_json(&path))
+        .unwrap_or_default()
+}
+
+pub fn save_config<T: Serialize>(app: &AppHandle, name: &str, value: &T) -> Result<(), String> {
+    let path = config_file(app, name).ok_or("Config directory unavailable")?;
+    write_json(&path, value)
+}
+
+// ---------------------------------------------------------------------------
+// JSON Lines logs
+// ---------------------------------------------------------------------------
+
+/// Reads every parseable line of a JSON Lines file. Malformed lines (e.g. a
+/// partially written tail after a crash) are skipped.
+pub fn read_json_lines<T: DeserializeOwned>(path: &Path) -> Vec<T> {
+    let Ok(content) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+pub fn append_json_lines<'a, T, I>(path: &Path, values: I) -> Result<(), String>
+where
+    T: Serialize + 'a,
+    I: IntoIterator<Item = &'a T>,
+{
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let mut buffer = Vec::new();
+    for value in values {
+        serde_json::to_writer(&mut buffer, value).map_err(|e| e.to_string())?;
+        buffer.push(b'\n');
+    }
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| e.to_string())?;
+    file.write_all(&buffer).map_err(|e| e.to_string())
+}